@@ -1,68 +1,842 @@
 use eframe::egui;
-use egui::FontFamily::Proportional;
-use egui::{Color32, FontData, FontDefinitions}; // Re-added Color32
-use std::{sync::{Arc, Mutex}, time::Duration};
+use egui::{Color32, FontDefinitions}; // Re-added Color32
+use std::{collections::VecDeque, hash::{BuildHasher, Hasher, RandomState}, panic::{self, AssertUnwindSafe}, sync::{Arc, Mutex, PoisonError}, time::{Duration, Instant, SystemTime}};
 
 // Import functions/structs from our other modules
-use crate::spotify::{self, SpotifyInfo};
-use crate::lyrics;
+use crate::spotify::{self, DeviceInfo, SpotifyInfo};
+use crate::lyrics::{self, LyricLine, Provider};
 use crate::cache; // Import cache module
+use crate::server;
+use crate::config::{self, FontUnits, LyricsMode, LyricsSpacing, PausedLyrics, PlaceholderMode, ScrollAnchor, Settings, TextDirection, WindowSizing};
+use crate::artwork;
+use crate::nowplaying::{self, SourceKind};
+use crate::playlog::{self, PlayLogFormat};
+use crate::secrets;
 
 // --- Application State ---
 
+const SKIPPED_STATUS: &str = "Lyrics disabled for this track";
+const NOTHING_PLAYING_STATUS: &str = "Spotify stopped or nothing playing.";
+const AD_STATUS: &str = "Advertisement (lyrics resume with the next song)";
+const MAX_HISTORY_ENTRIES: usize = 20; // Songs kept in the "Recently played" panel
+const POLL_INTERVAL: Duration = Duration::from_secs(3);
+const WAKE_GAP: Duration = Duration::from_secs(30); // Wall-clock time beyond the poll interval that means we were suspended
+const SEEK_THRESHOLD_MS: u32 = 2000; // Estimate vs. polled progress gap that counts as a user seek
+const RESTART_WINDOW_MS: u32 = 5000; // A backwards jump landing this close to the start counts as a repeat
+const QUEUE_CACHE_TTL: Duration = Duration::from_secs(30); // Re-read the queue at most this often (or on song change)
+const PANIC_RESTART_DELAY: Duration = Duration::from_secs(5); // Pause before polling again after the loop panicked
+const ALBUM_PREFETCH_SPACING: Duration = Duration::from_secs(5); // Gap between prefetch lookups, to go easy on rate limits
+const MAX_ALBUM_PREFETCH_TRACKS: usize = 10;
+const MAX_PANIC_RESTARTS: usize = 5; // Give up when the loop panics more often than this within PANIC_WINDOW
+const PANIC_WINDOW: Duration = Duration::from_secs(10 * 60);
+const IDLE_REPAINT_INTERVAL: Duration = Duration::from_secs(5); // Fallback repaint when nothing is scheduled
+const AUTO_SCROLL_REPAINT_INTERVAL: Duration = Duration::from_millis(500); // Plain-lyrics auto-scroll has no line times
+const MIN_FIT_HEIGHT: f32 = 60.0; // "Fit content" never shrinks the window below this
+const MAX_FIT_HEIGHT: f32 = 1200.0; // ...or grows it past this (or 90% of the monitor, if smaller)
+const TOAST_DURATION: Duration = Duration::from_secs(3); // How long transient notices stay up
+const OSD_FADE: Duration = Duration::from_millis(400); // Now-playing popup fade in and out
+const OSD_ART_SIZE: f32 = 56.0;
+const WIPE_REPAINT_INTERVAL: Duration = Duration::from_millis(33); // Karaoke wipe animation (~30 fps)
+const LINE_FADE_START_ALPHA: f32 = 0.35; // How dim a line starts out as it fades in to become current
+const PAUSED_LYRICS_OPACITY: f32 = 0.4; // PausedLyrics::Dim
+const PRESENTATION_SCALE: f32 = 2.5; // Current line size in presentation mode, relative to the font size setting
+const PRESENTATION_CONTEXT_LINES: usize = 2; // Dimmed lines shown above and below the current one
+const TICKER_GAP: f32 = 48.0; // Space between the end of a scrolling line and its repeat
+const MIN_FONT_SIZE: f32 = 8.0;
+const MAX_FONT_SIZE: f32 = 48.0;
+
+/// How the polled position relates to where we expected playback to be.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlaybackJump {
+    None, // Normal progress (or a different song)
+    Seek, // The user jumped somewhere in the same song
+    Restart, // The same song started over (repeat, or "previous" near the start)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FetchStatus {
+    Pending,
+    Cached,
+    Fetched,
+    Failed,
+    Skipped, // On the user's skip list, never looked up
+}
+
+/// Tap sync in progress: plain lyrics being timed by hand, one tap per non-blank line.
+#[derive(Clone, Debug)]
+pub struct TapSync {
+    pub artists: Vec<String>,
+    pub title: String,
+    pub lyrics: String, // The plain lyrics being timed
+    pub taps: Vec<u32>, // Start times so far; taps[i] belongs to the i-th non-blank line
+}
+
+impl TapSync {
+    fn lines_to_tap(&self) -> impl Iterator<Item = &str> {
+        self.lyrics.lines().filter(|line| !line.trim().is_empty())
+    }
+
+    /// The line the next tap will time, if any are left.
+    pub fn next_line(&self) -> Option<&str> {
+        self.lines_to_tap().nth(self.taps.len())
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.lines_to_tap().count()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HistoryEntry {
+    pub artists: Vec<String>,
+    pub title: String,
+    pub status: FetchStatus,
+}
+
+/// A previously played song's cached lyrics, shown instead of the live song's until returning to live.
+#[derive(Clone, Debug)]
+pub struct HistoryView {
+    pub index: usize, // Position in `history` (0 = newest)
+    pub lyrics: Option<String>, // Plain text of the cached lyrics; None if nothing is cached for the song
+}
+
 #[derive(Clone, Debug)] // Removed Default, will init manually
 pub struct AppState {
+    pub settings: Settings, // Persisted preferences (see config.rs)
     pub current_info: Option<SpotifyInfo>,
-    pub lyrics: String,
+    pub lyrics: String, // Displayed lyrics: `raw_lyrics` after the user's display formatting
+    pub raw_lyrics: String, // Lyrics as fetched or cached
+    pub lines: Vec<LyricLine>, // `lyrics` split into lines, kept in sync by `set_lyrics`
+    pub lyrics_stale: bool, // Lyrics belong to the previous song and are kept (dimmed) until new ones load
+    pub lyrics_missing: bool, // The last fetch failed; `lyrics` holds the error message
+    pub album_art: Option<(String, Arc<egui::ColorImage>)>, // Decoded cover and the URL it came from
     pub status: String,
-    pub opacity: f32, // Opacity level (0.0 to 1.0)
+    pub progress_updated_at: Option<Instant>, // When current_info's progress_ms was last polled
+    pub auto_scroll: bool, // Scroll along with playback (synced lyrics follow the current line)
+    pub auto_scroll_speed: f32, // Multiplier applied to the playback fraction when auto-scrolling
+    pub auto_scroll_paused: bool, // Set when the user scrolls manually, cleared by "Resume"
+    pub recenter_requested: bool, // Scroll the current synced line to the anchor once, on the next frame
+    pub album_art_requested: bool, // Load the current cover even if the placeholder doesn't use it (share image)
+    pub server_enabled: bool, // Serve now-playing info on localhost (see server.rs)
+    pub refetch_requested: bool, // Set by the UI (button / F5), consumed by the poll loop
+    pub slug_artist_selection: Vec<bool>, // Per-artist URL inclusion for the current song
+    pub last_url: Option<String>, // Genius URL last attempted for the current song
+    pub history: VecDeque<HistoryEntry>, // Songs detected this session, newest first
+    pub history_view: Option<HistoryView>, // Browsing an earlier song's lyrics; None = live
+    pub devices: Vec<DeviceInfo>, // Last fetched Spotify Connect devices, for the device picker
+    pub devices_requested: bool, // Set by the UI, consumed by the poll loop
+    pub archive_path: String, // File used by cache export/import
+    pub up_next: Option<String>, // "artists - title" of the next queued track, if known
+    pub queue_checked_at: Option<Instant>, // When `up_next` was last fetched
+    pub fetch_skipped: bool, // The current song's lookup was skipped while fetching was paused
+    pub busy: bool, // A lyrics web fetch is in progress (drives the footer spinner)
+    pub volume_requested: Option<u8>, // Volume picked in the UI, sent to Spotify by the poll loop
+    pub scroll_to_top_requested: bool, // Scroll the lyrics back to the top on the next frame
+    pub lookup_input: String, // Spotify track link typed into the lookup field
+    pub lookup_requested: Option<String>, // Link to resolve, handed to the poll loop's runtime
+    pub controls_need_reauth: bool, // A control call was refused because the token lacks the modify scope
+    pub reauth_requested: bool, // Set by the UI, consumed by the poll loop (re-runs Spotify auth)
+    pub auth_url: Option<String>, // Spotify authorization page while re-authorizing waits for the redirect
+    pub lyrics_provider: Option<Provider>, // Provider of the shown lyrics (None: cached or not fetched), for the source badge
+    pub cache_notice: Option<String>, // Cache directory problem found at startup, shown until dismissed
+    pub editing_song: Option<(Vec<String>, String)>, // Artists and title whose lyrics are being edited by hand
+    pub lyrics_draft: String, // Text in the lyrics editor
+    pub tap_sync: Option<TapSync>, // Timing plain lyrics by tapping along
+    pub toast: Option<(String, Instant)>, // Transient notice in the corner and when it was raised
+    pub osd_raised: Option<Instant>, // When the now-playing popup for the current song appeared
+    pub refreshed_from: Option<Vec<LyricLine>>, // Lines replaced by a background refresh, to keep the reading position
+    pub manual_replacement: Option<(Vec<String>, String, lyrics::FetchedLyrics)>, // Web lyrics a re-fetch found for a song with hand-entered ones, until the user decides
 }
 
 impl Default for AppState {
     fn default() -> Self {
         Self {
+            settings: Settings::default(),
             current_info: None,
             lyrics: String::from(""),
+            raw_lyrics: String::new(),
+            lines: Vec::new(),
+            lyrics_stale: false,
+            lyrics_missing: false,
+            album_art: None,
             status: String::from("Initializing..."),
-            opacity: 1.0, // Default to fully opaque
+            progress_updated_at: None,
+            auto_scroll: false,
+            auto_scroll_speed: 1.0,
+            auto_scroll_paused: false,
+            recenter_requested: false,
+            album_art_requested: false,
+            server_enabled: false,
+            refetch_requested: false,
+            slug_artist_selection: Vec::new(),
+            last_url: None,
+            history: VecDeque::new(),
+            history_view: None,
+            devices: Vec::new(),
+            devices_requested: true, // Populate the picker on startup
+            archive_path: dirs::home_dir()
+                .unwrap_or_else(|| std::path::PathBuf::from("."))
+                .join("lyricrs_cache_export.json")
+                .display()
+                .to_string(),
+            up_next: None,
+            queue_checked_at: None,
+            fetch_skipped: false,
+            busy: false,
+            volume_requested: None,
+            scroll_to_top_requested: false,
+            lookup_input: String::new(),
+            lookup_requested: None,
+            controls_need_reauth: false,
+            reauth_requested: false,
+            auth_url: None,
+            lyrics_provider: None,
+            cache_notice: None,
+            editing_song: None,
+            lyrics_draft: String::new(),
+            tap_sync: None,
+            toast: None,
+            osd_raised: None,
+            refreshed_from: None,
+            manual_replacement: None,
+        }
+    }
+}
+
+impl AppState {
+    /// State at startup. A song found by the startup poll is shown right away; the poll loop fetches its lyrics first thing.
+    pub fn new(settings: Settings, startup_info: Option<SpotifyInfo>) -> Self {
+        let mut state = AppState { settings, ..AppState::default() };
+        if let Some(info) = startup_info {
+            state.status = format!("Looking for lyrics for {} - {}...", state.settings.display_artists(&info.artists), info.title);
+            state.apply_playback_update(info);
+        }
+        state
+    }
+
+    /// Replaces the displayed lyrics and re-parses them into lines.
+    pub fn set_lyrics(&mut self, lyrics: String) {
+        self.raw_lyrics = lyrics;
+        self.reformat_lyrics();
+        self.lyrics_stale = false;
+        self.lyrics_missing = false;
+    }
+
+    /// Re-applies the display formatting settings to the current lyrics.
+    pub fn reformat_lyrics(&mut self) {
+        let formatted = lyrics::format_lyrics(&self.raw_lyrics, &self.settings.lyrics_format);
+        self.lines = lyrics::parse_lyric_lines(&formatted);
+        self.lyrics = lyrics::strip_annotation_marks(&formatted);
+        if self.settings.lyrics_mode == LyricsMode::Plain && self.lines.iter().any(|line| line.time_ms.is_some()) {
+            // Drop the timestamps, so nothing is highlighted and the plain text reads cleanly
+            for line in &mut self.lines {
+                line.time_ms = None;
+            }
+            self.lyrics = self.lines.iter().map(|line| line.text.as_str()).collect::<Vec<_>>().join("\n");
+        }
+    }
+
+    /// Stores freshly polled playback info, snapping the interpolation to its progress.
+    /// Reports whether the position jumped further than normal drift, and whether that was a restart.
+    pub fn apply_playback_update(&mut self, info: SpotifyInfo) -> PlaybackJump {
+        let same_song = self.current_info.as_ref().is_some_and(|current| current.title == info.title && current.artists == info.artists);
+        let expected = if same_song { self.interpolated_progress_ms() } else { None };
+        let jump = match (expected, info.progress_ms) {
+            (Some(expected), Some(actual)) if expected.abs_diff(actual) > SEEK_THRESHOLD_MS => {
+                if actual < expected && actual <= RESTART_WINDOW_MS {
+                    PlaybackJump::Restart
+                } else {
+                    PlaybackJump::Seek
+                }
+            }
+            _ => PlaybackJump::None,
+        };
+        self.current_info = Some(info);
+        self.progress_updated_at = Some(Instant::now());
+        jump
+    }
+
+    /// Records a newly detected song at the front of the history (consecutive repeats are merged).
+    pub fn push_history(&mut self, info: &SpotifyInfo) {
+        let is_repeat = self.history.front().is_some_and(|entry| entry.title == info.title && entry.artists == info.artists);
+        if !is_repeat {
+            self.history.push_front(HistoryEntry {
+                artists: info.artists.clone(),
+                title: info.title.clone(),
+                status: FetchStatus::Pending,
+            });
+            self.history.truncate(MAX_HISTORY_ENTRIES);
+            playlog::record(&info.artists, &info.title, nowplaying::last_source());
+            if self.settings.now_playing_osd {
+                self.osd_raised = Some(Instant::now());
+            }
+            // A browsed entry moves down one; leave it for the new song if the user wants that
+            if self.settings.history_auto_return {
+                self.history_view = None;
+            } else if let Some(view) = self.history_view.as_mut() {
+                view.index += 1;
+                if view.index >= self.history.len() {
+                    self.history_view = None;
+                }
+            }
+        }
+    }
+
+    // Index of the playing song's history entry (normally the newest), which "live" stands for
+    fn live_history_index(&self) -> Option<usize> {
+        let info = self.current_info.as_ref()?;
+        self.history.front().filter(|entry| entry.title == info.title && entry.artists == info.artists).map(|_| 0)
+    }
+
+    /// Shows the cached lyrics of history entry `index` without touching playback or the live lyrics.
+    pub fn view_history(&mut self, index: usize) {
+        let Some(entry) = self.history.get(index) else {
+            return;
+        };
+        if self.live_history_index() == Some(index) {
+            self.history_view = None;
+            return;
+        }
+        let lyrics = cache::get_lyrics_from_cache(&entry.artists, &entry.title).map(|lyrics| {
+            // Timestamps would follow the live song's position, so history is always shown plain
+            let formatted = lyrics::format_lyrics(&lyrics, &self.settings.lyrics_format);
+            lyrics::parse_lyric_lines(&formatted).into_iter().map(|line| line.text).collect::<Vec<_>>().join("\n")
+        });
+        self.history_view = Some(HistoryView { index, lyrics });
+        self.scroll_to_top_requested = true;
+    }
+
+    /// Steps to the previous (older) song in the history.
+    pub fn history_back(&mut self) {
+        let next = match &self.history_view {
+            Some(view) => view.index + 1,
+            None => self.live_history_index().map_or(0, |live| live + 1),
+        };
+        if next < self.history.len() {
+            self.view_history(next);
+        }
+    }
+
+    /// Steps to the next (newer) song, back to live after the newest.
+    pub fn history_forward(&mut self) {
+        match self.history_view.as_ref().map(|view| view.index) {
+            Some(0) | None => self.history_view = None,
+            Some(index) => self.view_history(index - 1),
+        }
+    }
+
+    /// Updates the fetch status of the history entry for this song, if it's the latest one.
+    pub fn set_history_status(&mut self, info: &SpotifyInfo, status: FetchStatus) {
+        if let Some(entry) = self.history.front_mut()
+            && entry.title == info.title
+            && entry.artists == info.artists
+        {
+            entry.status = status;
+            match status {
+                FetchStatus::Cached | FetchStatus::Fetched => playlog::set_lyrics_found(&info.artists, &info.title, true),
+                FetchStatus::Failed | FetchStatus::Skipped => playlog::set_lyrics_found(&info.artists, &info.title, false),
+                FetchStatus::Pending => {}
+            }
+        }
+    }
+
+    /// Puts the playing song on the skip list and clears its lyrics.
+    pub fn skip_current_song(&mut self) {
+        let Some(info) = self.current_info.clone() else {
+            return;
+        };
+        cache::set_song_skipped(&info.artists, &info.title, true);
+        self.set_lyrics(String::new());
+        self.lyrics_missing = true;
+        self.status = SKIPPED_STATUS.to_string();
+        self.set_history_status(&info, FetchStatus::Skipped);
+    }
+
+    /// Opens the lyrics editor for the playing song, starting from what's shown
+    /// (unless that's an error message or the previous song's lyrics).
+    pub fn start_editing_lyrics(&mut self) {
+        let Some(info) = self.current_info.as_ref() else {
+            return;
+        };
+        let shown = !self.lyrics_missing && !self.lyrics_stale;
+        self.editing_song = Some((info.artists.clone(), info.title.clone()));
+        self.lyrics_draft = if shown { lyrics::strip_annotation_marks(&self.raw_lyrics) } else { String::new() };
+    }
+
+    /// Whether the shown lyrics are plain ones that tap sync could time.
+    pub fn can_tap_sync(&self) -> bool {
+        self.current_info.is_some()
+            && self.tap_sync.is_none()
+            && !self.lyrics_missing
+            && !self.lyrics_stale
+            && self.lines.iter().any(|line| !line.text.trim().is_empty())
+            && self.lines.iter().all(|line| line.time_ms.is_none())
+    }
+
+    /// Starts timing the shown plain lyrics from the playing song's position.
+    pub fn start_tap_sync(&mut self) {
+        let Some(info) = self.current_info.as_ref() else {
+            return;
+        };
+        self.tap_sync = Some(TapSync { artists: info.artists.clone(), title: info.title.clone(), lyrics: lyrics::strip_annotation_marks(&self.raw_lyrics), taps: Vec::new() });
+    }
+
+    /// Records the playback position as the start of the next line. Taps that go back in time are ignored.
+    pub fn tap(&mut self) {
+        let progress = self.interpolated_progress_ms();
+        let Some(sync) = self.tap_sync.as_mut() else {
+            return;
+        };
+        if let Some(progress) = progress
+            && sync.next_line().is_some()
+            && sync.taps.last().is_none_or(|&last| progress >= last)
+        {
+            sync.taps.push(progress);
+        }
+    }
+
+    /// Saves the tapped timings as manual (pinned) synced lyrics and shows them if the song is still playing.
+    pub fn finish_tap_sync(&mut self) {
+        let Some(sync) = self.tap_sync.take() else {
+            return;
+        };
+        let lrc = lyrics::tapped_lrc(&sync.lyrics, &sync.taps);
+        let saved = cache::store_manual_lyrics(&sync.artists, &sync.title, &lrc);
+        let still_playing = self.current_info.as_ref().is_some_and(|info| info.artists == sync.artists && info.title == sync.title);
+        if still_playing {
+            self.set_lyrics(lrc);
+        }
+        let artists_str = self.settings.display_artists(&sync.artists);
+        self.status = if saved {
+            format!("Saved synced lyrics for {} - {} ({} lines timed)", artists_str, sync.title, sync.taps.len())
+        } else {
+            "Could not save the synced lyrics to the cache".to_string()
+        };
+    }
+
+    /// Resets the current song's artist selection to the default: just the primary one unless configured otherwise.
+    pub fn reset_slug_artist_selection(&mut self) {
+        let all = self.settings.slug_all_artists;
+        let count = self.current_info.as_ref().map_or(0, |info| info.artists.len());
+        self.slug_artist_selection = (0..count).map(|i| all || i == 0).collect();
+    }
+
+    /// Artists to use when building the Genius URL, per the current selection.
+    /// Falls back to the primary artist if nothing (or a stale selection) is set.
+    pub fn slug_artists(&self, artists: &[String]) -> Vec<String> {
+        let selected: Vec<String> = artists
+            .iter()
+            .zip(&self.slug_artist_selection)
+            .filter(|(_, include)| **include)
+            .map(|(artist, _)| artist.clone())
+            .collect();
+        if selected.is_empty() || self.slug_artist_selection.len() != artists.len() {
+            artists.iter().take(1).cloned().collect()
+        } else {
+            selected
+        }
+    }
+
+    /// The synced line at the current (interpolated) playback position, if the lyrics are synced.
+    pub fn current_line(&self) -> Option<&LyricLine> {
+        let progress = self.interpolated_progress_ms()?;
+        lyrics::current_line_index(&self.lines, progress).map(|index| &self.lines[index])
+    }
+
+    /// Whether a song is loaded but not playing (the paused-lyrics setting applies).
+    pub fn is_paused(&self) -> bool {
+        self.current_info.as_ref().is_some_and(|info| !info.is_playing)
+    }
+
+    /// Time until the next synced line starts, while playing. Used to repaint exactly when the highlight moves.
+    pub fn next_line_change_in(&self) -> Option<Duration> {
+        if !self.current_info.as_ref().is_some_and(|info| info.is_playing) {
+            return None;
+        }
+        let progress = self.interpolated_progress_ms()?;
+        let next = self.lines.iter().filter_map(|line| line.time_ms).filter(|&time| time > progress).min()?;
+        Some(Duration::from_millis(u64::from(next - progress)))
+    }
+
+    /// Estimates the current playback position between polls.
+    /// Adds the time elapsed since the last poll while playing, clamped to the track duration.
+    pub fn interpolated_progress_ms(&self) -> Option<u32> {
+        let info = self.current_info.as_ref()?;
+        let progress = info.progress_ms?;
+        let elapsed = match (info.is_playing, self.progress_updated_at) {
+            (true, Some(updated_at)) => updated_at.elapsed().as_millis() as u32,
+            _ => 0,
+        };
+        let estimate = progress.saturating_add(elapsed);
+        Some(info.duration_ms.map_or(estimate, |duration| estimate.min(duration)))
+    }
+}
+
+
+// POLL_INTERVAL shifted by up to ±`jitter` (at most half the interval), so several instances don't poll in lockstep.
+// `random` is any random number; the same value always gives the same interval.
+fn jittered_poll_interval(jitter: Duration, random: u64) -> Duration {
+    let jitter_ms = jitter.min(POLL_INTERVAL / 2).as_millis() as u64;
+    let offset_ms = random % (2 * jitter_ms + 1); // 0..=2*jitter, centered on the interval below
+    POLL_INTERVAL + Duration::from_millis(offset_ms) - Duration::from_millis(jitter_ms)
+}
+
+// --- Background Refresh ---
+
+// Re-fetches lyrics for a song whose cache entry is stale. The cache is only updated when the new
+// result differs and is at least as long, and the display only changes if the song is still on screen.
+async fn refresh_stale_lyrics(state: Arc<Mutex<AppState>>, info: SpotifyInfo, slug_artists: Vec<String>, cached: String) {
+    println!("Cached lyrics for {} - {} are stale, refreshing in background...", info.artists.join(", "), info.title);
+    let options = state.lock().unwrap().settings.fetch_options();
+    let fresh = match lyrics::fetch_and_parse_lyrics(&slug_artists, &info.title, &options).await {
+        Ok(fresh) => fresh,
+        Err(e) => {
+            println!("Background refresh failed, keeping cached lyrics: {}", e);
+            return;
+        }
+    };
+
+    let lyrics::FetchedLyrics { lyrics: fresh, url, .. } = fresh;
+    let improved = fresh != cached && fresh.len() >= cached.len();
+    // Re-store the better version either way so the entry counts as fresh again
+    cache::store_lyrics_to_cache(&info.artists, &info.title, if improved { &fresh } else { &cached }, Some(&url));
+    if !improved {
+        return;
+    }
+
+    println!("Updated cached lyrics for {} - {} from background refresh.", info.artists.join(", "), info.title);
+    let mut current_state = state.lock().unwrap();
+    let still_showing = current_state.current_info.as_ref().is_some_and(|current| current.title == info.title && current.artists == info.artists)
+        && current_state.raw_lyrics == cached;
+    if still_showing {
+        let previous = std::mem::take(&mut current_state.lines);
+        current_state.set_lyrics(fresh);
+        current_state.refreshed_from = Some(previous);
+        current_state.toast = Some(("Lyrics updated".to_string(), Instant::now()));
+    }
+}
+
+// Fetches lyrics for the next few tracks of the album being played through, so they're cached when they come up.
+// Lookups are spaced out and the run stops once the song changes (the next song starts its own run).
+async fn prefetch_album(state: Arc<Mutex<AppState>>, album_uri: String, info: SpotifyInfo) {
+    let tracks = match spotify::album_tracks(&album_uri).await {
+        Ok(tracks) => tracks,
+        Err(e) => {
+            println!("Album prefetch skipped: {}", e);
+            return;
+        }
+    };
+    let (limit, all_artists) = {
+        let current_state = state.lock().unwrap();
+        (current_state.settings.album_prefetch_tracks.clamp(1, MAX_ALBUM_PREFETCH_TRACKS), current_state.settings.slug_all_artists)
+    };
+    let upcoming: Vec<_> = spotify::tracks_after(&tracks, &info.artists, &info.title, limit)
+        .into_iter()
+        .filter(|(artists, title)| cache::get_cache_age(artists, title).is_none() && !cache::is_song_skipped(artists, title))
+        .collect();
+
+    for (artists, title) in upcoming {
+        tokio::time::sleep(ALBUM_PREFETCH_SPACING).await;
+        let options = {
+            let current_state = state.lock().unwrap();
+            let still_playing = current_state.current_info.as_ref().is_some_and(|current| current.title == info.title && current.artists == info.artists);
+            if !still_playing || !current_state.settings.album_prefetch || current_state.settings.fetching_paused {
+                return;
+            }
+            current_state.settings.fetch_options()
+        };
+        let slug_artists: Vec<String> = artists.iter().take(if all_artists { usize::MAX } else { 1 }).cloned().collect();
+        match lyrics::fetch_and_parse_lyrics(&slug_artists, &title, &options).await {
+            Ok(fetched) => {
+                println!("Prefetched lyrics for {} - {}", artists.join(", "), title);
+                cache::store_lyrics_to_cache(&artists, &title, &fetched.lyrics, Some(&fetched.url));
+            }
+            Err(e) => println!("Prefetch failed for {} - {}: {}", artists.join(", "), title, e.lines().next().unwrap_or_default()),
+        }
+    }
+}
+
+// Shows the lyrics of a pasted Spotify track, whatever is currently playing (until the song changes)
+async fn lookup_track_lyrics(state: Arc<Mutex<AppState>>, uri: String) {
+    state.lock().unwrap().status = "Looking up track...".to_string();
+    let info = match spotify::track_info_from_uri(&uri).await {
+        Ok(info) => info,
+        Err(e) => {
+            println!("Track lookup failed: {}", e);
+            state.lock().unwrap().status = format!("Lookup failed: {}", e);
+            return;
+        }
+    };
+    let current_state = state.lock().unwrap();
+    let artists_str = current_state.settings.display_artists(&info.artists);
+    // No per-song artist selection here, so follow the default
+    let slug_artists: Vec<String> = info.artists.iter().take(if current_state.settings.slug_all_artists { usize::MAX } else { 1 }).cloned().collect();
+    let fetching_paused = current_state.settings.fetching_paused;
+    let options = current_state.settings.fetch_options();
+    drop(current_state);
+
+    // Lyrics with their page and provider (None for cached ones), like the poll loop shows them
+    let result = match cache::get_lyrics_from_cache(&info.artists, &info.title) {
+        Some(lyrics) => Ok((lyrics, cache::get_cached_source_url(&info.artists, &info.title), None, FetchStatus::Cached)),
+        None if fetching_paused => Err("Fetching paused".to_string()),
+        None => {
+            let fetched = lyrics::fetch_and_parse_lyrics(&slug_artists, &info.title, &options).await;
+            if let Ok(fetched) = &fetched {
+                cache::store_lyrics_to_cache(&info.artists, &info.title, &fetched.lyrics, Some(&fetched.url));
+            }
+            fetched.map(|fetched| (fetched.lyrics, Some(fetched.url), Some(fetched.provider), FetchStatus::Fetched))
+        }
+    };
+
+    let mut current_state = state.lock().unwrap();
+    match result {
+        Ok((lyrics, url, provider, status)) => {
+            current_state.set_lyrics(lyrics);
+            current_state.last_url = url;
+            current_state.lyrics_provider = provider;
+            current_state.status = format!("Showing lyrics for {} - {} (Lookup)", artists_str, info.title);
+            current_state.set_history_status(&info, status);
+        }
+        Err(e) => {
+            current_state.status = format!("No lyrics for {} - {}: {}", artists_str, info.title, e);
+            if !fetching_paused {
+                current_state.set_history_status(&info, FetchStatus::Failed);
+            }
+        }
+    }
+}
+
+async fn load_album_art(state: Arc<Mutex<AppState>>, url: String) {
+    match artwork::fetch_album_art(&url).await {
+        Ok(image) => state.lock().unwrap().album_art = Some((url, Arc::new(image))),
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+// --- Command Palette ---
+
+/// Actions offered by the command palette (Ctrl+P). New actions go here, not into the config panel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PaletteCommand {
+    Refetch,
+    CopyLyrics,
+    CopyUrl,
+    EditLyrics,
+    TapSync,
+    TogglePin,
+    SkipSong,
+    ToggleAutoScroll,
+    TogglePauseFetching,
+    ToggleHighContrast,
+    Present,
+    ExportCache,
+    OpenCacheFolder,
+}
+
+impl PaletteCommand {
+    const ALL: [PaletteCommand; 13] = [
+        PaletteCommand::Refetch,
+        PaletteCommand::CopyLyrics,
+        PaletteCommand::CopyUrl,
+        PaletteCommand::EditLyrics,
+        PaletteCommand::TapSync,
+        PaletteCommand::TogglePin,
+        PaletteCommand::SkipSong,
+        PaletteCommand::ToggleAutoScroll,
+        PaletteCommand::TogglePauseFetching,
+        PaletteCommand::ToggleHighContrast,
+        PaletteCommand::Present,
+        PaletteCommand::ExportCache,
+        PaletteCommand::OpenCacheFolder,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            PaletteCommand::Refetch => "Re-fetch lyrics",
+            PaletteCommand::CopyLyrics => "Copy lyrics",
+            PaletteCommand::CopyUrl => "Copy Genius URL",
+            PaletteCommand::EditLyrics => "Edit lyrics",
+            PaletteCommand::TapSync => "Tap sync (time plain lyrics)",
+            PaletteCommand::TogglePin => "Pin / unpin cached lyrics",
+            PaletteCommand::SkipSong => "Skip this song",
+            PaletteCommand::ToggleAutoScroll => "Toggle auto-scroll",
+            PaletteCommand::TogglePauseFetching => "Pause / resume lyrics fetching",
+            PaletteCommand::ToggleHighContrast => "Toggle high contrast theme",
+            PaletteCommand::Present => "Presentation mode",
+            PaletteCommand::ExportCache => "Export cache",
+            PaletteCommand::OpenCacheFolder => "Open cache folder",
+        }
+    }
+
+    // Unavailable commands are left out of the list rather than shown disabled
+    fn available(self, state: &AppState) -> bool {
+        let song = state.current_info.as_ref();
+        let skipped = song.is_some_and(|info| cache::is_song_skipped(&info.artists, &info.title));
+        match self {
+            PaletteCommand::Refetch => song.is_some() && !state.refetch_requested,
+            PaletteCommand::CopyLyrics => !state.lines.is_empty() && !state.lyrics_missing,
+            PaletteCommand::CopyUrl => state.last_url.is_some(),
+            PaletteCommand::EditLyrics => song.is_some() && state.editing_song.is_none(),
+            PaletteCommand::TapSync => state.can_tap_sync(),
+            PaletteCommand::TogglePin => song.is_some_and(|info| cache::get_cache_age(&info.artists, &info.title).is_some()) && !skipped,
+            PaletteCommand::SkipSong => song.is_some() && !skipped,
+            PaletteCommand::ToggleAutoScroll
+            | PaletteCommand::TogglePauseFetching
+            | PaletteCommand::ToggleHighContrast
+            | PaletteCommand::Present
+            | PaletteCommand::ExportCache => true,
+            PaletteCommand::OpenCacheFolder => cache::cache_dir().is_some(),
+        }
+    }
+}
+
+/// Open command palette: the filter text and the highlighted entry.
+#[derive(Default)]
+struct CommandPalette {
+    query: String,
+    selected: usize,
+}
+
+// Fuzzy match: every query character must appear in order (case-insensitive). Higher is better;
+// consecutive characters and matches at word starts score extra, so "cl" ranks "Copy lyrics" well.
+fn fuzzy_score(query: &str, label: &str) -> Option<i32> {
+    let label: Vec<char> = label.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut position = 0;
+    let mut previous_match: Option<usize> = None;
+    for wanted in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = position + label[position..].iter().position(|&c| c == wanted)?;
+        score += 1;
+        if previous_match.is_some_and(|previous| previous + 1 == found) {
+            score += 2;
+        }
+        if found == 0 || !label[found - 1].is_alphanumeric() {
+            score += 3;
         }
+        previous_match = Some(found);
+        position = found + 1;
+    }
+    Some(score)
+}
+
+// Available commands matching the query, best first (ties keep the registry order)
+fn palette_matches(query: &str, state: &AppState) -> Vec<PaletteCommand> {
+    let mut matches: Vec<(i32, PaletteCommand)> = PaletteCommand::ALL
+        .into_iter()
+        .filter(|command| command.available(state))
+        .filter_map(|command| fuzzy_score(query, command.label()).map(|score| (score, command)))
+        .collect();
+    matches.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+    matches.into_iter().map(|(_, command)| command).collect()
+}
+
+// Writes out what the poll loop hasn't flushed yet, including the song still playing (on exit)
+fn flush_play_log(state: &Mutex<AppState>) {
+    let settings = state.lock().unwrap_or_else(PoisonError::into_inner).settings.clone();
+    if settings.play_log
+        && let Err(e) = playlog::flush(settings.play_log_format, true)
+    {
+        eprintln!("{}", e);
     }
 }
 
+// --- Headless Mode ---
+
+/// Runs without a window (`--daemon`): the poll loop follows playback and fetches lyrics, and the local
+/// server (plus line output, if enabled) serves them. Returns after Ctrl+C / SIGINT, once the log is flushed.
+pub async fn run_daemon(settings: Settings, startup_info: Option<SpotifyInfo>) -> Result<(), String> {
+    let line_output_path = settings.line_output.then(|| settings.line_output_path()).flatten();
+    let state = Arc::new(Mutex::new(AppState::new(settings, startup_info)));
+    let _server = server::start(Arc::clone(&state)).map_err(|e| format!("Failed to start local server on {}: {}", server::SERVER_ADDR, e))?;
+    state.lock().unwrap().server_enabled = true;
+    let _line_output = line_output_path.map(|path| server::start_line_output(Arc::clone(&state), path));
+    LyricsApp::spawn_poll_loop(Arc::clone(&state), None);
+    println!("Running headless, serving lyrics on http://{} (Ctrl+C to stop)", server::SERVER_ADDR);
+
+    tokio::signal::ctrl_c().await.map_err(|e| format!("Failed to listen for Ctrl+C: {}", e))?;
+    println!("Shutting down...");
+    flush_play_log(&state);
+    Ok(()) // Dropping the handles stops the server and the line output
+}
 
 // --- GUI Application ---
 
+/// Progress of the "share image" feature: pick a line, then draw the card and screenshot it.
+enum ShareState {
+    Idle,
+    Picking,
+    Capturing { line: String, card_rect: Option<egui::Rect> }, // Rect is set once the screenshot is requested
+}
+
 pub struct LyricsApp {
     state: Arc<Mutex<AppState>>,
+    max_scroll: f32, // Scrollable height of the lyrics area, measured on the previous frame
+    scroll_fraction: f32, // How far the lyrics are scrolled (0.0 top, 1.0 bottom), for the footer's line indicator
+    lyrics_slack: f32, // Unused height of the lyrics area on the last frame (negative when it overflows), for "fit content"
+    server: Option<server::ServerHandle>, // Running local HTTP server, if enabled
+    line_output: Option<server::ServerHandle>, // Running line output writer, if enabled
+    saved_settings: Settings, // Last settings written to disk, to detect changes
+    album_art_textures: artwork::TextureCache<egui::TextureHandle>, // GPU copies of recent covers, by URL
+    ticker: (String, f32), // Line shown by the single-line ticker and its scroll offset
+    applied_opacity: f32, // Opacity last passed to apply_opacity (animated between active/idle levels)
+    base_visuals: egui::Visuals, // Startup theme, restored when high contrast is turned off
+    applied_high_contrast: bool,
+    share: ShareState,
+    presenting: bool, // Fullscreen "lyrics only" view (F11 toggles, Esc leaves)
+    palette: Option<CommandPalette>, // Open command palette (Ctrl+P), never persisted
+    opened_auth_url: Option<String>, // Authorization page last opened in the browser
 }
 
 impl LyricsApp {
-    pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
+    pub fn new(cc: &eframe::CreationContext<'_>, settings: Settings, startup_info: Option<SpotifyInfo>) -> Self {
         // --- Font Configuration ---
-        let mut fonts = FontDefinitions::default();
-        fonts.font_data.insert(
-            "noto_sans_kr".to_owned(),
-            FontData::from_static(include_bytes!("../../assets/NotoSansKR-VariableFont_wght.ttf"))
-        );
-        fonts
-            .families
-            .entry(Proportional)
-            .or_default()
-            .insert(0, "noto_sans_kr".to_owned());
-        cc.egui_ctx.set_fonts(fonts);
+        cc.egui_ctx.set_fonts(Self::font_definitions());
         // --- End Font Configuration ---
 
         // Apply initial visuals (including opacity)
-        let initial_state = AppState::default();
-        let initial_opacity = initial_state.opacity;
+        let initial_state = AppState::new(settings, startup_info);
+        let saved_settings = initial_state.settings.clone();
+        let base_visuals = cc.egui_ctx.style().visuals.clone();
+        let high_contrast = initial_state.settings.high_contrast;
+        let initial_opacity = if high_contrast { 1.0 } else { initial_state.settings.opacity };
+        if high_contrast {
+            cc.egui_ctx.set_visuals(Self::high_contrast_visuals());
+        }
         Self::apply_opacity(&cc.egui_ctx, initial_opacity);
 
 
         let state = Arc::new(Mutex::new(initial_state));
 
 
-        // --- Background Thread ---
-        let state_clone = Arc::clone(&state);
+        Self::spawn_poll_loop(Arc::clone(&state), Some(cc.egui_ctx.clone()));
+
+        // The remote source is fed through the local server, so it has to be running
+        let mut server = None;
+        if nowplaying::uses_remote(&saved_settings) {
+            match server::start(Arc::clone(&state)) {
+                Ok(handle) => {
+                    server = Some(handle);
+                    state.lock().unwrap().server_enabled = true;
+                }
+                Err(e) => {
+                    eprintln!("Failed to start local server: {}", e);
+                    state.lock().unwrap().status = format!("Local server error: {}", e);
+                }
+            }
+        }
+
+        let line_output = saved_settings.line_output
+            .then(|| saved_settings.line_output_path())
+            .flatten()
+            .map(|path| server::start_line_output(Arc::clone(&state), path));
+
+        Self { state, max_scroll: 0.0, scroll_fraction: 0.0, lyrics_slack: 0.0, server, line_output, saved_settings, album_art_textures: artwork::TextureCache::new(artwork::MAX_CACHED_TEXTURES), ticker: (String::new(), 0.0), applied_opacity: initial_opacity, base_visuals, applied_high_contrast: high_contrast, share: ShareState::Idle, presenting: false, palette: None, opened_auth_url: None }
+    }
+
+    // --- Background Thread ---
+    // Polls playback and fetches lyrics into `state_clone` until the process ends. The UI only repaints
+    // on demand, so with a `repaint_ctx` the loop wakes it after each poll (None when running headless).
+    fn spawn_poll_loop(state_clone: Arc<Mutex<AppState>>, repaint_ctx: Option<egui::Context>) {
         std::thread::spawn(move || {
             let rt = tokio::runtime::Builder::new_multi_thread()
                 .enable_all()
@@ -70,66 +844,333 @@ impl LyricsApp {
                 .expect("Failed to create Tokio runtime in background thread");
 
             // Initialize the cache (synchronous call)
-            if let Err(e) = cache::init_cache() {
+            let (cache_mode, cache_file_names, compression, verify_cache) = {
+                let settings = &state_clone.lock().unwrap().settings;
+                (settings.cache_mode, settings.cache_file_names, (settings.cache_codec, settings.cache_compression_level), settings.verify_cache_on_start)
+            };
+            cache::set_file_names(cache_file_names);
+            cache::set_compression(compression.0, compression.1);
+            lyrics::set_slug_strip_tags(&state_clone.lock().unwrap().settings.slug_strip_tags);
+            if let Err(e) = cache::init_cache(cache_mode) {
                 eprintln!("Failed to initialize lyrics cache: {}", e);
                 // Application can continue, but caching won't work
+                state_clone.lock().unwrap().cache_notice = Some(format!("Lyrics cache unavailable ({}). Try \"Memory only\".", e));
+            } else if verify_cache
+                && let Err(e) = cache::prune_missing_files()
+            {
+                eprintln!("Failed to verify lyrics cache: {}", e);
+            }
+
+            if let Some(notice) = cache::take_dir_notice() {
+                state_clone.lock().unwrap().cache_notice = Some(notice);
+            }
+
+            // Selector fixes published by the community; the cached list covers a failed or slow fetch
+            let selector_update_url = state_clone.lock().unwrap().settings.selector_update_url.trim().to_string();
+            if !selector_update_url.is_empty() {
+                lyrics::load_cached_selector_update();
+                rt.spawn(async move {
+                    match lyrics::update_selectors(&selector_update_url).await {
+                        Ok(count) => println!("Updated lyrics selectors from {} ({} selectors)", selector_update_url, count),
+                        Err(e) => eprintln!("{}", e),
+                    }
+                });
             }
 
             let mut last_song_title: Option<String> = None; // Track only title to detect changes
+            let mut woke_from_sleep = false; // Set when the last poll interval spanned a suspend
+            let mut first_poll = true; // Lyrics come first on startup; secondary lookups wait for the next poll
+            let mut recent_panics: Vec<Instant> = Vec::new(); // When the loop body panicked, within PANIC_WINDOW
+            let mut idle_since: Option<Instant> = None; // Start of the current stretch with nothing playing
+            let mut window_hidden = false; // Hidden by auto-hide
+            let mut fetch_retries: (u32, Option<Instant>) = (0, None); // Automatic retries used for this song, and when the next is due
+            let mut last_log_flush = Instant::now(); // Listening log entries are appended every playlog::FLUSH_INTERVAL
 
             loop {
-                rt.block_on(async {
+                // A panic in one iteration (e.g. an unexpected API response) must not freeze the overlay for good
+                let iteration = panic::catch_unwind(AssertUnwindSafe(|| rt.block_on(async {
+                    if woke_from_sleep {
+                        // The token (and any error shown before sleeping) is likely stale; start over cleanly
+                        state_clone.lock().unwrap().status = "Reconnecting after sleep...".to_string();
+                        if let Err(e) = spotify::refresh_access_token().await {
+                            println!("{}", e);
+                        }
+                    }
+
                     let mut current_state = state_clone.lock().unwrap();
-                    current_state.status = "Checking Spotify...".to_string();
+                    if !first_poll {
+                        current_state.status = "Checking Spotify...".to_string(); // Keep the startup poll's status until lyrics arrive
+                    }
+                    // Take the re-fetch trigger so each request is handled exactly once
+                    let refetch_requested = std::mem::take(&mut current_state.refetch_requested);
+                    let devices_requested = std::mem::take(&mut current_state.devices_requested);
+                    let preferred_device = current_state.settings.preferred_device.as_ref().map(|(id, _)| id.clone());
+                    let poll_settings = current_state.settings.clone(); // Picks and configures the now-playing source
+                    let volume_requested = current_state.volume_requested.take();
+                    let lookup_requested = current_state.lookup_requested.take();
+                    let reauth_requested = std::mem::take(&mut current_state.reauth_requested);
+                    let art_url = current_state.current_info.as_ref().and_then(|info| info.album_art_url.clone());
+                    let art_missing = current_state.album_art.as_ref().map(|(url, _)| url) != art_url.as_ref();
+                    let album_art_requested = std::mem::take(&mut current_state.album_art_requested) && art_missing;
+                    let auth_settings = (current_state.settings.playback_endpoint, current_state.settings.show_up_next, current_state.settings.volume_control);
+                    let fetching_paused = current_state.settings.fetching_paused;
+                    // Look up the song that was skipped while paused, now that fetching is back on
+                    let resume_fetch = !fetching_paused && std::mem::take(&mut current_state.fetch_skipped);
+                    let auto_retry = current_state.settings.auto_retry_failed.then_some((current_state.settings.auto_retry_delay_secs, current_state.settings.auto_retry_max));
                     drop(current_state);
 
+                    if poll_settings.play_log && last_log_flush.elapsed() >= playlog::FLUSH_INTERVAL {
+                        last_log_flush = Instant::now();
+                        if let Err(e) = playlog::flush(poll_settings.play_log_format, false) {
+                            eprintln!("{}", e);
+                        }
+                    }
+
+                    if devices_requested {
+                        match spotify::list_devices().await {
+                            Ok(devices) => state_clone.lock().unwrap().devices = devices,
+                            Err(e) => println!("{}", e),
+                        }
+                    }
+
+                    if let Some(uri) = lookup_requested {
+                        lookup_track_lyrics(Arc::clone(&state_clone), uri).await;
+                    }
+
+                    if album_art_requested
+                        && let Some(url) = art_url
+                    {
+                        tokio::spawn(load_album_art(Arc::clone(&state_clone), url));
+                    }
+
+                    // In its own task: polling goes on with the current client while the browser is open
+                    if reauth_requested {
+                        let (endpoint, queue_access, volume_control) = auth_settings;
+                        let state = Arc::clone(&state_clone);
+                        tokio::spawn(async move {
+                            let show_url = {
+                                let state = Arc::clone(&state);
+                                move |url| {
+                                    let mut current_state = state.lock().unwrap();
+                                    current_state.status = "Waiting for Spotify authorization in your browser...".to_string();
+                                    current_state.auth_url = Some(url);
+                                }
+                            };
+                            let result = spotify::reauthorize(endpoint, queue_access, volume_control, show_url).await;
+                            let mut current_state = state.lock().unwrap();
+                            current_state.auth_url = None;
+                            current_state.status = match result {
+                                Ok(()) => {
+                                    current_state.controls_need_reauth = false;
+                                    "Spotify authorized".to_string()
+                                }
+                                Err(e) => {
+                                    eprintln!("{}", e);
+                                    e
+                                }
+                            };
+                        });
+                    }
+
+                    // Before polling, so the poll below already reports the new volume
+                    if let Some(volume) = volume_requested {
+                        match spotify::set_volume(volume, preferred_device.as_deref()).await {
+                            Ok(()) => {}
+                            Err(spotify::ControlError::MissingScope) => state_clone.lock().unwrap().controls_need_reauth = true,
+                            Err(spotify::ControlError::Failed(e)) => println!("{}", e),
+                        }
+                    }
+
                     // Await the async function call
-                    match spotify::get_current_info().await {
+                    match nowplaying::current(&poll_settings).await {
                         Ok(Some(info)) => {
                             let song_changed = last_song_title.as_ref() != Some(&info.title);
                             last_song_title = Some(info.title.clone());
 
                             // Store the latest info (including playback state)
                             let mut current_state = state_clone.lock().unwrap();
-                            current_state.current_info = Some(info.clone());
+                            match current_state.apply_playback_update(info.clone()) {
+                                PlaybackJump::None => {}
+                                PlaybackJump::Seek => {
+                                    println!("Seek detected, now at {:?} ms", info.progress_ms);
+                                    current_state.auto_scroll_paused = false; // Follow the new position again
+                                }
+                                PlaybackJump::Restart => {
+                                    println!("Song restarted: {} - {}", info.artists.join(", "), info.title);
+                                    current_state.auto_scroll_paused = false;
+                                    // The title is unchanged, so nothing else resets the view
+                                    if current_state.settings.reset_on_repeat {
+                                        current_state.scroll_to_top_requested = true;
+                                    }
+                                }
+                            }
+                            if song_changed {
+                                fetch_retries = (0, None);
+                                current_state.auto_scroll_paused = false; // Start each song from the top
+                                current_state.reset_slug_artist_selection();
+                                current_state.manual_replacement = None;
+                            }
                             drop(current_state);
 
-                            if song_changed {
-                                let artists_str = info.artists.join(", "); // For display/logging
-                                println!("New song detected: {} - {}", artists_str, info.title);
+                            // Up next: refreshed on song change, otherwise cached for QUEUE_CACHE_TTL
+                            let current_state = state_clone.lock().unwrap();
+                            let artist_separator = current_state.settings.artist_separator.clone();
+                            let queue_due = current_state.settings.show_up_next
+                                && poll_settings.now_playing_source == SourceKind::Spotify
+                                && !first_poll
+                                && (song_changed || current_state.queue_checked_at.is_none_or(|checked| checked.elapsed() > QUEUE_CACHE_TTL));
+                            drop(current_state);
+                            if queue_due {
+                                let up_next = match spotify::get_next_in_queue(&artist_separator).await {
+                                    Ok(next) => next,
+                                    Err(e) => {
+                                        println!("{}", e); // Likely missing scope; just hide the line
+                                        None
+                                    }
+                                };
+                                let mut current_state = state_clone.lock().unwrap();
+                                current_state.up_next = up_next;
+                                current_state.queue_checked_at = Some(Instant::now());
+                            }
+
+                            // A re-fetch skips the cache for this one lookup; the cached entry stays until the result
+                            // replaces it, and hand-entered lyrics are only replaced if the user confirms
+                            let bypass_cache = refetch_requested && !song_changed && !fetching_paused;
+                            if bypass_cache {
+                                println!("Re-fetch requested for: {} - {}", info.artists.join(", "), info.title);
+                            }
+
+                            // A retry scheduled after a failed fetch; a manual re-fetch starts the count over
+                            if refetch_requested {
+                                fetch_retries = (0, None);
+                            }
+                            let retry_due = !fetching_paused && fetch_retries.1.is_some_and(|due| Instant::now() >= due);
+                            if retry_due {
+                                fetch_retries.1 = None;
+                                println!("Retrying lyrics fetch ({}) for: {} - {}", fetch_retries.0, info.artists.join(", "), info.title);
+                            }
+
+                            if fetching_paused && (song_changed || refetch_requested) {
+                                // Keep the now-playing display, but don't touch the cache or the web
                                 let mut current_state = state_clone.lock().unwrap();
-                                current_state.lyrics = "".to_string(); // Clear lyrics immediately
+                                if song_changed {
+                                    println!("New song detected (fetching paused): {} - {}", info.artists.join(", "), info.title);
+                                    current_state.set_lyrics(String::new());
+                                    current_state.last_url = None;
+                                    current_state.push_history(&info);
+                                }
+                                current_state.fetch_skipped = true;
+                                current_state.status = "Fetching paused".to_string();
+                            } else if song_changed || refetch_requested || resume_fetch || retry_due {
+                                let mut current_state = state_clone.lock().unwrap();
+                                let artists_str = current_state.settings.display_artists(&info.artists); // For display/logging
+                                if song_changed {
+                                    println!("New song detected: {} - {}", artists_str, info.title);
+                                }
+                                if current_state.settings.keep_lyrics_on_change {
+                                    current_state.lyrics_stale = true; // Keep showing them dimmed until the fetch resolves
+                                } else {
+                                    current_state.set_lyrics(String::new()); // Clear lyrics immediately
+                                }
                                 current_state.status = format!("Looking for lyrics for {} - {}...", artists_str, info.title);
+                                current_state.lyrics_provider = None;
+                                current_state.push_history(&info);
+                                // Cover for the placeholder, loaded alongside the lyrics
+                                let loaded_art_url = current_state.album_art.as_ref().map(|(url, _)| url.clone());
+                                if (current_state.settings.placeholder == PlaceholderMode::AlbumArt || current_state.settings.now_playing_osd)
+                                    && let Some(url) = info.album_art_url.clone()
+                                    && loaded_art_url.as_ref() != Some(&url)
+                                {
+                                    tokio::spawn(load_album_art(Arc::clone(&state_clone), url));
+                                }
+                                if song_changed
+                                    && current_state.settings.album_prefetch
+                                    && nowplaying::last_source() == Some(SourceKind::Spotify)
+                                    && let Some(album_uri) = spotify::playing_album()
+                                {
+                                    tokio::spawn(prefetch_album(Arc::clone(&state_clone), album_uri, info.clone()));
+                                }
+                                let slug_artists = current_state.slug_artists(&info.artists);
+                                let fetch_options = current_state.settings.fetch_options();
+                                // Without artists the URL comes from a Genius search instead, so there's nothing to show
+                                current_state.last_url = (!slug_artists.is_empty()).then(|| lyrics::build_genius_url(&fetch_options.base_url, &slug_artists, &info.title, fetch_options.max_slug_artists));
                                 drop(current_state);
 
                                 // --- Check Cache First ---
-                                let cached_lyrics = cache::get_lyrics_from_cache(&info.artists, &info.title);
+                                let skipped = cache::is_song_skipped(&info.artists, &info.title);
+                                let cached_lyrics = if skipped || bypass_cache { None } else { cache::get_lyrics_from_cache(&info.artists, &info.title) };
 
-                                if let Some(lyrics) = cached_lyrics {
+                                if skipped {
+                                     let mut current_state = state_clone.lock().unwrap();
+                                     current_state.set_lyrics(String::new());
+                                     current_state.lyrics_missing = true;
+                                     current_state.status = SKIPPED_STATUS.to_string();
+                                     current_state.set_history_status(&info, FetchStatus::Skipped);
+                                } else if let Some(lyrics) = cached_lyrics {
                                      // Found in cache
+                                     let source_url = cache::get_cached_source_url(&info.artists, &info.title);
                                      let mut current_state = state_clone.lock().unwrap();
-                                     current_state.lyrics = lyrics;
-                                     current_state.status = format!("Showing lyrics for {} - {} (Cached)", artists_str, info.title);
+                                     if source_url.is_some() {
+                                         current_state.last_url = source_url; // The resolved page beats the guessed slug
+                                     }
+                                     // Hand-entered lyrics are never swapped for a web copy
+                                     let manual = cache::is_song_manual(&info.artists, &info.title);
+                                     let stale_after = (current_state.settings.refresh_stale && !manual).then(|| Duration::from_secs(u64::from(current_state.settings.stale_after_days) * 24 * 60 * 60));
+                                     current_state.set_lyrics(lyrics.clone());
+                                     current_state.status = format!("Showing lyrics for {} - {} ({})", artists_str, info.title, if manual { "Manual" } else { "Cached" });
+                                     current_state.set_history_status(&info, FetchStatus::Cached);
+                                     drop(current_state);
+
+                                     // Optionally re-check old entries in the background without blocking the poll loop
+                                     if let Some(stale_after) = stale_after
+                                         && cache::get_cache_age(&info.artists, &info.title).is_some_and(|age| age > stale_after)
+                                     {
+                                         tokio::spawn(refresh_stale_lyrics(Arc::clone(&state_clone), info.clone(), slug_artists, lyrics));
+                                     }
                                 } else {
                                      // Not in cache, fetch from Genius
                                      current_state = state_clone.lock().unwrap(); // Re-acquire lock
                                      current_state.status = format!("Fetching lyrics for {} - {} (Web)...", artists_str, info.title);
+                                     current_state.busy = true;
                                      drop(current_state);
 
-                                     match lyrics::fetch_and_parse_lyrics(&info.artists, &info.title).await {
-                                        Ok(cleaned_lyrics) => {
-                                            // Store in cache *before* updating UI state
-                                            cache::store_lyrics_to_cache(&info.artists, &info.title, &cleaned_lyrics);
-
+                                     let fetch_result = lyrics::fetch_and_parse_lyrics(&slug_artists, &info.title, &fetch_options).await;
+                                     state_clone.lock().unwrap().busy = false;
+                                     match fetch_result {
+                                        Ok(fetched) if !cache::store_fetched_lyrics(&info.artists, &info.title, &fetched.lyrics, Some(&fetched.url)) => {
+                                            // The song has hand-entered lyrics: keep showing them and ask before replacing them
+                                            let mut current_state = state_clone.lock().unwrap();
+                                            if let Some(manual) = cache::get_lyrics_from_cache(&info.artists, &info.title) {
+                                                current_state.set_lyrics(manual);
+                                            }
+                                            current_state.last_url = None;
+                                            current_state.status = format!("Showing lyrics for {} - {} (Manual)", artists_str, info.title);
+                                            current_state.set_history_status(&info, FetchStatus::Cached);
+                                            current_state.manual_replacement = Some((info.artists.clone(), info.title.clone(), fetched));
+                                        }
+                                        Ok(fetched) => {
+                                            // Stored in cache (above) *before* updating UI state
                                             let mut current_state = state_clone.lock().unwrap();
-                                            current_state.lyrics = cleaned_lyrics;
+                                            current_state.last_url = Some(fetched.url); // Where the lyrics really came from
+                                            current_state.lyrics_provider = Some(fetched.provider);
+                                            current_state.set_lyrics(fetched.lyrics);
                                             current_state.status = format!("Showing lyrics for {} - {}", artists_str, info.title);
+                                            current_state.set_history_status(&info, FetchStatus::Fetched);
                                         }
                                         Err(e) => {
                                             println!("Lyrics fetch/parse error: {}", e); // Log error
                                             let mut current_state = state_clone.lock().unwrap();
-                                            current_state.lyrics = format!("Error fetching/parsing lyrics:\n{}", e); // Show error in GUI
+                                            current_state.set_lyrics(format!("Error fetching/parsing lyrics:\n{}", e)); // Show error in GUI
+                                            current_state.lyrics_missing = true;
                                             current_state.status = "Error".to_string();
+                                            if let Some((delay_secs, max_retries)) = auto_retry
+                                                && fetch_retries.0 < max_retries
+                                            {
+                                                fetch_retries.0 += 1;
+                                                fetch_retries.1 = Some(Instant::now() + Duration::from_secs(delay_secs));
+                                                current_state.status = format!("Error (retry {}/{} in {}s)", fetch_retries.0, max_retries, delay_secs);
+                                            }
+                                            current_state.set_history_status(&info, FetchStatus::Failed);
                                         }
                                      }
                                 }
@@ -138,7 +1179,11 @@ impl LyricsApp {
                                 let mut current_state = state_clone.lock().unwrap();
                                 let is_playing = current_state.current_info.as_ref().map_or(false, |info| info.is_playing);
 
-                                if is_playing && !current_state.status.starts_with("Showing lyrics") && !current_state.status.starts_with("Error") {
+                                if fetching_paused {
+                                     current_state.status = "Fetching paused".to_string();
+                                } else if cache::is_song_skipped(&info.artists, &info.title) {
+                                     current_state.status = SKIPPED_STATUS.to_string();
+                                } else if is_playing && !current_state.status.starts_with("Showing lyrics") && !current_state.status.starts_with("Error") {
                                      current_state.status = "Song unchanged.".to_string();
                                 } else if !is_playing && current_state.current_info.is_some() { // Check if info exists before declaring paused
                                      current_state.status = "Spotify paused.".to_string();
@@ -146,20 +1191,23 @@ impl LyricsApp {
                                 // TODO: Could update a progress bar here
                             }
                         }
-                        Ok(None) => { // Nothing playing according to API
+                        Ok(None) => { // Nothing playing according to API (or an ad, which isn't looked up)
+                            let idle_status = if spotify::ad_playing() { AD_STATUS } else { NOTHING_PLAYING_STATUS };
                             if last_song_title.is_some() {
-                                println!("Spotify stopped or nothing playing.");
+                                println!("{}", idle_status);
                                 last_song_title = None;
                                 let mut current_state = state_clone.lock().unwrap();
                                 current_state.current_info = None;
-                                current_state.lyrics = "".to_string();
-                                current_state.status = "Spotify stopped or nothing playing.".to_string();
+                                current_state.last_url = None;
+                                current_state.set_lyrics(String::new());
+                                current_state.status = idle_status.to_string();
                             } else {
                                  let mut current_state = state_clone.lock().unwrap();
-                                 if current_state.current_info.is_some() || current_state.status != "Spotify stopped or nothing playing." {
+                                 if current_state.current_info.is_some() || current_state.status != idle_status {
                                      current_state.current_info = None;
-                                     current_state.lyrics = "".to_string();
-                                     current_state.status = "Spotify stopped or nothing playing.".to_string();
+                                     current_state.last_url = None;
+                                     current_state.set_lyrics(String::new());
+                                     current_state.status = idle_status.to_string();
                                  }
                             }
                         }
@@ -168,33 +1216,399 @@ impl LyricsApp {
                              last_song_title = None;
                              let mut current_state = state_clone.lock().unwrap();
                              current_state.current_info = None;
-                             current_state.lyrics = "".to_string();
+                             current_state.last_url = None;
+                             current_state.set_lyrics(String::new());
                              current_state.status = format!("Spotify API Error: {}", e);
                         }
                     }
-                }); // End block_on
+                }))); // End block_on
+                first_poll = false;
+                if let Some(ctx) = &repaint_ctx {
+                    ctx.request_repaint();
+                }
+
+                if let Err(payload) = iteration {
+                    let message = payload
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| payload.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "unknown panic".to_string());
+                    let now = Instant::now();
+                    recent_panics.retain(|at| now.duration_since(*at) < PANIC_WINDOW);
+                    recent_panics.push(now);
+                    eprintln!("Poll loop panicked ({} time(s) recently): {}", recent_panics.len(), message);
+
+                    // Locks held during the panic are poisoned; the data is still usable
+                    state_clone.clear_poison();
+                    spotify::clear_poison();
+                    cache::clear_poison();
+                    lyrics::clear_poison();
+                    playlog::clear_poison();
+                    let mut current_state = state_clone.lock().unwrap();
+                    current_state.busy = false;
+                    if recent_panics.len() > MAX_PANIC_RESTARTS {
+                        current_state.status = "Polling stopped after repeated internal errors, please restart".to_string();
+                        break;
+                    }
+                    current_state.status = format!("Recovering from an internal error: {}", message);
+                    drop(current_state);
+                    last_song_title = None; // Look the current song up again from scratch
+                    std::thread::sleep(PANIC_RESTART_DELAY);
+                    continue;
+                }
+
+                // Auto-hide: the UI doesn't update while hidden, so the poll loop shows and hides the window
+                let current_state = state_clone.lock().unwrap();
+                let playing = current_state.current_info.as_ref().is_some_and(|info| info.is_playing);
+                let hide_after = current_state.settings.auto_hide_when_idle.then(|| Duration::from_secs(current_state.settings.auto_hide_after_secs));
+                drop(current_state);
+                if playing {
+                    idle_since = None;
+                } else {
+                    idle_since.get_or_insert_with(Instant::now);
+                }
+                let hide = hide_after.is_some_and(|after| idle_since.is_some_and(|since| since.elapsed() >= after));
+                if hide != window_hidden
+                    && let Some(ctx) = &repaint_ctx
+                {
+                    window_hidden = hide;
+                    println!("{}", if hide { "Nothing playing for a while, hiding the overlay." } else { "Playback resumed, showing the overlay." });
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Visible(!hide));
+                }
 
-                // Poll interval (can be adjusted)
-                std::thread::sleep(Duration::from_secs(3));
+                // Poll interval (can be adjusted), cut short when a re-fetch is requested
+                let poll_jitter = Duration::from_millis(state_clone.lock().unwrap().settings.poll_jitter_ms);
+                let poll_interval = jittered_poll_interval(poll_jitter, RandomState::new().build_hasher().finish());
+                let poll_started = Instant::now();
+                // Monotonic clocks stop while suspended, so the wall clock is what reveals a sleep/wake
+                let poll_started_wall = SystemTime::now();
+                while poll_started.elapsed() < poll_interval {
+                    let current_state = state_clone.lock().unwrap();
+                    let wake_early = current_state.refetch_requested || current_state.reauth_requested || current_state.album_art_requested || current_state.volume_requested.is_some() || current_state.lookup_requested.is_some();
+                    drop(current_state);
+                    if wake_early {
+                        break;
+                    }
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+                let wall_elapsed = SystemTime::now().duration_since(poll_started_wall).unwrap_or_default();
+                woke_from_sleep = wall_elapsed > poll_interval + WAKE_GAP;
+                if woke_from_sleep {
+                    println!("Woke from sleep after {}s, reconnecting to Spotify.", wall_elapsed.as_secs());
+                }
             }
         }); // End background thread spawn
+    }
 
-        Self { state }
+    // Embeds Noto Sans KR (from `<crate>/assets/`) so Korean and other CJK lyrics render
+    #[cfg(feature = "bundled-font")]
+    fn font_definitions() -> FontDefinitions {
+        let mut fonts = FontDefinitions::default();
+        fonts.font_data.insert(
+            "noto_sans_kr".to_owned(),
+            egui::FontData::from_static(include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/NotoSansKR-VariableFont_wght.ttf")))
+        );
+        fonts
+            .families
+            .entry(egui::FontFamily::Proportional)
+            .or_default()
+            .insert(0, "noto_sans_kr".to_owned());
+        fonts
     }
 
-    // Helper to apply transparency based on opacity
-    fn apply_opacity(ctx: &egui::Context, opacity: f32) {
-        let mut visuals = ctx.style().visuals.clone();
-        // Convert f32 (0.0-1.0) to u8 (0-255) for alpha
-        let alpha = (opacity.clamp(0.0, 1.0) * 255.0) as u8;
+    // Built without `bundled-font`: egui's default fonts (no CJK glyphs)
+    #[cfg(not(feature = "bundled-font"))]
+    fn font_definitions() -> FontDefinitions {
+        println!("Built without the bundled font, using egui's default fonts.");
+        FontDefinitions::default()
+    }
 
-        // Set window and panel fill to have the calculated alpha
-        // Create a new Color32 with the desired alpha
-        let base_color = visuals.window_fill; // Get the original color
-        visuals.window_fill = Color32::from_rgba_unmultiplied(base_color.r(), base_color.g(), base_color.b(), alpha);
-        visuals.panel_fill = visuals.window_fill; // Make panel match window background
+    // One line of text that marquee-scrolls horizontally when it's wider than the window; hovering pauses it
+    fn show_ticker(ui: &mut egui::Ui, text: egui::RichText, speed: f32, offset: &mut f32) {
+        let galley = egui::WidgetText::from(text).into_galley(ui, Some(false), f32::INFINITY, egui::TextStyle::Body);
+        let color = ui.visuals().text_color();
+        let available = ui.available_width();
+        let (rect, response) = ui.allocate_exact_size(egui::vec2(available, galley.size().y), egui::Sense::hover());
+        if galley.size().x <= available {
+            *offset = 0.0;
+            ui.painter().galley(rect.left_top(), galley, color);
+            return;
+        }
 
-        // Ensure text and other widgets remain fully opaque regardless of background
+        // Two copies, one cycle apart, so the text wraps around seamlessly
+        let cycle = galley.size().x + TICKER_GAP;
+        if !response.hovered() {
+            *offset = (*offset + speed * ui.input(|i| i.stable_dt)) % cycle;
+            ui.ctx().request_repaint(); // Keep animating
+        }
+        let painter = ui.painter_at(rect);
+        let left = rect.left() - *offset;
+        painter.galley(egui::pos2(left, rect.top()), Arc::clone(&galley), color);
+        painter.galley(egui::pos2(left + cycle, rect.top()), galley, color);
+    }
+
+    // Lyrics one row per line, with LRC tags stripped (optionally shown as a dim [mm:ss] prefix).
+    // The current synced line is highlighted; with `follow` it's scrolled to the configured anchor.
+    // With `pickable`, rows are clickable and the clicked line's index is returned.
+    fn show_lyric_lines(ui: &mut egui::Ui, state: &AppState, follow: bool, pickable: bool) -> Option<usize> {
+        let settings = &state.settings;
+        let font_size = settings.lyrics_font_size(ui.ctx().pixels_per_point());
+        let mut picked = None;
+        let progress = state.interpolated_progress_ms();
+        let current = progress.and_then(|progress| lyrics::current_line_index(&state.lines, progress));
+        let align = match settings.scroll_anchor {
+            ScrollAnchor::Top => egui::Align::Min,
+            ScrollAnchor::Center => egui::Align::Center,
+            ScrollAnchor::Bottom => egui::Align::Max,
+        };
+        ui.spacing_mut().item_spacing.y = 0.0; // Rows read like a single block of text
+        for (index, line) in state.lines.iter().enumerate() {
+            if line.text.trim().is_empty() {
+                if settings.lyrics_spacing == LyricsSpacing::Spaced {
+                    ui.label(egui::RichText::new(" ").size(font_size));
+                }
+                continue;
+            }
+            let rtl = match settings.text_direction {
+                TextDirection::Auto => lyrics::is_predominantly_rtl(&line.text),
+                TextDirection::LeftToRight => false,
+                TextDirection::RightToLeft => true,
+            };
+            let add_line = |ui: &mut egui::Ui| {
+                if settings.show_timestamps
+                    && let Some(time_ms) = line.time_ms
+                {
+                    ui.label(egui::RichText::new(lyrics::format_timestamp(time_ms)).monospace().size(font_size).weak());
+                }
+                let mut text = egui::RichText::new(&line.text).size(font_size);
+                if state.lyrics_stale {
+                    text = text.weak();
+                } else if settings.bold_lyrics {
+                    text = text.strong();
+                }
+                if settings.mark_annotations && line.annotated {
+                    text = text.underline();
+                }
+                let is_current = current == Some(index) && !state.lyrics_stale;
+                // 0.0 to 1.0 as the line becomes current (and back as it stops being it)
+                let emphasis = if settings.line_fade && !settings.high_contrast { // Black text on a half-faded yellow is unreadable
+                    ui.ctx().animate_bool_with_time(egui::Id::new(("lyric_line_fade", index)), is_current, settings.line_fade_ms as f32 / 1000.0)
+                } else if is_current {
+                    1.0
+                } else {
+                    0.0
+                };
+                if is_current && settings.high_contrast {
+                    text = text.color(Color32::BLACK); // White on yellow would be unreadable
+                } else if is_current && emphasis < 1.0 {
+                    let full = if settings.bold_lyrics { ui.visuals().strong_text_color() } else { ui.visuals().text_color() };
+                    text = text.color(full.gamma_multiply(LINE_FADE_START_ALPHA + (1.0 - LINE_FADE_START_ALPHA) * emphasis));
+                }
+                // Karaoke wipe needs the next line's timestamp to know where this one ends
+                let wipe = (is_current && settings.karaoke_wipe)
+                    .then(|| progress.and_then(|progress| lyrics::line_progress(&state.lines, index, progress)))
+                    .flatten();
+                let response = if let Some(fraction) = wipe {
+                    let unsung = if settings.high_contrast { Color32::from_gray(90) } else { ui.visuals().weak_text_color() };
+                    let sense = if pickable { egui::Sense::click() } else { egui::Sense::hover() };
+                    let response = Self::add_wiped_line(ui, text, fraction, rtl, unsung, sense);
+                    if pickable { response.on_hover_cursor(egui::CursorIcon::PointingHand) } else { response }
+                } else if pickable {
+                    ui.add(egui::Label::new(Self::highlight(ui, text, emphasis)).sense(egui::Sense::click())).on_hover_cursor(egui::CursorIcon::PointingHand)
+                } else {
+                    ui.label(Self::highlight(ui, text, emphasis))
+                };
+                if response.clicked() {
+                    picked = Some(index);
+                }
+                if follow && current == Some(index) {
+                    response.scroll_to_me(Some(align));
+                }
+            };
+            if rtl {
+                // Starts at the right edge: timestamp first, then the text to its left
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center).with_main_wrap(true), add_line);
+            } else {
+                ui.horizontal_wrapped(add_line);
+            }
+        }
+        picked
+    }
+
+    // The current line's highlight, at `emphasis` strength while it fades in or out
+    fn highlight(ui: &egui::Ui, text: egui::RichText, emphasis: f32) -> egui::RichText {
+        if emphasis > 0.0 { text.background_color(ui.visuals().selection.bg_fill.gamma_multiply(emphasis)) } else { text }
+    }
+
+    // Manual: remember the size the user gives the window. Fit content: resize the window so the lyrics
+    // area is exactly as tall as its content, within MIN_FIT_HEIGHT and MAX_FIT_HEIGHT.
+    fn apply_window_sizing(&self, ctx: &egui::Context, settings: &mut Settings) {
+        let (inner, monitor) = ctx.input(|i| (i.viewport().inner_rect, i.viewport().monitor_size));
+        let Some(inner) = inner else {
+            return;
+        };
+        match settings.window_sizing {
+            WindowSizing::Manual => {
+                let size = [inner.width().round(), inner.height().round()];
+                if settings.window_size != Some(size) {
+                    settings.window_size = Some(size);
+                }
+            }
+            WindowSizing::FitContent => {
+                let max_height = monitor.map_or(MAX_FIT_HEIGHT, |monitor| (monitor.y * 0.9).min(MAX_FIT_HEIGHT));
+                let wanted = (inner.height() - self.lyrics_slack).clamp(MIN_FIT_HEIGHT, max_height.max(MIN_FIT_HEIGHT));
+                // A point of tolerance, so rounding doesn't keep the window resizing back and forth
+                if (wanted - inner.height()).abs() > 1.0 {
+                    ctx.send_viewport_cmd(egui::ViewportCommand::InnerSize(egui::vec2(inner.width(), wanted)));
+                }
+            }
+        }
+    }
+
+    // Current synced line as a karaoke wipe: the line in the `unsung` color, with the first `fraction` of its
+    // glyph width (row by row for wrapped lines) painted over in the normal text color. RTL lines wipe from the right.
+    fn add_wiped_line(ui: &mut egui::Ui, text: egui::RichText, fraction: f32, rtl: bool, unsung: Color32, sense: egui::Sense) -> egui::Response {
+        let galley = egui::WidgetText::from(text).into_galley(ui, Some(true), ui.available_width(), egui::TextStyle::Body);
+        let (rect, response) = ui.allocate_exact_size(galley.size(), sense);
+        if ui.is_rect_visible(rect) {
+            let painter = ui.painter();
+            painter.rect_filled(rect, 0.0, ui.visuals().selection.bg_fill);
+            painter.galley_with_override_text_color(rect.min, Arc::clone(&galley), unsung);
+            let mut remaining = fraction * galley.rows.iter().map(|row| row.rect.width()).sum::<f32>();
+            for row in &galley.rows {
+                if remaining <= 0.0 {
+                    break;
+                }
+                let row_rect = row.rect.translate(rect.min.to_vec2());
+                let width = remaining.min(row_rect.width());
+                remaining -= width;
+                let sung = if rtl {
+                    egui::Rect::from_min_max(egui::pos2(row_rect.max.x - width, row_rect.min.y), row_rect.max)
+                } else {
+                    egui::Rect::from_min_size(row_rect.min, egui::vec2(width, row_rect.height()))
+                };
+                painter.with_clip_rect(sung).galley(rect.min, Arc::clone(&galley), ui.visuals().text_color());
+            }
+        }
+        response
+    }
+
+    // Card for "share image": cover, song and the picked line on an opaque background. Returns its screen rect.
+    fn show_share_card(ui: &mut egui::Ui, state: &AppState, album_art: Option<&egui::TextureHandle>, line: &str) -> egui::Rect {
+        egui::Frame::none()
+            .fill(ui.visuals().extreme_bg_color.to_opaque()) // Transparent pixels would show whatever is behind the window
+            .inner_margin(egui::Margin::same(16.0))
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    if let Some(texture) = album_art {
+                        ui.add(egui::Image::new(texture).fit_to_exact_size(egui::vec2(96.0, 96.0)));
+                    }
+                    if let Some(info) = &state.current_info {
+                        ui.vertical(|ui| {
+                            ui.heading(&info.title);
+                            ui.label(egui::RichText::new(state.settings.display_artists(&info.artists)).weak());
+                        });
+                    }
+                });
+                ui.add_space(12.0);
+                ui.label(egui::RichText::new(line).size(state.settings.lyrics_font_size(ui.ctx().pixels_per_point()) * 1.5).strong());
+            })
+            .response
+            .rect
+    }
+
+    // Placeholder for the lyrics area when there's nothing to show, so the overlay looks intentional
+    // `album_art` is the current song's cover texture, if loaded
+    fn show_placeholder(ui: &mut egui::Ui, state: &AppState, album_art: Option<&egui::TextureHandle>) {
+        ui.vertical_centered(|ui| {
+            match (state.settings.placeholder, album_art) {
+                (PlaceholderMode::AlbumArt, Some(texture)) => {
+                    // Square cover, as large as the width allows
+                    let side = ui.available_width();
+                    ui.add(egui::Image::new(texture).fit_to_exact_size(egui::vec2(side, side)));
+                }
+                _ => {
+                    ui.label(egui::RichText::new(&state.settings.placeholder_text).monospace().size(state.settings.lyrics_font_size(ui.ctx().pixels_per_point())));
+                }
+            }
+            // Keep the reason visible, just less prominent
+            if state.lyrics_missing {
+                ui.label(egui::RichText::new(&state.lyrics).small().weak());
+            }
+        });
+    }
+
+    // Fullscreen party view: the current synced line large and centered, its neighbors dimmed, on a solid background
+    fn show_presentation(ctx: &egui::Context, state: &AppState) {
+        let background = state.settings.lyrics_background.map_or(Color32::BLACK, |[r, g, b, _]| Color32::from_rgb(r, g, b));
+        let size = state.settings.lyrics_font_size(ctx.pixels_per_point());
+        egui::CentralPanel::default().frame(egui::Frame::none().fill(background).inner_margin(egui::Margin::same(32.0))).show(ctx, |ui| {
+            let current = state.interpolated_progress_ms().and_then(|progress| lyrics::current_line_index(&state.lines, progress));
+            ui.vertical_centered(|ui| {
+                let Some(current) = current else {
+                    // Plain lyrics, or before the first synced line
+                    ui.add_space((ui.available_height() / 2.0 - size).max(0.0));
+                    if let Some(info) = &state.current_info {
+                        ui.label(egui::RichText::new(format!("{} - {}", state.settings.display_artists(&info.artists), info.title)).size(size * 1.5).color(Color32::GRAY));
+                    }
+                    return;
+                };
+                let non_blank = |line: &&LyricLine| !line.text.trim().is_empty();
+                let mut before: Vec<&LyricLine> = state.lines[..current].iter().rev().filter(non_blank).take(PRESENTATION_CONTEXT_LINES).collect();
+                before.reverse();
+                let after: Vec<&LyricLine> = state.lines[current + 1..].iter().filter(non_blank).take(PRESENTATION_CONTEXT_LINES).collect();
+
+                let dimmed = |text: &str| egui::RichText::new(text).size(size * 1.2).color(Color32::from_gray(110));
+                // Roughly center the current line vertically
+                let block_height = size * (PRESENTATION_SCALE + 1.5 * (before.len() + after.len()) as f32);
+                ui.add_space(((ui.available_height() - block_height) / 2.0).max(0.0));
+                for line in before {
+                    ui.label(dimmed(&line.text));
+                }
+                let mut text = egui::RichText::new(&state.lines[current].text).size(size * PRESENTATION_SCALE).color(Color32::WHITE);
+                if state.settings.bold_lyrics {
+                    text = text.strong();
+                }
+                ui.label(text);
+                for line in after {
+                    ui.label(dimmed(&line.text));
+                }
+            });
+        });
+    }
+
+    // Pictures folder (or home) with a timestamped name, so shares never overwrite each other
+    fn share_image_path() -> std::path::PathBuf {
+        let dir = dirs::picture_dir().or_else(dirs::home_dir).unwrap_or_else(|| std::path::PathBuf::from("."));
+        dir.join(format!("lyricrs-share-{}.png", chrono::Local::now().format("%Y%m%d-%H%M%S")))
+    }
+
+    // Solid black with white text and a yellow selection, for the high-contrast setting
+    fn high_contrast_visuals() -> egui::Visuals {
+        let mut visuals = egui::Visuals::dark();
+        visuals.window_fill = Color32::BLACK;
+        visuals.panel_fill = Color32::BLACK;
+        visuals.extreme_bg_color = Color32::BLACK;
+        visuals.widgets.noninteractive.fg_stroke.color = Color32::WHITE; // Label text
+        visuals.widgets.inactive.fg_stroke.color = Color32::WHITE;
+        visuals.selection.bg_fill = Color32::YELLOW; // Synced line highlight
+        visuals.selection.stroke.color = Color32::BLACK;
+        visuals
+    }
+
+    // Helper to apply transparency based on opacity
+    fn apply_opacity(ctx: &egui::Context, opacity: f32) {
+        let mut visuals = ctx.style().visuals.clone();
+        // Convert f32 (0.0-1.0) to u8 (0-255) for alpha
+        let alpha = (opacity.clamp(0.0, 1.0) * 255.0) as u8;
+
+        // Set window and panel fill to have the calculated alpha
+        // Create a new Color32 with the desired alpha
+        let base_color = visuals.window_fill; // Get the original color
+        visuals.window_fill = Color32::from_rgba_unmultiplied(base_color.r(), base_color.g(), base_color.b(), alpha);
+        visuals.panel_fill = visuals.window_fill; // Make panel match window background
+
+        // Ensure text and other widgets remain fully opaque regardless of background
         visuals.override_text_color = None; // Use default text color calculation
         let opaque_color = |c: Color32| Color32::from_rgba_unmultiplied(c.r(), c.g(), c.b(), 255);
         visuals.widgets.noninteractive.fg_stroke.color = opaque_color(visuals.widgets.noninteractive.fg_stroke.color);
@@ -208,36 +1622,819 @@ impl LyricsApp {
 
         ctx.set_visuals(visuals);
     }
+
+    // Filter field plus the matching commands; arrows move, Enter (or a click) runs, Esc closes
+    fn show_command_palette(ctx: &egui::Context, palette: &mut Option<CommandPalette>, presenting: &mut bool, state: &mut AppState) {
+        let Some(open) = palette.as_mut() else {
+            return;
+        };
+        // Taken before the text field sees them, so they steer the list instead of the cursor
+        let (up, down, enter, escape) = ctx.input_mut(|i| {
+            (
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Enter),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Escape),
+            )
+        });
+        let matches = palette_matches(&open.query, state);
+        if down {
+            open.selected = (open.selected + 1).min(matches.len().saturating_sub(1));
+        }
+        if up {
+            open.selected = open.selected.saturating_sub(1);
+        }
+        open.selected = open.selected.min(matches.len().saturating_sub(1));
+
+        let mut chosen = enter.then(|| matches.get(open.selected).copied()).flatten();
+        egui::Window::new("Command palette")
+            .title_bar(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_TOP, [0.0, 40.0])
+            .show(ctx, |ui| {
+                let input = ui.add(egui::TextEdit::singleline(&mut open.query).hint_text("Type a command…").desired_width(280.0));
+                input.request_focus();
+                if input.changed() {
+                    open.selected = 0;
+                }
+                if matches.is_empty() {
+                    ui.label(egui::RichText::new("No matching commands").weak());
+                }
+                for (index, command) in matches.iter().enumerate() {
+                    if ui.selectable_label(index == open.selected, command.label()).clicked() {
+                        chosen = Some(*command);
+                    }
+                }
+            });
+
+        if escape || chosen.is_some() {
+            *palette = None;
+        }
+        if let Some(command) = chosen {
+            Self::run_palette_command(ctx, command, presenting, state);
+        }
+    }
+
+    fn run_palette_command(ctx: &egui::Context, command: PaletteCommand, presenting: &mut bool, state: &mut AppState) {
+        match command {
+            PaletteCommand::Refetch => state.refetch_requested = true,
+            PaletteCommand::CopyLyrics => {
+                ctx.copy_text(state.lyrics.clone());
+                state.status = "Copied the lyrics".to_string();
+            }
+            PaletteCommand::CopyUrl => {
+                if let Some(url) = state.last_url.clone() {
+                    ctx.copy_text(url);
+                    state.status = "Copied the Genius URL".to_string();
+                }
+            }
+            PaletteCommand::EditLyrics => state.start_editing_lyrics(),
+            PaletteCommand::TapSync => state.start_tap_sync(),
+            PaletteCommand::TogglePin => {
+                if let Some(info) = state.current_info.clone() {
+                    let pinned = cache::is_song_pinned(&info.artists, &info.title);
+                    cache::set_song_pinned(&info.artists, &info.title, !pinned);
+                }
+            }
+            PaletteCommand::SkipSong => state.skip_current_song(),
+            PaletteCommand::ToggleAutoScroll => state.auto_scroll = !state.auto_scroll,
+            PaletteCommand::TogglePauseFetching => state.settings.fetching_paused = !state.settings.fetching_paused,
+            PaletteCommand::ToggleHighContrast => {
+                if state.settings.high_contrast {
+                    state.settings.high_contrast = false;
+                } else {
+                    state.settings.apply_high_contrast_preset();
+                }
+            }
+            PaletteCommand::Present => {
+                *presenting = true;
+                ctx.send_viewport_cmd(egui::ViewportCommand::Fullscreen(true));
+            }
+            PaletteCommand::ExportCache => {
+                let path = std::path::PathBuf::from(&state.archive_path);
+                state.status = match cache::export_archive(&path) {
+                    Ok(count) => format!("Exported {} cached songs to {}", count, path.display()),
+                    Err(e) => format!("Cache export failed: {}", e),
+                };
+            }
+            PaletteCommand::OpenCacheFolder => {
+                if let Some(dir) = cache::cache_dir() {
+                    ctx.open_url(egui::OpenUrl::new_tab(format!("file://{}", dir.display())));
+                }
+            }
+        }
+    }
 }
 
 impl eframe::App for LyricsApp {
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        flush_play_log(&self.state);
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        ctx.request_repaint_after(Duration::from_millis(500)); // Keep requesting repaints
+        // A panic in the poll loop poisons the state; keep drawing what it left rather than crash the window
+        let mut current_state = self.state.lock().unwrap_or_else(PoisonError::into_inner); // Lock state for read/write
+
+        // Repaint only when the screen can change: the poll loop wakes us after each poll, synced lyrics
+        // at the next line, plain auto-scroll periodically; input and animations are handled by egui
+        let is_playing = current_state.current_info.as_ref().is_some_and(|info| info.is_playing);
+        let plain_auto_scroll = current_state.auto_scroll && !current_state.auto_scroll_paused && !current_state.lyrics.is_empty()
+            && current_state.lines.iter().all(|line| line.time_ms.is_none());
+        let wiping = current_state.settings.karaoke_wipe && current_state.current_line().is_some();
+        let repaint_after = if wiping && is_playing {
+            WIPE_REPAINT_INTERVAL
+        } else if plain_auto_scroll && is_playing {
+            AUTO_SCROLL_REPAINT_INTERVAL
+        } else {
+            current_state.next_line_change_in().map_or(IDLE_REPAINT_INTERVAL, |next| next + Duration::from_millis(10))
+        };
+        ctx.request_repaint_after(repaint_after.min(IDLE_REPAINT_INTERVAL));
+
+        // F5 forces a re-fetch of the current song; key-repeat events are ignored so holding it doesn't spam
+        let refresh_pressed = ctx.input(|i| {
+            i.events.iter().any(|event| matches!(event, egui::Event::Key { key: egui::Key::F5, pressed: true, repeat: false, .. }))
+        });
+        if refresh_pressed && current_state.current_info.is_some() {
+            current_state.refetch_requested = true;
+        }
+
+        // Presentation mode hides all panels and controls
+        let (toggle_presentation, escape) = ctx.input(|i| (i.key_pressed(egui::Key::F11), i.key_pressed(egui::Key::Escape)));
+        if toggle_presentation || (escape && self.presenting) {
+            self.presenting = toggle_presentation && !self.presenting;
+            ctx.send_viewport_cmd(egui::ViewportCommand::Fullscreen(self.presenting));
+        }
+        if self.presenting {
+            Self::show_presentation(ctx, &current_state);
+            return;
+        }
+
+        // Ctrl+P (Cmd+P on macOS) opens or closes the command palette
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::P)) {
+            self.palette = if self.palette.is_some() { None } else { Some(CommandPalette::default()) };
+        }
+        if self.palette.is_some() {
+            Self::show_command_palette(ctx, &mut self.palette, &mut self.presenting, &mut current_state);
+        }
+
+        // Transient notice (e.g. "Lyrics updated"), fading out over its last second
+        if let Some((message, raised)) = current_state.toast.clone() {
+            let remaining = TOAST_DURATION.saturating_sub(raised.elapsed());
+            if remaining.is_zero() {
+                current_state.toast = None;
+            } else {
+                egui::Area::new(egui::Id::new("toast"))
+                    .anchor(egui::Align2::RIGHT_BOTTOM, [-12.0, -40.0])
+                    .interactable(false)
+                    .show(ctx, |ui| {
+                        ui.set_opacity(remaining.as_secs_f32().min(1.0));
+                        egui::Frame::popup(ui.style()).show(ui, |ui| ui.label(message));
+                    });
+                ctx.request_repaint_after(Duration::from_millis(50));
+            }
+        }
 
-        let mut current_state = self.state.lock().unwrap(); // Lock state for read/write
+        // The share card was screenshotted on an earlier frame: crop it out and save it
+        let screenshot = ctx.input(|i| {
+            i.events.iter().find_map(|event| match event {
+                egui::Event::Screenshot { image, .. } => Some(Arc::clone(image)),
+                _ => None,
+            })
+        });
+        if let (Some(image), ShareState::Capturing { card_rect: Some(rect), .. }) = (screenshot, &self.share) {
+            // region() panics on a rect outside the image, e.g. a card taller than the window
+            let ppp = ctx.pixels_per_point();
+            let window = egui::Rect::from_min_size(egui::Pos2::ZERO, egui::vec2(image.size[0] as f32, image.size[1] as f32) / ppp);
+            let visible = rect.intersect(window);
+            current_state.status = if !visible.is_positive() {
+                "Could not save the share image: the card is not on screen".to_string()
+            } else if visible != *rect {
+                "Could not save the share image: the card does not fit in the window. Make the window larger or the font smaller".to_string()
+            } else {
+                let card = image.region(&visible, Some(ppp));
+                let path = Self::share_image_path();
+                match artwork::save_png(&card, &path) {
+                    Ok(()) => {
+                        println!("Saved share image to {}", path.display());
+                        format!("Saved share image to {}", path.display())
+                    }
+                    Err(e) => e,
+                }
+            };
+            self.share = ShareState::Idle;
+        }
+
+        // --- Opacity ---
+        // Active level while focused or hovered; optionally fade to the idle level otherwise
+        let active = ctx.input(|i| i.focused || i.pointer.hover_pos().is_some());
+        let high_contrast = current_state.settings.high_contrast;
+        if high_contrast != self.applied_high_contrast {
+            ctx.set_visuals(if high_contrast { Self::high_contrast_visuals() } else { self.base_visuals.clone() });
+            Self::apply_opacity(ctx, self.applied_opacity);
+            self.applied_high_contrast = high_contrast;
+        }
+        let target_opacity = if high_contrast {
+            1.0 // Transparency hurts legibility
+        } else if current_state.settings.fade_when_idle && !active {
+            current_state.settings.idle_opacity
+        } else {
+            current_state.settings.opacity
+        };
+        let opacity = ctx.animate_value_with_time(egui::Id::new("window_opacity"), target_opacity, 0.3);
+        if opacity != self.applied_opacity {
+            Self::apply_opacity(ctx, opacity);
+            self.applied_opacity = opacity;
+        }
 
         // --- Opacity Slider ---
         // Place it before the main panel to potentially put it in a top bar later
-        let mut new_opacity = current_state.opacity; // Copy value for slider
         egui::TopBottomPanel::top("config_panel").show(ctx, |ui| {
+             ui.horizontal(|ui| {
+                let mut high_contrast = current_state.settings.high_contrast;
+                if ui.checkbox(&mut high_contrast, "High contrast")
+                    .on_hover_text("Solid black background, large bold white text and a yellow highlight for the current line")
+                    .changed()
+                {
+                    if high_contrast {
+                        current_state.settings.apply_high_contrast_preset();
+                    } else {
+                        current_state.settings.high_contrast = false;
+                    }
+                }
+             });
              ui.horizontal(|ui| {
                 ui.label("Opacity:");
-                // Use a slider to change the opacity value
-                if ui.add(egui::Slider::new(&mut new_opacity, 0.0..=1.0).step_by(0.05)).changed() {
-                    current_state.opacity = new_opacity; // Update state if slider moved
-                    // Apply the new opacity immediately
-                    Self::apply_opacity(ctx, new_opacity);
+                let high_contrast = current_state.settings.high_contrast;
+                ui.add_enabled(!high_contrast, egui::Slider::new(&mut current_state.settings.opacity, 0.0..=1.0).step_by(0.05))
+                    .on_disabled_hover_text("High contrast keeps the window opaque");
+                ui.checkbox(&mut current_state.settings.fade_when_idle, "Fade when idle");
+                if current_state.settings.fade_when_idle {
+                    ui.add(egui::Slider::new(&mut current_state.settings.idle_opacity, 0.0..=1.0).step_by(0.05).text("idle"));
+                }
+             });
+             ui.horizontal(|ui| {
+                ui.checkbox(&mut current_state.settings.auto_hide_when_idle, "Hide when nothing plays")
+                    .on_hover_text("Hides the window after the timeout with nothing playing; it comes back with the next track");
+                if current_state.settings.auto_hide_when_idle {
+                    ui.label("after");
+                    ui.add(egui::DragValue::new(&mut current_state.settings.auto_hide_after_secs).clamp_range(10..=3600).suffix(" s"));
+                }
+             });
+             ui.horizontal(|ui| {
+                ui.checkbox(&mut current_state.settings.now_playing_osd, "Now-playing popup")
+                    .on_hover_text("Briefly show the cover, title and artists when the song changes");
+                if current_state.settings.now_playing_osd {
+                    ui.label("for");
+                    ui.add(egui::DragValue::new(&mut current_state.settings.now_playing_osd_secs).clamp_range(1..=30).suffix(" s"));
+                }
+             });
+             ui.horizontal(|ui| {
+                ui.checkbox(&mut current_state.auto_scroll, "Auto-scroll");
+                if current_state.auto_scroll {
+                    ui.add(egui::Slider::new(&mut current_state.auto_scroll_speed, 0.5..=2.0).step_by(0.1).text("speed"));
+                    if current_state.auto_scroll_paused && ui.button("Resume").clicked() {
+                        current_state.auto_scroll_paused = false;
+                    }
+                }
+                if current_state.current_line().is_some() && ui.button("Re-center").on_hover_text("Scroll to the current line").clicked() {
+                    current_state.recenter_requested = true;
+                }
+             });
+             ui.horizontal(|ui| {
+                ui.label("Current line at:");
+                ui.radio_value(&mut current_state.settings.scroll_anchor, ScrollAnchor::Top, "Top");
+                ui.radio_value(&mut current_state.settings.scroll_anchor, ScrollAnchor::Center, "Center");
+                ui.radio_value(&mut current_state.settings.scroll_anchor, ScrollAnchor::Bottom, "Bottom");
+             });
+             ui.horizontal(|ui| {
+                let label = format!("Local server ({})", server::SERVER_ADDR);
+                if ui.checkbox(&mut current_state.server_enabled, label).changed() {
+                    if current_state.server_enabled {
+                        match server::start(Arc::clone(&self.state)) {
+                            Ok(handle) => self.server = Some(handle),
+                            Err(e) => {
+                                eprintln!("Failed to start local server: {}", e);
+                                current_state.server_enabled = false;
+                                current_state.status = format!("Local server error: {}", e);
+                            }
+                        }
+                    } else {
+                        self.server = None; // Dropping the handle stops the server
+                    }
+                }
+             });
+             ui.horizontal(|ui| {
+                let toggled = ui.checkbox(&mut current_state.settings.line_output, "Write current line to")
+                    .on_hover_text("For Stream Deck plugins and other tools that read a text file or named pipe; a file is replaced on every line change")
+                    .changed();
+                let path_edit = ui.add(egui::TextEdit::singleline(&mut current_state.settings.line_output_path).hint_text("current_line.txt in the config folder"));
+                // Restart the writer when it's switched on or its target changes
+                if toggled || path_edit.lost_focus() {
+                    self.line_output = current_state.settings.line_output
+                        .then(|| current_state.settings.line_output_path())
+                        .flatten()
+                        .map(|path| server::start_line_output(Arc::clone(&self.state), path));
+                }
+             });
+             ui.horizontal(|ui| {
+                if ui.checkbox(&mut current_state.settings.slug_all_artists, "Include all artists in lyrics lookup").changed() {
+                    current_state.reset_slug_artist_selection(); // Takes effect on the next re-fetch, not only the next song
+                }
+                ui.label("(at most");
+                ui.add(egui::DragValue::new(&mut current_state.settings.max_slug_artists).clamp_range(1..=10))
+                    .on_hover_text("Genius page names rarely list more artists; if the page isn't found, the primary artist alone is tried");
+                ui.label(")");
+             });
+             ui.horizontal(|ui| {
+                ui.checkbox(&mut current_state.settings.refresh_stale, "Refresh cached lyrics older than");
+                ui.add_enabled(current_state.settings.refresh_stale, egui::DragValue::new(&mut current_state.settings.stale_after_days).clamp_range(1..=365).suffix(" days"));
+             });
+             ui.checkbox(&mut current_state.settings.keep_lyrics_on_change, "Keep previous lyrics until new ones load");
+             ui.checkbox(&mut current_state.settings.reset_on_repeat, "Jump to the top when a song repeats");
+             ui.horizontal(|ui| {
+                ui.label("Give up after");
+                ui.add(egui::DragValue::new(&mut current_state.settings.fetch_deadline_secs).clamp_range(1..=60).suffix(" s"));
+                ui.label("or");
+                ui.add(egui::DragValue::new(&mut current_state.settings.max_fetch_attempts).clamp_range(1..=10).suffix(" attempts"));
+             });
+             ui.horizontal(|ui| {
+                ui.label("Min. match confidence:");
+                ui.add(egui::Slider::new(&mut current_state.settings.min_match_confidence, 0.0..=1.0).step_by(0.05))
+                    .on_hover_text("Search results that match the song's title/artist less closely are ignored (0 accepts anything)");
+             });
+             ui.horizontal(|ui| {
+                ui.label("Preferred language:");
+                ui.add(egui::TextEdit::singleline(&mut current_state.settings.preferred_lyrics_language).desired_width(60.0).hint_text("any"))
+                    .on_hover_text("Language code like \"en\" or \"ko\". Where a source offers several versions (originals, translations, romanizations), the one in this language is used; otherwise whatever is available");
+             });
+             ui.checkbox(&mut current_state.settings.retry_with_featured_artists, "Retry with featured artists from the title")
+                 .on_hover_text("If \"Song (feat. Y)\" isn't found, look it up again with Y added to the artists");
+             ui.checkbox(&mut current_state.settings.best_match, "Best match (prefer the most complete lyrics)")
+                 .on_hover_text("Compares the first couple of results instead of taking the first one; usually finds the full version over a clean/radio edit, but fetches take longer");
+             ui.horizontal(|ui| {
+                ui.checkbox(&mut current_state.settings.auto_retry_failed, "Retry failed fetches after")
+                    .on_hover_text("While the same song keeps playing, e.g. after a network blip or rate limit");
+                let enabled = current_state.settings.auto_retry_failed;
+                ui.add_enabled(enabled, egui::DragValue::new(&mut current_state.settings.auto_retry_delay_secs).clamp_range(5..=600).suffix(" s"));
+                ui.label("up to");
+                ui.add_enabled(enabled, egui::DragValue::new(&mut current_state.settings.auto_retry_max).clamp_range(1..=10).suffix(" times"));
+             });
+             ui.horizontal(|ui| {
+                ui.label("Poll jitter: ±");
+                ui.add(egui::DragValue::new(&mut current_state.settings.poll_jitter_ms).clamp_range(0..=1500).speed(10).suffix(" ms"))
+                    .on_hover_text("Randomly shifts each Spotify poll a little, so several overlays don't poll at the same moment");
+             });
+             ui.horizontal(|ui| {
+                let log_path = playlog::log_path(current_state.settings.play_log_format)
+                    .map_or("the config folder".to_string(), |path| path.display().to_string());
+                if ui.checkbox(&mut current_state.settings.play_log, "Keep a listening log")
+                    .on_hover_text(format!("Appends each detected song (time, artists, title, lyrics found, source) to {}", log_path))
+                    .changed()
+                    && current_state.settings.play_log
+                {
+                    playlog::discard_pending(); // Songs from before the opt-in stay out of the file
+                }
+                let enabled = current_state.settings.play_log;
+                ui.add_enabled_ui(enabled, |ui| {
+                    ui.radio_value(&mut current_state.settings.play_log_format, PlayLogFormat::Jsonl, "JSONL");
+                    ui.radio_value(&mut current_state.settings.play_log_format, PlayLogFormat::Csv, "CSV");
+                });
+             });
+             ui.checkbox(&mut current_state.settings.fetching_paused, "Pause lyrics fetching")
+                .on_hover_text("Keeps showing what's playing, but skips the lyrics cache and all lyrics requests");
+             ui.checkbox(&mut current_state.settings.startup_poll, "Show the current song on launch")
+                .on_hover_text("Checks Spotify once before the window opens (applies on next start)");
+             ui.horizontal(|ui| {
+                ui.label("Font size:");
+                ui.add(egui::Slider::new(&mut current_state.settings.font_size, MIN_FONT_SIZE..=MAX_FONT_SIZE).step_by(1.0))
+                    .on_hover_text("Ctrl+scroll over the lyrics also zooms");
+                ui.radio_value(&mut current_state.settings.font_units, FontUnits::Logical, "pt")
+                    .on_hover_text("Scale with the display (the same apparent size on every monitor)");
+                ui.radio_value(&mut current_state.settings.font_units, FontUnits::Physical, "px")
+                    .on_hover_text("Exact screen pixels, regardless of the display's scale factor");
+                ui.label("Stanzas:");
+                ui.radio_value(&mut current_state.settings.lyrics_spacing, LyricsSpacing::Spaced, "Spaced");
+                ui.radio_value(&mut current_state.settings.lyrics_spacing, LyricsSpacing::Compact, "Compact");
+                ui.checkbox(&mut current_state.settings.bold_lyrics, "Bold")
+                    .on_hover_text("Stronger, brighter lyrics text for busy backgrounds");
+                ui.checkbox(&mut current_state.settings.mark_annotations, "Annotations")
+                    .on_hover_text("Underline lines that are annotated on Genius (for lyrics fetched with this on)");
+                ui.checkbox(&mut current_state.settings.show_timestamps, "Timestamps")
+                    .on_hover_text("Show each synced line's start time");
+                ui.checkbox(&mut current_state.settings.karaoke_wipe, "Karaoke wipe")
+                    .on_hover_text("Fill in the current synced line as it's sung");
+                ui.checkbox(&mut current_state.settings.line_fade, "Fade")
+                    .on_hover_text("Fade synced lines in (and their highlight in and out) instead of switching instantly");
+                if current_state.settings.line_fade {
+                    ui.add(egui::DragValue::new(&mut current_state.settings.line_fade_ms).clamp_range(50..=1000).speed(10).suffix(" ms"));
+                }
+             });
+             ui.horizontal(|ui| {
+                ui.label("Direction:");
+                ui.radio_value(&mut current_state.settings.text_direction, TextDirection::Auto, "Auto")
+                    .on_hover_text("Right-align lines written mostly in Arabic, Hebrew and other RTL scripts");
+                ui.radio_value(&mut current_state.settings.text_direction, TextDirection::LeftToRight, "Left to right");
+                ui.radio_value(&mut current_state.settings.text_direction, TextDirection::RightToLeft, "Right to left");
+             });
+             ui.horizontal(|ui| {
+                ui.label("Lyrics:");
+                let mode = &mut current_state.settings.lyrics_mode;
+                let changed = ui.radio_value(mode, LyricsMode::Auto, "Auto").on_hover_text("Synced when the lyrics have timestamps, plain otherwise").changed()
+                    | ui.radio_value(mode, LyricsMode::Synced, "Synced").on_hover_text("Prefer synced lyrics and say so when a song only has plain ones").changed()
+                    | ui.radio_value(mode, LyricsMode::Plain, "Plain").on_hover_text("Ignore timestamps: no highlighting or following, just text to read").changed();
+                if changed {
+                    current_state.reformat_lyrics();
+                }
+             });
+             ui.horizontal(|ui| {
+                ui.label("While paused:");
+                ui.radio_value(&mut current_state.settings.paused_lyrics, PausedLyrics::Keep, "Keep");
+                ui.radio_value(&mut current_state.settings.paused_lyrics, PausedLyrics::Dim, "Dim");
+                ui.radio_value(&mut current_state.settings.paused_lyrics, PausedLyrics::Hide, "Hide")
+                    .on_hover_text("Show only \"Paused\" until playback resumes");
+             });
+             ui.horizontal(|ui| {
+                ui.label("Tidy up:");
+                let format = &mut current_state.settings.lyrics_format;
+                let changed = ui.checkbox(&mut format.normalize_spaces, "Spaces").on_hover_text("Turn odd or repeated spaces into a single space").changed()
+                    | ui.checkbox(&mut format.trim_lines, "Trailing spaces").on_hover_text("Remove whitespace at the end of each line").changed()
+                    | ui.checkbox(&mut format.capitalize_lines, "Capitalize lines").on_hover_text("Uppercase each line's first letter (words like \"iPhone\" are left alone)").changed()
+                    | ui.checkbox(&mut format.collapse_repeats, "Repeats").on_hover_text("Show a line repeated back to back once, marked \"×N\"").changed();
+                if changed {
+                    current_state.reformat_lyrics();
+                }
+             });
+             ui.horizontal(|ui| {
+                ui.label("Window size:");
+                ui.radio_value(&mut current_state.settings.window_sizing, WindowSizing::Manual, "Manual")
+                    .on_hover_text("Resize the window yourself; the size is restored on the next start");
+                ui.radio_value(&mut current_state.settings.window_sizing, WindowSizing::FitContent, "Fit content")
+                    .on_hover_text("Grow and shrink the window height with the lyrics, e.g. for a single-line strip");
+             });
+             ui.horizontal(|ui| {
+                let mut limit_width = current_state.settings.max_text_width.is_some();
+                if ui.checkbox(&mut limit_width, "Max text width").changed() {
+                    current_state.settings.max_text_width = limit_width.then_some(480.0);
+                }
+                if let Some(width) = current_state.settings.max_text_width.as_mut() {
+                    ui.add(egui::Slider::new(width, 200.0..=1600.0).step_by(20.0).suffix(" pt"));
+                }
+             });
+             ui.horizontal(|ui| {
+                ui.label("Artist separator:");
+                ui.add(egui::TextEdit::singleline(&mut current_state.settings.artist_separator).desired_width(60.0))
+                    .on_hover_text("Only changes how artists are displayed");
+                for preset in [", ", " & ", " feat. "] {
+                    if ui.small_button(format!("\"{}\"", preset.trim())).clicked() {
+                        current_state.settings.artist_separator = preset.to_string();
+                    }
+                }
+             });
+             ui.horizontal(|ui| {
+                ui.checkbox(&mut current_state.settings.single_line_mode, "Single line")
+                    .on_hover_text("Show only the current synced line; long lines scroll (hover to pause)");
+                if ui.button("Present").on_hover_text("Fullscreen lyrics only (F11, Esc to leave)").clicked() {
+                    self.presenting = true;
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Fullscreen(true));
+                }
+                if current_state.settings.single_line_mode {
+                    ui.add(egui::Slider::new(&mut current_state.settings.ticker_speed, 10.0..=200.0).step_by(5.0).text("ticker speed"));
+                }
+             });
+             ui.horizontal(|ui| {
+                ui.label("Padding:");
+                ui.add(egui::Slider::new(&mut current_state.settings.lyrics_padding, 0.0..=48.0).step_by(1.0));
+                let mut use_background = current_state.settings.lyrics_background.is_some();
+                if ui.checkbox(&mut use_background, "Lyrics background").changed() {
+                    current_state.settings.lyrics_background = use_background.then_some([0, 0, 0, 160]);
+                }
+                if let Some([r, g, b, a]) = current_state.settings.lyrics_background {
+                    let mut color = Color32::from_rgba_unmultiplied(r, g, b, a);
+                    if ui.color_edit_button_srgba(&mut color).changed() {
+                        current_state.settings.lyrics_background = Some(color.to_srgba_unmultiplied());
+                    }
+                }
+             });
+             ui.horizontal(|ui| {
+                ui.label("Cache:");
+                let previous_mode = current_state.settings.cache_mode;
+                ui.radio_value(&mut current_state.settings.cache_mode, cache::CacheMode::Disk, "On disk");
+                ui.radio_value(&mut current_state.settings.cache_mode, cache::CacheMode::Memory, "Memory only (this session)");
+                if current_state.settings.cache_mode != previous_mode
+                    && let Err(e) = cache::init_cache(current_state.settings.cache_mode)
+                {
+                    eprintln!("Failed to switch lyrics cache: {}", e);
+                    current_state.cache_notice = Some(format!("Lyrics cache unavailable ({})", e));
+                }
+             });
+             if current_state.settings.cache_mode == cache::CacheMode::Disk {
+                 ui.horizontal(|ui| {
+                    ui.label("Cache file names:");
+                    let previous_names = current_state.settings.cache_file_names;
+                    ui.radio_value(&mut current_state.settings.cache_file_names, cache::CacheFileNames::Hashed, "Hashed");
+                    ui.radio_value(&mut current_state.settings.cache_file_names, cache::CacheFileNames::Readable, "Artist - title")
+                        .on_hover_text("e.g. beyonce-halo-ab12cd.txt; applies to newly cached songs");
+                    if current_state.settings.cache_file_names != previous_names {
+                        cache::set_file_names(current_state.settings.cache_file_names);
+                    }
+                 });
+                 ui.horizontal(|ui| {
+                    ui.label("Compression:");
+                    let previous = (current_state.settings.cache_codec, current_state.settings.cache_compression_level);
+                    ui.radio_value(&mut current_state.settings.cache_codec, cache::CacheCodec::Plain, "None");
+                    ui.radio_value(&mut current_state.settings.cache_codec, cache::CacheCodec::Gzip, "gzip");
+                    let zstd_selected = current_state.settings.cache_codec == cache::CacheCodec::Zstd;
+                    if ui.add_enabled(cfg!(feature = "zstd"), egui::RadioButton::new(zstd_selected, "zstd"))
+                        .on_disabled_hover_text("Needs a build with --features zstd")
+                        .clicked()
+                    {
+                        current_state.settings.cache_codec = cache::CacheCodec::Zstd;
+                    }
+                    let compressed = current_state.settings.cache_codec != cache::CacheCodec::Plain;
+                    ui.add_enabled_ui(compressed, |ui| {
+                        ui.radio_value(&mut current_state.settings.cache_compression_level, cache::CompressionLevel::Fast, "Fast");
+                        ui.radio_value(&mut current_state.settings.cache_compression_level, cache::CompressionLevel::Best, "Best");
+                    });
+                    if (current_state.settings.cache_codec, current_state.settings.cache_compression_level) != previous {
+                        cache::set_compression(current_state.settings.cache_codec, current_state.settings.cache_compression_level);
+                    }
+                 });
+                 ui.checkbox(&mut current_state.settings.verify_cache_on_start, "Verify cache on start")
+                     .on_hover_text("Forget cached songs whose files were deleted, so they're fetched again");
+             }
+             ui.horizontal(|ui| {
+                ui.label("Now playing from:");
+                ui.radio_value(&mut current_state.settings.now_playing_source, SourceKind::Spotify, "Spotify");
+                ui.radio_value(&mut current_state.settings.now_playing_source, SourceKind::LastFm, "last.fm")
+                    .on_hover_text("Your last.fm \"now playing\" scrobble, for other players (no sync or playback position)");
+                ui.radio_value(&mut current_state.settings.now_playing_source, SourceKind::Mpris, "MPRIS")
+                    .on_hover_text("Any local media player on Linux (build with --features mpris)");
+                ui.radio_value(&mut current_state.settings.now_playing_source, SourceKind::SpotifyApp, "Spotify app")
+                    .on_hover_text("The Spotify desktop app on macOS, read locally without the Web API");
+                ui.radio_value(&mut current_state.settings.now_playing_source, SourceKind::Remote, "Remote")
+                    .on_hover_text(format!("Tracks pushed to http://{}/track by another instance or a companion app", server::SERVER_ADDR));
+                ui.label(egui::RichText::new("(restart to apply)").weak());
+             });
+             ui.horizontal(|ui| {
+                ui.label("Fall back to:")
+                    .on_hover_text("Tried in order when the main source fails (e.g. Spotify rate limits); the order can be changed in settings.json");
+                for (kind, name) in [(SourceKind::Spotify, "Spotify"), (SourceKind::LastFm, "last.fm"), (SourceKind::Mpris, "MPRIS"), (SourceKind::SpotifyApp, "Spotify app"), (SourceKind::Remote, "Remote")] {
+                    if kind == current_state.settings.now_playing_source {
+                        continue;
+                    }
+                    let fallbacks = &mut current_state.settings.fallback_sources;
+                    let mut enabled = fallbacks.contains(&kind);
+                    if ui.checkbox(&mut enabled, name).changed() {
+                        if enabled {
+                            fallbacks.push(kind);
+                        } else {
+                            fallbacks.retain(|&fallback| fallback != kind);
+                        }
+                    }
+                }
+             });
+             if current_state.settings.now_playing_source == SourceKind::LastFm || current_state.settings.fallback_sources.contains(&SourceKind::LastFm) {
+                 ui.horizontal(|ui| {
+                    ui.label("last.fm user:");
+                    ui.add(egui::TextEdit::singleline(&mut current_state.settings.lastfm_user).desired_width(100.0));
+                    ui.label("API key:");
+                    ui.add(egui::TextEdit::singleline(&mut current_state.settings.lastfm_api_key).password(true).desired_width(120.0).hint_text("or LASTFM_API_KEY"));
+                 });
+             }
+             ui.horizontal(|ui| {
+                ui.label("Spotify endpoint:");
+                ui.radio_value(&mut current_state.settings.playback_endpoint, spotify::PlaybackEndpoint::PlaybackState, "Playback state")
+                    .on_hover_text("user-read-playback-state: includes device and volume info");
+                ui.radio_value(&mut current_state.settings.playback_endpoint, spotify::PlaybackEndpoint::CurrentlyPlaying, "Currently playing")
+                    .on_hover_text("user-read-currently-playing: minimal scope, no device/volume info");
+                ui.label(egui::RichText::new("(restart to apply)").weak());
+             });
+             ui.add_enabled(secrets::is_supported(), egui::Checkbox::new(&mut current_state.settings.use_keyring, "Store Spotify credentials in the system keyring (restart to apply)"))
+                .on_hover_text("Keeps the client secret and token out of plain-text files")
+                .on_disabled_hover_text("Build with --features keyring to enable");
+             ui.horizontal(|ui| {
+                ui.label("When no lyrics:");
+                ui.radio_value(&mut current_state.settings.placeholder, PlaceholderMode::Off, "Nothing");
+                ui.radio_value(&mut current_state.settings.placeholder, PlaceholderMode::Message, "Message");
+                ui.radio_value(&mut current_state.settings.placeholder, PlaceholderMode::AlbumArt, "Album art");
+             });
+             if current_state.settings.placeholder == PlaceholderMode::Message {
+                ui.add(egui::TextEdit::multiline(&mut current_state.settings.placeholder_text).code_editor().desired_rows(2));
+             }
+             ui.horizontal(|ui| {
+                ui.label("Device:");
+                let selected_name = current_state.settings.preferred_device.as_ref()
+                    .map_or_else(|| "Active device".to_string(), |(_, name)| name.clone());
+                let devices = current_state.devices.clone();
+                egui::ComboBox::from_id_source("device_picker")
+                    .selected_text(selected_name)
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut current_state.settings.preferred_device, None, "Active device");
+                        for device in devices {
+                            let label = if device.is_active { format!("{} (active)", device.name) } else { device.name.clone() };
+                            ui.selectable_value(&mut current_state.settings.preferred_device, Some((device.id, device.name)), label);
+                        }
+                    });
+                if ui.button("⟳").on_hover_text("Refresh device list").clicked() {
+                    current_state.devices_requested = true;
+                }
+             });
+             ui.horizontal(|ui| {
+                ui.checkbox(&mut current_state.settings.show_volume, "Show volume");
+                ui.add_enabled(current_state.settings.show_volume, egui::Checkbox::new(&mut current_state.settings.volume_control, "with slider (restart to apply)"))
+                    .on_hover_text("Needs the user-modify-playback-state scope; volume is only reported by the playback-state endpoint");
+             });
+             ui.checkbox(&mut current_state.settings.show_up_next, "Show up next (restart to apply)")
+                .on_hover_text("Reads your Spotify queue; needs the user-read-currently-playing scope");
+             ui.horizontal(|ui| {
+                ui.checkbox(&mut current_state.settings.album_prefetch, "Prefetch album lyrics:")
+                    .on_hover_text("When playing an album, fetch lyrics for the next tracks in the background (spaced out, skips cached songs)");
+                ui.add_enabled(current_state.settings.album_prefetch, egui::DragValue::new(&mut current_state.settings.album_prefetch_tracks).clamp_range(1..=MAX_ALBUM_PREFETCH_TRACKS).suffix(" tracks"));
+             });
+             ui.collapsing("Cache backup", |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("File:");
+                    ui.text_edit_singleline(&mut current_state.archive_path);
+                });
+                ui.horizontal(|ui| {
+                    let path = std::path::PathBuf::from(&current_state.archive_path);
+                    if ui.button("Export").clicked() {
+                        current_state.status = match cache::export_archive(&path) {
+                            Ok(count) => format!("Exported {} cached songs to {}", count, path.display()),
+                            Err(e) => format!("Cache export failed: {}", e),
+                        };
+                    }
+                    if ui.button("Import").clicked() {
+                        current_state.status = match cache::import_archive(&path) {
+                            Ok(report) => format!(
+                                "Imported cache: {} added, {} conflicts ({} updated from archive, {} kept local)",
+                                report.added, report.updated + report.kept_local, report.updated, report.kept_local
+                            ),
+                            Err(e) => format!("Cache import failed: {}", e),
+                        };
+                    }
+                });
+             });
+             ui.collapsing("Lyrics selectors", |ui| {
+                ui.label("CSS selectors for the Genius lyrics container, one per line, tried in order:");
+                let mut selectors_text = current_state.settings.lyrics_selectors.join("\n");
+                if ui.text_edit_multiline(&mut selectors_text).changed() {
+                    // Keep blank lines while editing; they're skipped when parsing
+                    current_state.settings.lyrics_selectors = selectors_text.split('\n').map(String::from).collect();
+                }
+                if ui.button("Reset to built-in list").clicked() {
+                    current_state.settings.lyrics_selectors = Settings::default().lyrics_selectors;
+                }
+                ui.label("Promo text to strip, one per line (* matches any text; only removed on its own line or glued to the end of one):");
+                let mut promo_text = current_state.settings.promo_phrases.join("\n");
+                if ui.text_edit_multiline(&mut promo_text).changed() {
+                    current_state.settings.promo_phrases = promo_text.split('\n').map(String::from).collect();
+                }
+                if ui.button("Reset promo list").clicked() {
+                    current_state.settings.promo_phrases = Settings::default().promo_phrases;
+                }
+                ui.label("Title tags left out of page names, one per line (only as the last \"(...)\" or \"[...]\" of a title):");
+                let mut tags_text = current_state.settings.slug_strip_tags.join("\n");
+                let mut tags_changed = ui.text_edit_multiline(&mut tags_text).changed();
+                if tags_changed {
+                    current_state.settings.slug_strip_tags = tags_text.split('\n').map(String::from).collect();
+                }
+                if ui.button("Reset tag list").clicked() {
+                    current_state.settings.slug_strip_tags = Settings::default().slug_strip_tags;
+                    tags_changed = true;
+                }
+                if tags_changed {
+                    lyrics::set_slug_strip_tags(&current_state.settings.slug_strip_tags);
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Genius base URL:");
+                    ui.text_edit_singleline(&mut current_state.settings.genius_base_url)
+                        .on_hover_text("For mirrors; blank or invalid values fall back to genius.com");
+                    if ui.button("Reset").clicked() {
+                        current_state.settings.genius_base_url = lyrics::GENIUS_BASE_URL.to_string();
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Selector updates URL:");
+                    ui.text_edit_singleline(&mut current_state.settings.selector_update_url)
+                        .on_hover_text("Optional. A JSON list of selectors, e.g. [\"div.lyrics-v2\"], fetched on start and tried before the ones above. Applies after restart");
+                });
+                ui.label("Also try these Genius hosts when a page isn't found, one per line (e.g. regional sites):");
+                let mut hosts_text = current_state.settings.genius_alternate_hosts.join("\n");
+                if ui.text_edit_multiline(&mut hosts_text).changed() {
+                    current_state.settings.genius_alternate_hosts = hosts_text.split('\n').map(String::from).collect();
                 }
              });
         });
 
 
+        // Upload newly decoded album art once; recent covers stay cached until evicted
+        if let Some((url, image)) = &current_state.album_art
+            && !self.album_art_textures.contains(url)
+        {
+            let texture = ctx.load_texture(format!("album_art:{}", url), (**image).clone(), egui::TextureOptions::LINEAR);
+            self.album_art_textures.insert(url.clone(), texture);
+        }
+        let album_art = current_state.current_info.as_ref()
+            .and_then(|info| info.album_art_url.as_deref())
+            .and_then(|url| self.album_art_textures.get(url).cloned());
+
+        // Now-playing popup after a song change, fading in and out
+        if let Some(raised) = current_state.osd_raised {
+            let shown_for = Duration::from_secs(current_state.settings.now_playing_osd_secs.max(1));
+            let elapsed = raised.elapsed();
+            match &current_state.current_info {
+                Some(info) if elapsed < shown_for => {
+                    let fade = |time: Duration| (time.as_secs_f32() / OSD_FADE.as_secs_f32()).min(1.0);
+                    let opacity = fade(elapsed).min(fade(shown_for - elapsed));
+                    let artists = current_state.settings.display_artists(&info.artists);
+                    egui::Area::new(egui::Id::new("now_playing_osd"))
+                        .anchor(egui::Align2::CENTER_TOP, [0.0, 48.0])
+                        .order(egui::Order::Foreground)
+                        .interactable(false)
+                        .show(ctx, |ui| {
+                            ui.set_opacity(opacity);
+                            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                                ui.horizontal(|ui| {
+                                    if let Some(texture) = &album_art {
+                                        ui.add(egui::Image::new(texture).fit_to_exact_size(egui::vec2(OSD_ART_SIZE, OSD_ART_SIZE)));
+                                    }
+                                    ui.vertical(|ui| {
+                                        ui.label(egui::RichText::new(&info.title).heading().strong());
+                                        ui.label(egui::RichText::new(artists).weak());
+                                    });
+                                });
+                            });
+                        });
+                    ctx.request_repaint_after(Duration::from_millis(33));
+                }
+                _ => current_state.osd_raised = None,
+            }
+        }
+
         // --- Main Content Panel ---
         egui::CentralPanel::default().show(ctx, |ui| {
             // Display current song title and artists
             if let Some(info) = &current_state.current_info {
-                 let artists_str = info.artists.join(", ");
+                 let artists_str = current_state.settings.display_artists(&info.artists);
                  ui.heading(format!("{} - {}", artists_str, info.title));
+
+                 // Manual override: pick which artists go into the Genius URL, then re-fetch
+                 if info.artists.len() > 1 && current_state.slug_artist_selection.len() == info.artists.len() {
+                     let artists = info.artists.clone();
+                     ui.collapsing("Artists used for lyrics lookup", |ui| {
+                         for (artist, include) in artists.iter().zip(current_state.slug_artist_selection.iter_mut()) {
+                             ui.checkbox(include, artist);
+                         }
+                         let any_selected = current_state.slug_artist_selection.iter().any(|include| *include);
+                         if ui.add_enabled(any_selected && !current_state.refetch_requested, egui::Button::new("Re-fetch with selection")).clicked() {
+                             current_state.refetch_requested = true;
+                         }
+                     });
+                 }
+                 if current_state.settings.show_up_next
+                     && let Some(next) = &current_state.up_next
+                 {
+                     ui.label(egui::RichText::new(format!("Up next: {}", next)).small().weak());
+                 }
+                 if current_state.settings.show_volume
+                     && let Some(volume) = current_state.current_info.as_ref().and_then(|info| info.volume_percent)
+                 {
+                     if current_state.settings.volume_control {
+                         let mut new_volume = volume;
+                         ui.horizontal(|ui| {
+                             ui.label("🔊");
+                             if ui.add(egui::Slider::new(&mut new_volume, 0..=100).suffix("%")).changed() {
+                                 current_state.volume_requested = Some(new_volume as u8);
+                                 // Show the new value right away instead of waiting for the next poll
+                                 if let Some(info) = current_state.current_info.as_mut() {
+                                     info.volume_percent = Some(new_volume);
+                                 }
+                             }
+                         });
+                     } else {
+                         ui.label(egui::RichText::new(format!("🔊 {}%", volume)).small().weak());
+                     }
+                 }
+                 if let Some(url) = &current_state.auth_url {
+                     // Opened once; the link is for a closed tab or a browser that didn't come up
+                     if self.opened_auth_url.as_ref() != Some(url) {
+                         ctx.open_url(egui::OpenUrl::new_tab(url));
+                         self.opened_auth_url = Some(url.clone());
+                     }
+                     ui.horizontal(|ui| {
+                         ui.label(egui::RichText::new("Authorize LyricRs in your browser").small());
+                         ui.hyperlink_to(egui::RichText::new("Open the page again").small(), url);
+                     });
+                 } else if current_state.controls_need_reauth {
+                     ui.horizontal(|ui| {
+                         ui.label(egui::RichText::new("Re-authorize to enable controls").small());
+                         if ui.small_button("Re-authorize").clicked() {
+                             current_state.settings.volume_control = true; // Make sure the modify scope is requested
+                             current_state.reauth_requested = true;
+                         }
+                     });
+                 }
                  // TODO: Add playback progress bar here later
                  ui.separator();
             } else {
@@ -245,14 +2442,570 @@ impl eframe::App for LyricsApp {
                  ui.separator();
             }
 
-            // Display lyrics
-            egui::ScrollArea::vertical().auto_shrink([false; 2]).show(ui, |ui| {
-                ui.label(egui::RichText::new(&current_state.lyrics).size(14.0));
+            // Recently played: clicking an entry shows its cached lyrics without touching Spotify
+            if !current_state.history.is_empty() {
+                let mut selected: Option<usize> = None;
+                ui.collapsing(format!("Recently played ({})", current_state.history.len()), |ui| {
+                    let viewing = current_state.history_view.as_ref().map(|view| view.index);
+                    for (index, entry) in current_state.history.iter().enumerate() {
+                        let status = match entry.status {
+                            FetchStatus::Pending => "…",
+                            FetchStatus::Cached => "cached",
+                            FetchStatus::Fetched => "fetched",
+                            FetchStatus::Failed => "not found",
+                            FetchStatus::Skipped => "skipped",
+                        };
+                        let label = format!("{} - {} ({})", current_state.settings.display_artists(&entry.artists), entry.title, status);
+                        if ui.selectable_label(viewing == Some(index), label).clicked() {
+                            selected = Some(index);
+                        }
+                    }
+                    ui.checkbox(&mut current_state.settings.history_auto_return, "Return to live when a new song starts");
+                });
+                if let Some(index) = selected {
+                    current_state.view_history(index);
+                }
+            }
+
+            // Back/forward through the history (Alt+Left/Right); the live song keeps updating underneath.
+            // Left alone while a text field has focus, where Alt+arrows move the cursor by word on macOS
+            let (back_pressed, forward_pressed) = if ctx.wants_keyboard_input() {
+                (false, false)
+            } else {
+                ctx.input_mut(|i| (i.consume_key(egui::Modifiers::ALT, egui::Key::ArrowLeft), i.consume_key(egui::Modifiers::ALT, egui::Key::ArrowRight)))
+            };
+            if back_pressed {
+                current_state.history_back();
+            }
+            if forward_pressed {
+                current_state.history_forward();
+            }
+            if let Some(index) = current_state.history_view.as_ref().map(|view| view.index)
+                && let Some(entry) = current_state.history.get(index).cloned()
+            {
+                ui.horizontal(|ui| {
+                    let has_older = index + 1 < current_state.history.len();
+                    if ui.add_enabled(has_older, egui::Button::new("◀")).on_hover_text("Older song (Alt+Left)").clicked() {
+                        current_state.history_back();
+                    }
+                    if ui.button("▶").on_hover_text("Newer song (Alt+Right)").clicked() {
+                        current_state.history_forward();
+                    }
+                    ui.label(egui::RichText::new(format!("Viewing history: {} - {}", current_state.settings.display_artists(&entry.artists), entry.title)).strong());
+                    if ui.button("Return to live").clicked() {
+                        current_state.history_view = None;
+                    }
+                });
+            } else if current_state.live_history_index().map_or(0, |live| live + 1) < current_state.history.len() {
+                ui.horizontal(|ui| {
+                    if ui.small_button("◀").on_hover_text("Previous songs' lyrics (Alt+Left)").clicked() {
+                        current_state.history_back();
+                    }
+                });
+            }
+
+            // Look up any track by its Spotify link, without playing it
+            ui.collapsing("Look up a Spotify track", |ui| {
+                ui.horizontal(|ui| {
+                    let input = ui.add(egui::TextEdit::singleline(&mut current_state.lookup_input).hint_text("spotify:track:… or open.spotify.com link"));
+                    let submitted = input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    let can_look_up = !current_state.lookup_input.trim().is_empty() && current_state.lookup_requested.is_none();
+                    if (ui.add_enabled(can_look_up, egui::Button::new("Look up")).clicked() || submitted) && can_look_up {
+                        current_state.lookup_requested = Some(current_state.lookup_input.trim().to_string());
+                    }
+                });
             });
 
+            // Hand-written lyrics for songs no source has; saved to the cache under the song's key
+            if let Some((artists, title)) = current_state.editing_song.clone() {
+                ui.group(|ui| {
+                    ui.label(format!("Lyrics for {} - {}", current_state.settings.display_artists(&artists), title));
+                    egui::ScrollArea::vertical().max_height(ui.available_height() / 2.0).show(ui, |ui| {
+                        ui.add(egui::TextEdit::multiline(&mut current_state.lyrics_draft).desired_width(f32::INFINITY).desired_rows(10).hint_text("Paste or type the lyrics"));
+                    });
+                    ui.horizontal(|ui| {
+                        let can_save = !current_state.lyrics_draft.trim().is_empty();
+                        if ui.add_enabled(can_save, egui::Button::new("Save")).on_hover_text("Store in the cache (pinned) and show them").clicked() {
+                            let draft = std::mem::take(&mut current_state.lyrics_draft);
+                            let saved = cache::store_manual_lyrics(&artists, &title, &draft);
+                            let artists_str = current_state.settings.display_artists(&artists);
+                            // Only replace what's shown if it's still the edited song
+                            let still_playing = current_state.current_info.as_ref().is_some_and(|info| info.artists == artists && info.title == title);
+                            if still_playing {
+                                current_state.set_lyrics(draft);
+                                current_state.last_url = None;
+                                current_state.lyrics_provider = None;
+                                if let Some(info) = current_state.current_info.clone() {
+                                    current_state.set_history_status(&info, FetchStatus::Cached);
+                                }
+                            }
+                            current_state.status = match (saved, still_playing) {
+                                (true, true) => format!("Showing lyrics for {} - {} (Manual)", artists_str, title),
+                                (true, false) => format!("Saved lyrics for {} - {}", artists_str, title),
+                                (false, _) => "Could not save the lyrics to the cache".to_string(),
+                            };
+                            current_state.editing_song = None;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            current_state.editing_song = None;
+                            current_state.lyrics_draft.clear();
+                        }
+                    });
+                });
+            }
+
+            // Tap sync: Space marks the start of the next line while the song plays
+            if let Some(sync) = current_state.tap_sync.clone() {
+                let same_song = current_state.current_info.as_ref().is_some_and(|info| info.artists == sync.artists && info.title == sync.title);
+                if !same_song {
+                    current_state.tap_sync = None; // The song changed before saving; the taps would be meaningless
+                } else {
+                    if !ctx.wants_keyboard_input() && ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Space)) {
+                        current_state.tap();
+                    }
+                    ui.group(|ui| {
+                        ui.label(format!("Tap sync: press Space as each line starts ({}/{})", sync.taps.len(), sync.line_count()));
+                        match sync.next_line() {
+                            Some(line) => ui.label(egui::RichText::new(format!("Next: {}", line)).strong()),
+                            None => ui.label(egui::RichText::new("All lines timed").weak()),
+                        };
+                        ui.horizontal(|ui| {
+                            if ui.add_enabled(!sync.taps.is_empty(), egui::Button::new("Save")).on_hover_text("Store as synced lyrics (pinned); untimed lines stay plain").clicked() {
+                                current_state.finish_tap_sync();
+                            }
+                            if ui.add_enabled(!sync.taps.is_empty(), egui::Button::new("Undo")).on_hover_text("Forget the last tap").clicked()
+                                && let Some(sync) = current_state.tap_sync.as_mut()
+                            {
+                                sync.taps.pop();
+                            }
+                            if ui.button("Cancel").clicked() {
+                                current_state.tap_sync = None;
+                            }
+                        });
+                    });
+                }
+            }
+
+            // Display lyrics
+            let mut scroll_area = egui::ScrollArea::vertical().auto_shrink([false; 2]);
+            let history_view = current_state.history_view.clone();
+            let auto_scrolling = current_state.auto_scroll && !current_state.auto_scroll_paused && !current_state.lyrics.is_empty() && history_view.is_none();
+            let scroll_to_top = std::mem::take(&mut current_state.scroll_to_top_requested); // Auto-scroll already restarts at the top
+            let refreshed_from = current_state.refreshed_from.take();
+            let synced = history_view.is_none() && current_state.lines.iter().any(|line| line.time_ms.is_some());
+            let follow_line = synced && (auto_scrolling || std::mem::take(&mut current_state.recenter_requested));
+            if auto_scrolling && !synced {
+                // Plain lyrics have no timestamps, so pace the scroll by how far into the song we are
+                let fraction = match (current_state.interpolated_progress_ms(), current_state.current_info.as_ref().and_then(|info| info.duration_ms)) {
+                    (Some(progress), Some(duration)) if duration > 0 => progress as f32 / duration as f32,
+                    _ => 0.0,
+                };
+                let fraction = (fraction * current_state.auto_scroll_speed).clamp(0.0, 1.0);
+                scroll_area = scroll_area.vertical_scroll_offset(fraction * self.max_scroll);
+            } else if scroll_to_top || matches!(self.share, ShareState::Capturing { .. }) {
+                scroll_area = scroll_area.vertical_scroll_offset(0.0); // The share card must be fully visible
+            } else if let Some(previous) = refreshed_from
+                && !follow_line
+                && current_state.lines.len() > 1
+            {
+                // Stay on the same words after a background refresh (synced lyrics follow playback anyway)
+                let old_index = (self.scroll_fraction * previous.len().saturating_sub(1) as f32).round() as usize;
+                let new_index = lyrics::matching_line(&previous, old_index, &current_state.lines);
+                let fraction = new_index as f32 / (current_state.lines.len() - 1) as f32;
+                scroll_area = scroll_area.vertical_scroll_offset(fraction * self.max_scroll);
+            }
+            // Frame gives the lyrics breathing room from the window edges and an optional own background
+            let mut lyrics_frame = egui::Frame::none()
+                .inner_margin(egui::Margin::same(current_state.settings.lyrics_padding))
+                .rounding(4.0);
+            if let Some([r, g, b, a]) = current_state.settings.lyrics_background {
+                lyrics_frame = lyrics_frame.fill(Color32::from_rgba_unmultiplied(r, g, b, a));
+            }
+            let scroll_output = lyrics_frame.show(ui, |ui| {
+                scroll_area.show(ui, |ui| {
+                    // Optional fixed reading measure, centered; narrower windows just use their full width
+                    let available = ui.available_width();
+                    let column = current_state.settings.max_text_width.map_or(available, |max| max.min(available));
+                    ui.horizontal_top(|ui| {
+                        ui.add_space(((available - column) / 2.0).max(0.0));
+                        ui.vertical(|ui| {
+                            ui.set_max_width(column);
+                            if let Some(view) = &history_view {
+                                let size = current_state.settings.lyrics_font_size(ctx.pixels_per_point());
+                                match &view.lyrics {
+                                    Some(text) => ui.label(egui::RichText::new(text).size(size)),
+                                    None => ui.label(egui::RichText::new("No cached lyrics for this song").size(size).weak()),
+                                };
+                                return;
+                            }
+                            let no_lyrics = !current_state.lyrics_stale && (current_state.lyrics.is_empty() || current_state.lyrics_missing);
+                            if no_lyrics && current_state.settings.placeholder != PlaceholderMode::Off {
+                                Self::show_placeholder(ui, &current_state, album_art.as_ref());
+                                return;
+                            }
+                            if let ShareState::Capturing { line, card_rect } = &mut self.share {
+                                let rect = Self::show_share_card(ui, &current_state, album_art.as_ref(), line);
+                                if card_rect.is_none() {
+                                    *card_rect = Some(rect);
+                                    ui.ctx().send_viewport_cmd(egui::ViewportCommand::Screenshot);
+                                }
+                                return;
+                            }
+                            if matches!(self.share, ShareState::Picking) {
+                                if let Some(index) = Self::show_lyric_lines(ui, &current_state, false, true) {
+                                    self.share = ShareState::Capturing { line: current_state.lines[index].text.clone(), card_rect: None };
+                                }
+                                return;
+                            }
+                            // Follows is_playing every frame, so resuming brings the lyrics back as they were
+                            if current_state.is_paused() {
+                                match current_state.settings.paused_lyrics {
+                                    PausedLyrics::Keep => {}
+                                    PausedLyrics::Dim => ui.set_opacity(PAUSED_LYRICS_OPACITY),
+                                    PausedLyrics::Hide => {
+                                        ui.label(egui::RichText::new("Paused").weak());
+                                        return;
+                                    }
+                                }
+                            }
+                            if current_state.settings.single_line_mode {
+                                let Some(line) = current_state.current_line().map(|line| line.text.clone()) else {
+                                    ui.label(egui::RichText::new("Single-line mode needs synced lyrics").weak());
+                                    return;
+                                };
+                                // Each new line starts scrolling from its beginning
+                                if self.ticker.0 != line {
+                                    self.ticker = (line.clone(), 0.0);
+                                }
+                                let mut text = egui::RichText::new(line).size(current_state.settings.lyrics_font_size(ctx.pixels_per_point()));
+                                if current_state.settings.bold_lyrics {
+                                    text = text.strong();
+                                }
+                                Self::show_ticker(ui, text, current_state.settings.ticker_speed, &mut self.ticker.1);
+                                return;
+                            }
+                            if synced {
+                                Self::show_lyric_lines(ui, &current_state, follow_line, false);
+                                return;
+                            }
+                            if current_state.settings.lyrics_mode == LyricsMode::Synced
+                                && !current_state.lyrics_stale
+                                && !current_state.lyrics.is_empty()
+                                && !current_state.lyrics_missing
+                            {
+                                ui.label(egui::RichText::new("No synced lyrics for this song").small().weak());
+                            }
+                            let mut text = match current_state.settings.lyrics_spacing {
+                                LyricsSpacing::Spaced => egui::RichText::new(&current_state.lyrics),
+                                LyricsSpacing::Compact => egui::RichText::new(lyrics::compact_stanzas(&current_state.lyrics)),
+                            }
+                            .size(current_state.settings.lyrics_font_size(ctx.pixels_per_point()));
+                            if current_state.lyrics_stale {
+                                text = text.weak(); // Previous song's lyrics, about to be replaced
+                            } else if current_state.settings.bold_lyrics {
+                                text = text.strong();
+                            }
+                            ui.label(text);
+                        });
+                    });
+                })
+            }).inner;
+            self.max_scroll = (scroll_output.content_size.y - scroll_output.inner_rect.height()).max(0.0);
+            self.lyrics_slack = scroll_output.inner_rect.height() - scroll_output.content_size.y;
+            self.scroll_fraction = if self.max_scroll > 0.0 { (scroll_output.state.offset.y / self.max_scroll).clamp(0.0, 1.0) } else { 0.0 };
+
+            // Ctrl+scroll over the lyrics zooms the text (egui-winit reports it as a zoom delta)
+            if ui.rect_contains_pointer(scroll_output.inner_rect) {
+                let zoom = ui.input(|i| i.zoom_delta());
+                if zoom != 1.0 {
+                    current_state.settings.font_size = (current_state.settings.font_size * zoom).clamp(MIN_FONT_SIZE, MAX_FONT_SIZE);
+                }
+            }
+
+            // Manual scrolling or dragging takes over until the user resumes auto-scroll
+            if auto_scrolling && ui.rect_contains_pointer(scroll_output.inner_rect) {
+                let interacted = ui.input(|i| i.raw_scroll_delta.y != 0.0 || i.pointer.primary_down());
+                if interacted {
+                    current_state.auto_scroll_paused = true;
+                }
+            }
+
              // Footer area for status
              ui.separator();
-             ui.label(&current_state.status);
+             // A re-fetch found web lyrics for a song whose lyrics the user entered: replace them only on request
+             if let Some((artists, title, fetched)) = current_state.manual_replacement.clone()
+                 && current_state.current_info.as_ref().is_some_and(|info| info.artists == artists && info.title == title)
+             {
+                 ui.horizontal(|ui| {
+                    ui.colored_label(Color32::YELLOW, "Web lyrics found. Replace your own lyrics for this song?");
+                    if ui.small_button("Replace").clicked() {
+                        cache::store_lyrics_to_cache(&artists, &title, &fetched.lyrics, Some(&fetched.url));
+                        current_state.last_url = Some(fetched.url);
+                        current_state.lyrics_provider = Some(fetched.provider);
+                        current_state.set_lyrics(fetched.lyrics);
+                        current_state.manual_replacement = None;
+                    }
+                    if ui.small_button("Keep mine").clicked() {
+                        current_state.manual_replacement = None;
+                    }
+                 });
+             }
+             if let Some(notice) = current_state.cache_notice.clone() {
+                 ui.horizontal(|ui| {
+                    ui.colored_label(Color32::YELLOW, format!("⚠ {}", notice));
+                    if ui.small_button("✖").on_hover_text("Dismiss").clicked() {
+                        current_state.cache_notice = None;
+                    }
+                 });
+             }
+             ui.horizontal(|ui| {
+                let can_refetch = current_state.current_info.is_some() && !current_state.refetch_requested;
+                if ui.add_enabled(can_refetch, egui::Button::new("⟳")).on_hover_text("Re-fetch lyrics (F5)").clicked() {
+                    current_state.refetch_requested = true;
+                }
+                if current_state.current_info.is_some() {
+                    let editing = current_state.editing_song.is_some();
+                    if ui.add_enabled(!editing, egui::Button::new("✏")).on_hover_text("Enter or correct the lyrics by hand").clicked() {
+                        current_state.start_editing_lyrics();
+                    }
+                    if current_state.can_tap_sync() && ui.button("⏱").on_hover_text("Tap sync: time these plain lyrics by pressing Space as each line starts").clicked() {
+                        current_state.start_tap_sync();
+                    }
+                }
+                // Skip list: stop looking up a song that keeps getting wrong lyrics
+                if let Some(info) = current_state.current_info.clone() {
+                    if cache::is_song_skipped(&info.artists, &info.title) {
+                        if ui.button("Un-skip").on_hover_text("Look up lyrics for this song again").clicked() {
+                            cache::set_song_skipped(&info.artists, &info.title, false);
+                            current_state.refetch_requested = true; // Looked up on the web again, past any wrong cached copy
+                        }
+                    } else {
+                        if cache::get_cache_age(&info.artists, &info.title).is_some() {
+                            // Keeps (e.g. corrected) lyrics safe from cache eviction
+                            let pinned = cache::is_song_pinned(&info.artists, &info.title);
+                            let hover = if pinned { "Pinned: never evicted from the cache (click to unpin)" } else { "Pin these lyrics so the cache never evicts them" };
+                            if ui.add(egui::SelectableLabel::new(pinned, "📌")).on_hover_text(hover).clicked() {
+                                cache::set_song_pinned(&info.artists, &info.title, !pinned);
+                            }
+                        }
+                        if ui.button("🚫").on_hover_text("Skip lyrics for this song from now on").clicked() {
+                            current_state.skip_current_song();
+                        }
+                    }
+                }
+                // No URL for title-only (e.g. local file) lookups until a search resolves one
+                if ui.add_enabled(current_state.last_url.is_some(), egui::Button::new("🔗"))
+                    .on_hover_text("Copy the Genius URL")
+                    .on_disabled_hover_text("No Genius URL for this song")
+                    .clicked()
+                    && let Some(url) = current_state.last_url.clone()
+                {
+                    ctx.copy_text(url);
+                    current_state.status = "Copied the Genius URL".to_string();
+                }
+                // Share image: pick a line, then the card is drawn, screenshotted and saved
+                let can_share = !current_state.lines.is_empty() && !current_state.lyrics_missing && !current_state.lyrics_stale;
+                let sharing = !matches!(self.share, ShareState::Idle);
+                if ui.add_enabled(can_share || sharing, egui::SelectableLabel::new(sharing, "📷")).on_hover_text("Share a line as an image").clicked() {
+                    if sharing {
+                        self.share = ShareState::Idle;
+                    } else {
+                        self.share = ShareState::Picking;
+                        current_state.album_art_requested = true;
+                        current_state.status = "Click a line to share it".to_string();
+                    }
+                }
+                if current_state.busy {
+                    ui.add(egui::Spinner::new()); // Requests its own repaints while visible
+                }
+                // "line 12 / 48": the synced line, else estimated from the scroll position
+                let lyrics_shown = !current_state.lines.is_empty() && !current_state.lyrics_missing && !current_state.lyrics_stale;
+                let line_index = current_state.interpolated_progress_ms()
+                    .and_then(|progress| lyrics::current_line_index(&current_state.lines, progress))
+                    .unwrap_or_else(|| (self.scroll_fraction * (current_state.lines.len().saturating_sub(1)) as f32).round() as usize);
+                let (number, total) = lyrics::line_position(&current_state.lines, line_index);
+                if lyrics_shown && total > 0 {
+                    ui.label(egui::RichText::new(format!("line {} / {}", number, total)).small().weak());
+                }
+                ui.label(&current_state.status);
+                if let Some(provider) = current_state.lyrics_provider
+                    && !current_state.lyrics_missing
+                {
+                    ui.label(egui::RichText::new(format!("via {}", provider.name())).small().weak()); // Source badge
+                }
+             });
+             if let Some(url) = &current_state.last_url {
+                 ui.label(egui::RichText::new(url).small().weak()); // Debug aid for wrong-slug issues
+             }
         });
+
+        self.apply_window_sizing(ctx, &mut current_state.settings);
+
+        // Persist settings once they've changed, waiting until any slider drag has finished
+        if current_state.settings != self.saved_settings && !ctx.input(|i| i.pointer.any_down()) {
+            config::save_settings(&current_state.settings);
+            self.saved_settings = current_state.settings.clone();
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_score_matches_in_order_and_prefers_word_starts() {
+        assert!(fuzzy_score("cl", "Copy lyrics").is_some());
+        assert_eq!(fuzzy_score("sc", "Copy lyrics"), None); // Out of order
+        assert_eq!(fuzzy_score("", "Copy lyrics"), Some(0));
+        // "cl" hits both word starts of "Copy lyrics", only the first one of "Cancel"
+        assert!(fuzzy_score("cl", "Copy lyrics") > fuzzy_score("cl", "Cancel"));
+        assert!(fuzzy_score("EXPORT", "Export cache") > fuzzy_score("ex", "Export cache"));
+    }
+
+    fn playing(title: &str, progress_ms: u32) -> SpotifyInfo {
+        SpotifyInfo {
+            artists: vec!["Artist".to_string()],
+            title: title.to_string(),
+            progress_ms: Some(progress_ms),
+            duration_ms: Some(200_000),
+            is_playing: true,
+            album_art_url: None,
+            volume_percent: None,
+        }
+    }
+
+    #[test]
+    fn now_playing_popup_only_on_new_songs_when_enabled() {
+        let mut state = AppState::default();
+        state.push_history(&playing("One", 0));
+        assert!(state.osd_raised.is_none());
+        state.settings.now_playing_osd = true;
+        state.push_history(&playing("Two", 0));
+        let raised = state.osd_raised.expect("raised on song change");
+        state.push_history(&playing("Two", 5_000)); // Same song again: keeps the original time
+        assert_eq!(state.osd_raised, Some(raised));
+    }
+
+    #[test]
+    fn all_artists_setting_applies_to_the_current_song() {
+        let mut state = AppState::default();
+        let artists = vec!["Lead".to_string(), "Guest".to_string()];
+        state.apply_playback_update(SpotifyInfo { artists: artists.clone(), ..playing("Duet", 0) });
+        state.reset_slug_artist_selection();
+        assert_eq!(state.slug_artists(&artists), ["Lead"]);
+        state.settings.slug_all_artists = true;
+        state.reset_slug_artist_selection();
+        assert_eq!(state.slug_artists(&artists), ["Lead", "Guest"]);
+    }
+
+    #[test]
+    fn paused_only_with_a_song_that_is_not_playing() {
+        let mut state = AppState::default();
+        assert!(!state.is_paused()); // Nothing loaded
+        state.apply_playback_update(playing("Song", 10_000));
+        assert!(!state.is_paused());
+        state.apply_playback_update(SpotifyInfo { is_playing: false, ..playing("Song", 10_000) });
+        assert!(state.is_paused());
+        state.apply_playback_update(playing("Song", 10_000));
+        assert!(!state.is_paused());
+    }
+
+    #[test]
+    fn normal_progress_is_not_a_jump() {
+        let mut state = AppState::default();
+        assert_eq!(state.apply_playback_update(playing("Song", 10_000)), PlaybackJump::None);
+        assert_eq!(state.apply_playback_update(playing("Song", 11_000)), PlaybackJump::None);
+    }
+
+    #[test]
+    fn detects_seeks_both_ways() {
+        let mut state = AppState::default();
+        state.apply_playback_update(playing("Song", 10_000));
+        assert_eq!(state.apply_playback_update(playing("Song", 90_000)), PlaybackJump::Seek);
+        assert_eq!(state.apply_playback_update(playing("Song", 40_000)), PlaybackJump::Seek);
+        // The estimate snaps to the polled position
+        assert!(state.interpolated_progress_ms().is_some_and(|progress| progress.abs_diff(40_000) < SEEK_THRESHOLD_MS));
+    }
+
+    #[test]
+    fn detects_restart_of_same_song() {
+        let mut state = AppState::default();
+        state.apply_playback_update(playing("Song", 195_000));
+        assert_eq!(state.apply_playback_update(playing("Song", 1_000)), PlaybackJump::Restart);
+    }
+
+    #[test]
+    fn history_navigation_steps_back_and_returns_to_live() {
+        let mut state = AppState::default();
+        for title in ["One", "Two", "Three"] {
+            let info = playing(title, 0);
+            state.apply_playback_update(info.clone());
+            state.push_history(&info);
+        }
+        // Live is "Three" (index 0), so back starts at "Two"
+        state.history_back();
+        assert_eq!(state.history_view.as_ref().map(|view| view.index), Some(1));
+        state.history_back();
+        state.history_back(); // Nothing older than "One"
+        assert_eq!(state.history_view.as_ref().map(|view| view.index), Some(2));
+        state.history_forward();
+        state.history_forward(); // "Three" is the live song
+        assert!(state.history_view.is_none());
+
+        // A new song returns to live by default, or keeps the same entry in view
+        state.history_back();
+        let info = playing("Four", 0);
+        state.apply_playback_update(info.clone());
+        state.push_history(&info);
+        assert!(state.history_view.is_none());
+        state.settings.history_auto_return = false;
+        state.history_back();
+        let info = playing("Five", 0);
+        state.apply_playback_update(info.clone());
+        state.push_history(&info);
+        assert_eq!(state.history_view.as_ref().and_then(|view| state.history.get(view.index)).map(|entry| entry.title.as_str()), Some("Three"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn plain_mode_drops_timestamps() {
+        let mut state = AppState::default();
+        state.settings.lyrics_mode = LyricsMode::Plain;
+        state.set_lyrics("[00:05.00]One\n[00:12.00]Two".to_string());
+        assert!(state.lines.iter().all(|line| line.time_ms.is_none()));
+        assert_eq!(state.lyrics, "One\nTwo");
+
+        state.settings.lyrics_mode = LyricsMode::Auto;
+        state.reformat_lyrics();
+        assert_eq!(state.lines[1].time_ms, Some(12_000));
+    }
+
+    #[test]
+    fn next_line_change_is_timed_from_progress() {
+        let mut state = AppState::default();
+        state.set_lyrics("[00:05.00]One\n[00:12.00]Two\n[00:20.00]Three".to_string());
+        state.apply_playback_update(playing("Song", 10_000));
+        let next = state.next_line_change_in().unwrap();
+        assert!(next <= Duration::from_millis(2_000) && next > Duration::from_millis(1_500));
+
+        state.apply_playback_update(SpotifyInfo { is_playing: false, ..playing("Song", 10_000) });
+        assert_eq!(state.next_line_change_in(), None);
+    }
+
+    #[test]
+    fn song_change_is_not_a_jump() {
+        let mut state = AppState::default();
+        state.apply_playback_update(playing("Song", 150_000));
+        assert_eq!(state.apply_playback_update(playing("Other song", 0)), PlaybackJump::None);
+    }
+
+    #[test]
+    fn poll_jitter_stays_within_bounds() {
+        let jitter = Duration::from_millis(500);
+        assert_eq!(jittered_poll_interval(jitter, 0), POLL_INTERVAL - jitter);
+        assert_eq!(jittered_poll_interval(jitter, 1000), POLL_INTERVAL + jitter);
+        assert_eq!(jittered_poll_interval(jitter, 500), POLL_INTERVAL);
+        assert_eq!(jittered_poll_interval(Duration::ZERO, 12345), POLL_INTERVAL);
+        // Huge values are capped so polling never runs back to back
+        assert_eq!(jittered_poll_interval(Duration::from_secs(60), 0), POLL_INTERVAL / 2);
+    }
+}
@@ -0,0 +1,95 @@
+// Album art fetching and decoding, plus saving rendered images (share cards)
+use std::{path::Path, time::Duration};
+
+use eframe::egui::ColorImage;
+
+pub const MAX_CACHED_TEXTURES: usize = 16; // Covers kept on the GPU; skipping back through recent songs needs no re-upload
+
+/// Small LRU keyed by image URL, for decoded album-art textures. Like the lyrics cache it evicts
+/// the least recently used entry once full; dropping an egui `TextureHandle` frees its GPU copy.
+pub struct TextureCache<T> {
+    capacity: usize,
+    entries: Vec<(String, T)>, // Least recently used first
+}
+
+impl<T> TextureCache<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity: capacity.max(1), entries: Vec::new() }
+    }
+
+    pub fn contains(&self, url: &str) -> bool {
+        self.entries.iter().any(|(key, _)| key == url)
+    }
+
+    /// Looks up a texture and marks it as most recently used.
+    pub fn get(&mut self, url: &str) -> Option<&T> {
+        let position = self.entries.iter().position(|(key, _)| key == url)?;
+        let entry = self.entries.remove(position);
+        self.entries.push(entry);
+        self.entries.last().map(|(_, value)| value)
+    }
+
+    pub fn insert(&mut self, url: String, value: T) {
+        self.entries.retain(|(key, _)| *key != url);
+        self.entries.push((url, value));
+        while self.entries.len() > self.capacity {
+            let (evicted, _) = self.entries.remove(0);
+            println!("Album art cache full. Evicting texture: {}", evicted);
+        }
+    }
+}
+
+/// Downloads an album cover and decodes it into an egui image (texture upload happens on the UI thread).
+pub async fn fetch_album_art(url: &str) -> Result<ColorImage, String> {
+    let bytes = reqwest::Client::new()
+        .get(url)
+        .timeout(Duration::from_secs(15))
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Failed to download album art {}: {}", url, e))?
+        .bytes()
+        .await
+        .map_err(|e| format!("Failed to read album art {}: {}", url, e))?;
+
+    let image = image::load_from_memory(&bytes)
+        .map_err(|e| format!("Failed to decode album art {}: {}", url, e))?
+        .to_rgba8();
+    let size = [image.width() as usize, image.height() as usize];
+    Ok(ColorImage::from_rgba_unmultiplied(size, image.as_flat_samples().as_slice()))
+}
+
+/// Writes an egui image (e.g. a cropped screenshot) to disk as PNG.
+pub fn save_png(image: &ColorImage, path: &Path) -> Result<(), String> {
+    // Color32 is premultiplied; shares are drawn on an opaque fill, so this equals straight alpha
+    let bytes: Vec<u8> = image.pixels.iter().flat_map(|pixel| pixel.to_array()).collect();
+    image::save_buffer(path, &bytes, image.width() as u32, image.height() as u32, image::ColorType::Rgba8)
+        .map_err(|e| format!("Failed to save image {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn texture_cache_evicts_least_recently_used() {
+        let mut cache = TextureCache::new(2);
+        cache.insert("a".to_string(), 1);
+        cache.insert("b".to_string(), 2);
+        assert_eq!(cache.get("a"), Some(&1)); // "b" is now the oldest
+        cache.insert("c".to_string(), 3);
+        assert!(cache.contains("a"));
+        assert!(!cache.contains("b"));
+        assert!(cache.contains("c"));
+    }
+
+    #[test]
+    fn texture_cache_replaces_same_url() {
+        let mut cache = TextureCache::new(2);
+        cache.insert("a".to_string(), 1);
+        cache.insert("a".to_string(), 2);
+        assert_eq!(cache.get("a"), Some(&2));
+        cache.insert("b".to_string(), 3);
+        assert!(cache.contains("a")); // Only one slot was used by "a"
+    }
+}
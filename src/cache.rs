@@ -1,11 +1,11 @@
 // Lyrics Caching Logic will go here
 use std::{
-    collections::BTreeMap, // Use BTreeMap for ordered iteration (needed for LRU)
+    collections::{BTreeMap, BTreeSet, HashMap}, // Use BTreeMap for ordered iteration (needed for LRU)
     fs,
-    io::{self, Write},
+    io::{self, Read, Write},
     path::{Path, PathBuf},
     sync::Mutex,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
@@ -13,43 +13,192 @@ use sha2::{Digest, Sha256};
 const CACHE_DIR_NAME: &str = ".lyricrs_cache";
 const INDEX_FILE_NAME: &str = "index.json";
 const MAX_CACHE_ENTRIES: usize = 500; // Limit cache size
+const ARCHIVE_VERSION: u32 = 1; // Format version of export_archive files
+const INDEX_VERSION: u32 = 1; // Format version of index.json; indexes written before it was recorded are version 0
+const MAX_SLUG_LEN: usize = 64; // Readable file names: "<slug>-<hash prefix>.txt" stays well under OS limits
+const SHORT_HASH_LEN: usize = 6;
+const WRITE_PROBE_FILE_NAME: &str = ".write_probe";
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct CacheEntry {
     filename: String,
     last_accessed: u64, // Unix timestamp (seconds)
+    #[serde(default)] // Older indexes lack this; 0 means "unknown", i.e. treated as stale
+    stored_at: u64, // Unix timestamp (seconds) when the lyrics were last written
+    #[serde(default)]
+    source_url: Option<String>, // Page the lyrics were fetched from (after redirects / search fallback)
+    #[serde(default)]
+    pinned: bool, // Never evicted (e.g. hand-corrected lyrics)
+    #[serde(default)]
+    manual: bool, // Typed or pasted by the user, never replaced by background refreshes
+    #[serde(default)] // Older indexes only have plain files
+    codec: CacheCodec, // How this entry's file is encoded; entries keep theirs until re-stored
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug)]
 struct CacheIndex {
+    #[serde(default)] // Missing in version 0 indexes
+    version: u32,
     // Key: Unique identifier for the song (e.g., hash of "artist1,artist2 - title")
     // Value: CacheEntry
     entries: BTreeMap<String, CacheEntry>,
+    // Keys of songs the user chose to skip: no lookups, even if lyrics are cached
+    #[serde(default)]
+    skipped: BTreeSet<String>,
+}
+
+impl Default for CacheIndex {
+    fn default() -> Self {
+        Self { version: INDEX_VERSION, entries: BTreeMap::new(), skipped: BTreeSet::new() }
+    }
+}
+
+/// Where cached lyrics live. `Memory` keeps them only for this session and never touches disk.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CacheMode {
+    #[default]
+    Disk,
+    Memory,
+}
+
+/// How new cache files are named. Existing files keep their name (it's stored in the index).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CacheFileNames {
+    #[default]
+    Hashed, // "<sha256>.txt": safe for any artist/title
+    Readable, // "artist-title-ab12cd.txt": ASCII slug plus a short hash, for browsing the folder
+}
+
+/// Compression of newly written cache files. Zstd needs the `zstd` feature; without it, Zstd
+/// writes fall back to gzip and existing zstd files read as missing (so they're fetched again).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CacheCodec {
+    #[default]
+    Plain, // Plain .txt files, easy to read and edit by hand
+    Gzip, // .txt.gz
+    Zstd, // .txt.zst, smaller for short texts like lyrics
+}
+
+impl CacheCodec {
+    fn extension(self) -> &'static str {
+        match self {
+            CacheCodec::Plain => "",
+            CacheCodec::Gzip => ".gz",
+            CacheCodec::Zstd => ".zst",
+        }
+    }
+
+    // The codec this build can actually write
+    fn effective(self) -> CacheCodec {
+        if self == CacheCodec::Zstd && !cfg!(feature = "zstd") { CacheCodec::Gzip } else { self }
+    }
+}
+
+/// Speed/size trade-off for compressed cache files.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompressionLevel {
+    #[default]
+    Fast,
+    Best,
+}
+
+// Portable bundle of the whole cache (index metadata plus lyrics) for backup / moving machines
+#[derive(Serialize, Deserialize, Debug)]
+struct CacheArchive {
+    version: u32,
+    entries: Vec<ArchiveEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ArchiveEntry {
+    key: String,
+    last_accessed: u64,
+    stored_at: u64,
+    #[serde(default)]
+    source_url: Option<String>,
+    #[serde(default)]
+    pinned: bool,
+    #[serde(default)]
+    manual: bool,
+    // Readable name of the song ("artist-title"), if its file had one; archives don't hold the song names
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    slug: Option<String>,
+    lyrics: String,
+}
+
+/// Outcome of `import_archive`. Conflicts are songs present both locally and in the archive.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub added: usize,
+    pub updated: usize, // Conflicts where the archive copy was newer
+    pub kept_local: usize, // Conflicts where the local copy was newer (or equally new)
+}
+
+// Entry of the in-memory backend (same LRU bookkeeping as CacheEntry, but holding the lyrics)
+struct MemoryEntry {
+    lyrics: String,
+    last_accessed: u64,
+    stored_at: u64,
+    source_url: Option<String>,
+    pinned: bool,
+    manual: bool,
 }
 
 // --- Cache State ---
 // Using a simple Mutex for now. For heavy concurrency, RwLock might be better.
+static CACHE_MODE: Mutex<CacheMode> = Mutex::new(CacheMode::Disk);
+static FILE_NAMES: Mutex<CacheFileNames> = Mutex::new(CacheFileNames::Hashed);
+static COMPRESSION: Mutex<(CacheCodec, CompressionLevel)> = Mutex::new((CacheCodec::Plain, CompressionLevel::Fast));
 static CACHE_INDEX: Mutex<Option<CacheIndex>> = Mutex::new(None);
 static CACHE_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+static MEMORY_CACHE: Mutex<Option<HashMap<String, MemoryEntry>>> = Mutex::new(None);
+static MEMORY_SKIPPED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new()); // Skip list for memory mode (this session only)
+static DIR_NOTICE: Mutex<Option<String>> = Mutex::new(None); // Set when the cache had to move to a fallback directory
 
 // --- Helper Functions ---
 
+// Creates the directory if needed and checks that a file can actually be written there
+fn is_writable_dir(dir: &Path) -> bool {
+    let probe = dir.join(WRITE_PROBE_FILE_NAME);
+    let writable = fs::create_dir_all(dir).and_then(|_| fs::write(&probe, b"")).is_ok();
+    let _ = fs::remove_file(&probe);
+    writable
+}
+
+// First writable directory among the candidates, in order of preference
+fn pick_writable_dir(candidates: &[PathBuf]) -> Option<PathBuf> {
+    candidates.iter().find(|dir| is_writable_dir(dir)).cloned()
+}
+
 fn get_cache_dir() -> Result<PathBuf, io::Error> {
     let mut cache_dir_guard = CACHE_DIR.lock().unwrap();
     if let Some(ref path) = *cache_dir_guard {
         return Ok(path.clone());
     }
 
-    // Try to get user's cache directory or fallback to project dir
-    let base_path = dirs::cache_dir()
-        .unwrap_or_else(|| PathBuf::from(".")); // Fallback to current dir if system cache dir fails
-
-    let path = base_path.join(CACHE_DIR_NAME);
-    fs::create_dir_all(&path)?; // Ensure directory exists
+    // The user's cache directory, else the current dir, else the temp dir (locked-down systems)
+    let mut candidates: Vec<PathBuf> = dirs::cache_dir().into_iter().map(|base| base.join(CACHE_DIR_NAME)).collect();
+    candidates.push(PathBuf::from(".").join(CACHE_DIR_NAME));
+    candidates.push(std::env::temp_dir().join(CACHE_DIR_NAME));
+    let Some(path) = pick_writable_dir(&candidates) else {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("None of the cache directories are writable: {:?}", candidates)));
+    };
+    if path != candidates[0] {
+        let notice = format!("Cache directory {} isn't writable, using {} instead", candidates[0].display(), path.display());
+        eprintln!("{}", notice);
+        *DIR_NOTICE.lock().unwrap() = Some(notice);
+    }
     *cache_dir_guard = Some(path.clone());
     Ok(path)
 }
 
+/// The on-disk cache directory, if the disk cache is in use and a directory could be found.
+pub fn cache_dir() -> Option<PathBuf> {
+    if is_memory_mode() {
+        return None;
+    }
+    get_cache_dir().ok()
+}
 
 fn get_index_path() -> Result<PathBuf, io::Error> {
     Ok(get_cache_dir()?.join(INDEX_FILE_NAME))
@@ -68,6 +217,138 @@ fn generate_filename(key: &str) -> String {
     format!("{}.txt", key)
 }
 
+// Lowercase ASCII letters/digits (accented Latin letters folded to them), everything else collapsed into
+// single dashes, at most MAX_SLUG_LEN bytes
+fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c);
+        } else if let Some(folded) = fold_diacritic(c) {
+            slug.push_str(folded);
+        } else if ('\u{300}'..='\u{36f}').contains(&c) {
+            // Combining accent of a decomposed letter ("e" + U+0301): the letter was already taken
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.truncate(MAX_SLUG_LEN); // ASCII only, so any byte index is a char boundary
+    slug.trim_end_matches('-').to_string()
+}
+
+// ASCII spelling of a lowercase accented Latin letter, e.g. "é" -> "e", "ß" -> "ss"
+fn fold_diacritic(c: char) -> Option<&'static str> {
+    Some(match c {
+        'à'..='å' | 'ā' | 'ă' | 'ą' => "a",
+        'æ' => "ae",
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => "c",
+        'ð' | 'ď' | 'đ' => "d",
+        'è'..='ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => "e",
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' => "g",
+        'ì'..='ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => "i",
+        'ĺ' | 'ļ' | 'ľ' | 'ł' => "l",
+        'ñ' | 'ń' | 'ņ' | 'ň' => "n",
+        'ò'..='ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => "o",
+        'œ' => "oe",
+        'ŕ' | 'ŗ' | 'ř' => "r",
+        'ś' | 'ŝ' | 'ş' | 'š' => "s",
+        'ß' => "ss",
+        'ţ' | 'ť' | 'ŧ' => "t",
+        'þ' => "th",
+        'ù'..='ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => "u",
+        'ý' | 'ÿ' => "y",
+        'ź' | 'ż' | 'ž' => "z",
+        _ => return None,
+    })
+}
+
+fn song_slug(artists: &[String], title: &str) -> String {
+    slugify(&format!("{} {}", artists.join(" "), title))
+}
+
+// "<slug>-<hash prefix>.txt". Falls back to the hashed name when nothing readable is left (e.g. titles without Latin letters)
+fn readable_filename(key: &str, slug: &str) -> String {
+    if slug.is_empty() {
+        return generate_filename(key);
+    }
+    format!("{}-{}.txt", slug, &key[..SHORT_HASH_LEN])
+}
+
+// The slug of a readable file name ("artist-title" of "artist-title-ab12cd.txt.gz"); None for hashed names
+fn slug_from_filename<'a>(filename: &'a str, key: &str) -> Option<&'a str> {
+    let stem = filename.split('.').next()?;
+    stem.strip_suffix(&key[..SHORT_HASH_LEN])?.strip_suffix('-').filter(|slug| !slug.is_empty())
+}
+
+// Name for a new file of `key` under the FILE_NAMES setting. `slug` is the song's readable slug, if known;
+// without one the hashed name is used.
+fn cache_filename(index: &CacheIndex, key: &str, slug: Option<&str>, extension: &str) -> String {
+    match (*FILE_NAMES.lock().unwrap(), slug) {
+        (CacheFileNames::Readable, Some(slug)) => {
+            let readable = readable_filename(key, slug) + extension;
+            // The short hash makes clashes unlikely, but another song must never share the file
+            let taken = index.entries.iter().any(|(other_key, entry)| other_key != key && entry.filename == readable);
+            if taken { generate_filename(key) + extension } else { readable }
+        }
+        _ => generate_filename(key) + extension,
+    }
+}
+
+fn encode_lyrics(lyrics: &str, codec: CacheCodec, level: CompressionLevel) -> Result<Vec<u8>, io::Error> {
+    match codec {
+        CacheCodec::Plain => Ok(lyrics.as_bytes().to_vec()),
+        CacheCodec::Gzip => {
+            let compression = match level {
+                CompressionLevel::Fast => flate2::Compression::fast(),
+                CompressionLevel::Best => flate2::Compression::best(),
+            };
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), compression);
+            encoder.write_all(lyrics.as_bytes())?;
+            encoder.finish()
+        }
+        #[cfg(feature = "zstd")]
+        CacheCodec::Zstd => {
+            let zstd_level = match level {
+                CompressionLevel::Fast => 1,
+                CompressionLevel::Best => 19,
+            };
+            zstd::encode_all(lyrics.as_bytes(), zstd_level)
+        }
+        #[cfg(not(feature = "zstd"))]
+        CacheCodec::Zstd => Err(io::Error::new(io::ErrorKind::Unsupported, "Built without zstd support")),
+    }
+}
+
+fn decode_lyrics(bytes: &[u8], codec: CacheCodec) -> Result<String, io::Error> {
+    let decoded = match codec {
+        CacheCodec::Plain => bytes.to_vec(),
+        CacheCodec::Gzip => {
+            let mut decoded = Vec::new();
+            flate2::read::GzDecoder::new(bytes).read_to_end(&mut decoded)?;
+            decoded
+        }
+        #[cfg(feature = "zstd")]
+        CacheCodec::Zstd => zstd::decode_all(bytes)?,
+        #[cfg(not(feature = "zstd"))]
+        CacheCodec::Zstd => return Err(io::Error::new(io::ErrorKind::Unsupported, "Built without zstd support")),
+    };
+    String::from_utf8(decoded).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// Codec and level for newly written files, as far as this build supports them
+fn compression() -> (CacheCodec, CompressionLevel) {
+    let (codec, level) = *COMPRESSION.lock().unwrap();
+    (codec.effective(), level)
+}
+
+fn write_lyrics_file(path: &Path, lyrics: &str, (codec, level): (CacheCodec, CompressionLevel)) -> Result<(), io::Error> {
+    fs::write(path, encode_lyrics(lyrics, codec, level)?)
+}
+
+fn read_lyrics_file(path: &Path, codec: CacheCodec) -> Result<String, io::Error> {
+    decode_lyrics(&fs::read(path)?, codec)
+}
+
 fn get_current_timestamp() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -82,7 +363,47 @@ fn load_index() -> Result<CacheIndex, io::Error> {
     }
 
     let content = fs::read_to_string(&index_path)?;
-    serde_json::from_str(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    let (index, found_version, dropped) = parse_index(&content)?;
+    if found_version < INDEX_VERSION || dropped > 0 {
+        println!("Migrated cache index from version {} to {} ({} unreadable entries dropped).", found_version, INDEX_VERSION, dropped);
+        save_index(&index)?;
+    }
+    Ok(index)
+}
+
+// Reads index.json one entry at a time, so an older or newer index still loads: missing fields get their
+// defaults, unknown ones are ignored, and only entries that can't be read at all are dropped (and logged).
+// Returns the index, the version it was written with and the number of dropped entries.
+fn parse_index(content: &str) -> Result<(CacheIndex, u32, usize), io::Error> {
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+    let raw: serde_json::Value = serde_json::from_str(content).map_err(|e| invalid(e.to_string()))?;
+    let raw = raw.as_object().ok_or_else(|| invalid("Cache index is not a JSON object".to_string()))?;
+    let found_version = raw.get("version").and_then(serde_json::Value::as_u64).map_or(0, |version| version as u32);
+    if found_version > INDEX_VERSION {
+        eprintln!("Cache index version {} is newer than this app's ({}); fields it doesn't know are lost on the next write.", found_version, INDEX_VERSION);
+    }
+    let mut index = CacheIndex::default();
+    let mut dropped = 0;
+    if let Some(entries) = raw.get("entries").and_then(serde_json::Value::as_object) {
+        for (key, value) in entries {
+            match CacheEntry::deserialize(value) {
+                Ok(entry) => {
+                    index.entries.insert(key.clone(), entry);
+                }
+                Err(e) => {
+                    eprintln!("Dropping unreadable cache index entry {}: {}", key, e);
+                    dropped += 1;
+                }
+            }
+        }
+    }
+    if let Some(skipped) = raw.get("skipped") {
+        match BTreeSet::<String>::deserialize(skipped) {
+            Ok(skipped) => index.skipped = skipped,
+            Err(e) => eprintln!("Dropping unreadable skip list from the cache index: {}", e),
+        }
+    }
+    Ok((index, found_version, dropped))
 }
 
 fn save_index(index: &CacheIndex) -> Result<(), io::Error> {
@@ -94,10 +415,115 @@ fn save_index(index: &CacheIndex) -> Result<(), io::Error> {
     file.write_all(content.as_bytes())
 }
 
+// Key of the least recently used entry that isn't pinned, from (key, last_accessed, pinned)
+fn eviction_candidate<'a>(entries: impl Iterator<Item = (&'a String, u64, bool)>) -> Option<String> {
+    entries
+        .filter(|&(_, _, pinned)| !pinned)
+        .min_by_key(|&(_, last_accessed, _)| last_accessed)
+        .map(|(key, _, _)| key.clone())
+}
+
+// Evicts least recently used entries (and their files) until the index is within MAX_CACHE_ENTRIES.
+// Pinned entries are never evicted, so with enough pins the index may stay above the limit.
+fn evict_lru_entries(index: &mut CacheIndex, cache_dir: &Path) {
+    while index.entries.len() > MAX_CACHE_ENTRIES {
+        // BTreeMap iterates in sorted key order, but we need LRU (oldest timestamp)
+        let Some(evict_key) = eviction_candidate(index.entries.iter().map(|(key, entry)| (key, entry.last_accessed, entry.pinned))) else {
+            break;
+        };
+        println!("Cache limit reached. Evicting oldest entry: {}", evict_key);
+        if let Some(evicted_entry) = index.entries.remove(&evict_key) {
+             // Delete the associated lyrics file
+             let evict_file_path = cache_dir.join(evicted_entry.filename);
+             if let Err(e) = fs::remove_file(&evict_file_path) {
+                 eprintln!("Failed to delete evicted cache file '{}': {}", evict_file_path.display(), e);
+             }
+        }
+    }
+}
+
+fn is_memory_mode() -> bool {
+    *CACHE_MODE.lock().unwrap() == CacheMode::Memory
+}
+
+// --- In-Memory Backend ---
+
+fn memory_get(key: &str) -> Option<String> {
+    let mut memory_guard = MEMORY_CACHE.lock().unwrap();
+    let entry = memory_guard.as_mut()?.get_mut(key)?;
+    entry.last_accessed = get_current_timestamp();
+    Some(entry.lyrics.clone())
+}
+
+fn memory_store(key: String, lyrics: &str, source_url: Option<&str>) {
+    let timestamp = get_current_timestamp();
+    let mut memory_guard = MEMORY_CACHE.lock().unwrap();
+    let Some(entries) = memory_guard.as_mut() else {
+        eprintln!("Cache not initialized, cannot store lyrics.");
+        return;
+    };
+    let pinned = entries.get(&key).is_some_and(|entry| entry.pinned);
+    entries.insert(key, MemoryEntry {
+        lyrics: lyrics.to_string(),
+        last_accessed: timestamp,
+        stored_at: timestamp,
+        source_url: source_url.map(String::from),
+        pinned,
+        manual: false,
+    });
+
+    // Same LRU limit as the disk cache
+    if entries.len() > MAX_CACHE_ENTRIES
+        && let Some(evict_key) = eviction_candidate(entries.iter().map(|(key, entry)| (key, entry.last_accessed, entry.pinned)))
+    {
+        println!("Memory cache limit reached. Evicting oldest entry: {}", evict_key);
+        entries.remove(&evict_key);
+    }
+}
+
 // --- Public Cache API ---
 
-pub fn init_cache() -> Result<(), io::Error> {
-    println!("Initializing lyrics cache...");
+/// A one-time message for the UI if the cache had to fall back to another directory.
+pub fn take_dir_notice() -> Option<String> {
+    DIR_NOTICE.lock().unwrap().take()
+}
+
+/// Recovers the cache state after a panic while it was locked; the index and settings are still usable.
+pub fn clear_poison() {
+    CACHE_MODE.clear_poison();
+    FILE_NAMES.clear_poison();
+    COMPRESSION.clear_poison();
+    CACHE_INDEX.clear_poison();
+    CACHE_DIR.clear_poison();
+    MEMORY_CACHE.clear_poison();
+    MEMORY_SKIPPED.clear_poison();
+    DIR_NOTICE.clear_poison();
+}
+
+/// Sets how files for newly stored lyrics are named.
+pub fn set_file_names(file_names: CacheFileNames) {
+    *FILE_NAMES.lock().unwrap() = file_names;
+}
+
+/// Sets the compression of newly stored lyrics. Existing files keep their codec (it's stored in the index).
+pub fn set_compression(codec: CacheCodec, level: CompressionLevel) {
+    if codec.effective() != codec {
+        eprintln!("Built without zstd support, compressing the cache with gzip instead");
+    }
+    *COMPRESSION.lock().unwrap() = (codec, level);
+}
+
+pub fn init_cache(mode: CacheMode) -> Result<(), io::Error> {
+    println!("Initializing lyrics cache ({:?})...", mode);
+    *CACHE_MODE.lock().unwrap() = mode;
+    if mode == CacheMode::Memory {
+        let mut memory_guard = MEMORY_CACHE.lock().unwrap();
+        if memory_guard.is_none() {
+            *memory_guard = Some(HashMap::new());
+        }
+        return Ok(());
+    }
+
     let mut index_guard = CACHE_INDEX.lock().unwrap();
     if index_guard.is_some() {
         println!("Cache already initialized.");
@@ -112,6 +538,12 @@ pub fn init_cache() -> Result<(), io::Error> {
 
 pub fn get_lyrics_from_cache(artists: &[String], title: &str) -> Option<String> {
     let key = generate_key(artists, title);
+    if is_memory_mode() {
+        let lyrics = memory_get(&key);
+        let outcome = if lyrics.is_some() { "hit" } else { "miss" };
+        println!("Memory cache {} for: {} - {}", outcome, artists.join(", "), title);
+        return lyrics;
+    }
     let mut index_guard = CACHE_INDEX.lock().unwrap();
 
     if let Some(ref mut index) = *index_guard {
@@ -120,6 +552,7 @@ pub fn get_lyrics_from_cache(artists: &[String], title: &str) -> Option<String>
             // Update access time
             entry.last_accessed = get_current_timestamp();
             let filename = entry.filename.clone(); // Clone filename before saving index
+            let codec = entry.codec;
 
             // Save index immediately after updating timestamp
             if let Err(e) = save_index(index) {
@@ -131,11 +564,15 @@ pub fn get_lyrics_from_cache(artists: &[String], title: &str) -> Option<String>
             match get_cache_dir() {
                 Ok(cache_dir) => {
                     let file_path = cache_dir.join(filename);
-                    match fs::read_to_string(&file_path) {
+                    match read_lyrics_file(&file_path, codec) {
                         Ok(lyrics) => Some(lyrics),
                         Err(e) => {
                             eprintln!("Cache index points to file '{}', but failed to read it: {}", file_path.display(), e);
-                            // Consider removing the invalid entry here?
+                            // Drop the dangling entry so the song is fetched again instead of missing forever
+                            index.entries.remove(&key);
+                            if let Err(e) = save_index(index) {
+                                eprintln!("Error saving cache index after removing a broken entry: {}", e);
+                            }
                             None
                         }
                     }
@@ -155,44 +592,208 @@ pub fn get_lyrics_from_cache(artists: &[String], title: &str) -> Option<String>
     }
 }
 
-pub fn store_lyrics_to_cache(artists: &[String], title: &str, lyrics: &str) {
+/// Removes index entries whose lyrics file no longer exists. Returns how many were pruned.
+pub fn prune_missing_files() -> Result<usize, io::Error> {
+    if is_memory_mode() {
+        return Ok(0);
+    }
+    let mut index_guard = CACHE_INDEX.lock().unwrap();
+    let index = index_guard.as_mut().ok_or_else(|| io::Error::other("Cache not initialized"))?;
+    let cache_dir = get_cache_dir()?;
+
+    let before = index.entries.len();
+    index.entries.retain(|_, entry| cache_dir.join(&entry.filename).is_file());
+    let pruned = before - index.entries.len();
+    if pruned > 0 {
+        save_index(index)?;
+    }
+    println!("Cache verify: pruned {} entries with missing files", pruned);
+    Ok(pruned)
+}
+
+/// Whether the user turned lyrics off for this song.
+pub fn is_song_skipped(artists: &[String], title: &str) -> bool {
+    let key = generate_key(artists, title);
+    if is_memory_mode() {
+        return MEMORY_SKIPPED.lock().unwrap().contains(&key);
+    }
+    CACHE_INDEX.lock().unwrap().as_ref().is_some_and(|index| index.skipped.contains(&key))
+}
+
+/// Adds the song to (or removes it from) the skip list, persisted in the index.
+pub fn set_song_skipped(artists: &[String], title: &str, skipped: bool) {
+    let key = generate_key(artists, title);
+    if is_memory_mode() {
+        let mut skipped_keys = MEMORY_SKIPPED.lock().unwrap();
+        if skipped { skipped_keys.insert(key) } else { skipped_keys.remove(&key) };
+        return;
+    }
+    let mut index_guard = CACHE_INDEX.lock().unwrap();
+    let Some(index) = index_guard.as_mut() else {
+        eprintln!("Cache not initialized, cannot update the skip list.");
+        return;
+    };
+    let changed = if skipped { index.skipped.insert(key) } else { index.skipped.remove(&key) };
+    if changed {
+        println!("{} lyrics for: {} - {}", if skipped { "Skipping" } else { "Un-skipping" }, artists.join(", "), title);
+        if let Err(e) = save_index(index) {
+            eprintln!("Error saving cache index after updating the skip list: {}", e);
+        }
+    }
+}
+
+/// Whether the song's cached lyrics are pinned (exempt from eviction).
+pub fn is_song_pinned(artists: &[String], title: &str) -> bool {
+    let key = generate_key(artists, title);
+    if is_memory_mode() {
+        return MEMORY_CACHE.lock().unwrap().as_ref().and_then(|entries| entries.get(&key)).is_some_and(|entry| entry.pinned);
+    }
+    CACHE_INDEX.lock().unwrap().as_ref().and_then(|index| index.entries.get(&key)).is_some_and(|entry| entry.pinned)
+}
+
+/// Pins (or unpins) the song's cached lyrics, persisted in the index. Returns false if the song isn't cached.
+pub fn set_song_pinned(artists: &[String], title: &str, pinned: bool) -> bool {
+    let key = generate_key(artists, title);
+    if is_memory_mode() {
+        let mut memory_guard = MEMORY_CACHE.lock().unwrap();
+        let Some(entry) = memory_guard.as_mut().and_then(|entries| entries.get_mut(&key)) else {
+            return false;
+        };
+        entry.pinned = pinned;
+        return true;
+    }
+    let mut index_guard = CACHE_INDEX.lock().unwrap();
+    let Some(index) = index_guard.as_mut() else {
+        eprintln!("Cache not initialized, cannot pin lyrics.");
+        return false;
+    };
+    let Some(entry) = index.entries.get_mut(&key) else {
+        return false;
+    };
+    if entry.pinned != pinned {
+        entry.pinned = pinned;
+        println!("{} lyrics for: {} - {}", if pinned { "Pinned" } else { "Unpinned" }, artists.join(", "), title);
+        if let Err(e) = save_index(index) {
+            eprintln!("Error saving cache index after updating pins: {}", e);
+        }
+    }
+    true
+}
+
+/// Whether the song's cached lyrics were entered by the user (see `store_manual_lyrics`).
+pub fn is_song_manual(artists: &[String], title: &str) -> bool {
+    let key = generate_key(artists, title);
+    if is_memory_mode() {
+        return MEMORY_CACHE.lock().unwrap().as_ref().and_then(|entries| entries.get(&key)).is_some_and(|entry| entry.manual);
+    }
+    CACHE_INDEX.lock().unwrap().as_ref().and_then(|index| index.entries.get(&key)).is_some_and(|entry| entry.manual)
+}
+
+/// Stores lyrics the user typed or pasted, marked as manual and pinned so eviction never loses them.
+/// Returns false if they couldn't be stored.
+pub fn store_manual_lyrics(artists: &[String], title: &str, lyrics: &str) -> bool {
+    store_lyrics_to_cache(artists, title, lyrics, None);
+    let key = generate_key(artists, title);
+    if is_memory_mode() {
+        let mut memory_guard = MEMORY_CACHE.lock().unwrap();
+        let Some(entry) = memory_guard.as_mut().and_then(|entries| entries.get_mut(&key)) else {
+            return false;
+        };
+        entry.manual = true;
+        entry.pinned = true;
+        return true;
+    }
+    let mut index_guard = CACHE_INDEX.lock().unwrap();
+    let Some(index) = index_guard.as_mut() else {
+        return false;
+    };
+    let Some(entry) = index.entries.get_mut(&key) else {
+        return false; // The write failed (already logged)
+    };
+    entry.manual = true;
+    entry.pinned = true;
+    println!("Saved manual lyrics for: {} - {}", artists.join(", "), title);
+    if let Err(e) = save_index(index) {
+        eprintln!("Error saving cache index after storing manual lyrics: {}", e);
+    }
+    true
+}
+
+/// Stores lyrics found on the web, unless the song has lyrics the user entered (manual, including tap-synced
+/// ones): those are only replaced when the user confirms (see `store_lyrics_to_cache`). Returns whether they were stored.
+pub fn store_fetched_lyrics(artists: &[String], title: &str, lyrics: &str, source_url: Option<&str>) -> bool {
+    if is_song_manual(artists, title) {
+        println!("Keeping manual lyrics for: {} - {}", artists.join(", "), title);
+        return false;
+    }
+    store_lyrics_to_cache(artists, title, lyrics, source_url);
+    true
+}
+
+/// Returns how long ago the cached lyrics for this song were stored, if they are cached.
+pub fn get_cache_age(artists: &[String], title: &str) -> Option<Duration> {
     let key = generate_key(artists, title);
-    let filename = generate_filename(&key);
+    if is_memory_mode() {
+        let memory_guard = MEMORY_CACHE.lock().unwrap();
+        let entry = memory_guard.as_ref()?.get(&key)?;
+        return Some(Duration::from_secs(get_current_timestamp().saturating_sub(entry.stored_at)));
+    }
+    let index_guard = CACHE_INDEX.lock().unwrap();
+    let entry = index_guard.as_ref()?.entries.get(&key)?;
+    Some(Duration::from_secs(get_current_timestamp().saturating_sub(entry.stored_at)))
+}
+
+/// Returns the page the cached lyrics were fetched from, if it was recorded.
+pub fn get_cached_source_url(artists: &[String], title: &str) -> Option<String> {
+    let key = generate_key(artists, title);
+    if is_memory_mode() {
+        let memory_guard = MEMORY_CACHE.lock().unwrap();
+        return memory_guard.as_ref()?.get(&key)?.source_url.clone();
+    }
+    let index_guard = CACHE_INDEX.lock().unwrap();
+    index_guard.as_ref()?.entries.get(&key)?.source_url.clone()
+}
+
+pub fn store_lyrics_to_cache(artists: &[String], title: &str, lyrics: &str, source_url: Option<&str>) {
+    let key = generate_key(artists, title);
+    if is_memory_mode() {
+        memory_store(key, lyrics, source_url);
+        return;
+    }
     let timestamp = get_current_timestamp();
 
     let mut index_guard = CACHE_INDEX.lock().unwrap();
 
     if let Some(ref mut index) = *index_guard {
+         let compression = compression();
+         let extension = compression.0.extension();
+         let filename = cache_filename(index, &key, Some(&song_slug(artists, title)), extension);
          // Write the lyrics file first
          match get_cache_dir() {
             Ok(cache_dir) => {
                 let file_path = cache_dir.join(&filename);
-                match fs::write(&file_path, lyrics) {
-                    Ok(_) => {
+                match write_lyrics_file(&file_path, lyrics, compression) {
+                    Ok(()) => {
                          println!("Successfully wrote lyrics to cache file: {}", file_path.display());
                          // Now update the index
                          let new_entry = CacheEntry {
-                            filename,
+                            filename: filename.clone(),
                             last_accessed: timestamp,
+                            stored_at: timestamp,
+                            source_url: source_url.map(String::from),
+                            pinned: index.entries.get(&key).is_some_and(|entry| entry.pinned), // Corrections keep their pin
+                            manual: false,
+                            codec: compression.0,
                          };
-                         index.entries.insert(key, new_entry);
-
-                         // --- LRU Eviction ---
-                         if index.entries.len() > MAX_CACHE_ENTRIES {
-                            // BTreeMap iterates in sorted key order, but we need LRU (oldest timestamp)
-                            if let Some((evict_key, _)) = index.entries.iter().min_by_key(|(_, entry)| entry.last_accessed) {
-                                let evict_key = evict_key.clone(); // Clone key to remove later
-                                println!("Cache limit reached. Evicting oldest entry: {}", evict_key);
-                                if let Some(evicted_entry) = index.entries.remove(&evict_key) {
-                                     // Delete the associated lyrics file
-                                     let evict_file_path = cache_dir.join(evicted_entry.filename);
-                                     if let Err(e) = fs::remove_file(&evict_file_path) {
-                                         eprintln!("Failed to delete evicted cache file '{}': {}", evict_file_path.display(), e);
-                                     }
-                                }
-                            }
+                         // Re-stored under a different naming scheme: drop the old copy
+                         if let Some(old_entry) = index.entries.insert(key, new_entry)
+                             && old_entry.filename != filename
+                             && let Err(e) = fs::remove_file(cache_dir.join(&old_entry.filename))
+                         {
+                             eprintln!("Failed to delete old cache file '{}': {}", old_entry.filename, e);
                          }
-                         // --- End LRU Eviction ---
+
+                         evict_lru_entries(index, &cache_dir);
 
                          // Save the updated index
                          if let Err(e) = save_index(index) {
@@ -211,4 +812,328 @@ pub fn store_lyrics_to_cache(artists: &[String], title: &str, lyrics: &str) {
     } else {
         eprintln!("Cache not initialized, cannot store lyrics.");
     }
-}
\ No newline at end of file
+}
+
+/// Writes every cached song (metadata and lyrics) into a single JSON archive. Returns the entry count.
+pub fn export_archive(path: &Path) -> Result<usize, io::Error> {
+    if is_memory_mode() {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "Export is not available in memory-only cache mode"));
+    }
+    let index_guard = CACHE_INDEX.lock().unwrap();
+    let index = index_guard.as_ref().ok_or_else(|| io::Error::other("Cache not initialized"))?;
+    let cache_dir = get_cache_dir()?;
+
+    let mut entries = Vec::with_capacity(index.entries.len());
+    for (key, entry) in &index.entries {
+        match read_lyrics_file(&cache_dir.join(&entry.filename), entry.codec) {
+            Ok(lyrics) => entries.push(ArchiveEntry {
+                key: key.clone(),
+                last_accessed: entry.last_accessed,
+                stored_at: entry.stored_at,
+                source_url: entry.source_url.clone(),
+                pinned: entry.pinned,
+                manual: entry.manual,
+                slug: slug_from_filename(&entry.filename, key).map(String::from),
+                lyrics,
+            }),
+            Err(e) => eprintln!("Skipping cache entry {} in export, failed to read its file: {}", key, e),
+        }
+    }
+
+    let archive = CacheArchive { version: ARCHIVE_VERSION, entries };
+    let content = serde_json::to_string_pretty(&archive)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    fs::write(path, content)?;
+    println!("Exported {} cache entries to {}", archive.entries.len(), path.display());
+    Ok(archive.entries.len())
+}
+
+/// Merges a JSON archive into the cache. When a song exists on both sides, the newer copy wins.
+pub fn import_archive(path: &Path) -> Result<ImportReport, io::Error> {
+    if is_memory_mode() {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "Import is not available in memory-only cache mode"));
+    }
+    let content = fs::read_to_string(path)?;
+    let archive: CacheArchive = serde_json::from_str(&content)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Not a valid cache archive: {}", e)))?;
+    if archive.version != ARCHIVE_VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unsupported cache archive version {}", archive.version)));
+    }
+    // Keys become file names, so only accept what generate_key produces (hex SHA-256)
+    if let Some(bad) = archive.entries.iter().find(|entry| entry.key.len() != 64 || !entry.key.chars().all(|c| c.is_ascii_hexdigit())) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid cache key in archive: {:?}", bad.key)));
+    }
+
+    let mut index_guard = CACHE_INDEX.lock().unwrap();
+    let index = index_guard.as_mut().ok_or_else(|| io::Error::other("Cache not initialized"))?;
+    let cache_dir = get_cache_dir()?;
+
+    let mut report = ImportReport::default();
+    // On a failed write, what was imported until then stays: the index is saved either way, so it matches the files
+    let imported = import_entries(index, &cache_dir, archive.entries, &mut report);
+    evict_lru_entries(index, &cache_dir);
+    let saved = save_index(index);
+    imported?;
+    saved?;
+    println!("Imported cache archive {}: {:?}", path.display(), report);
+    Ok(report)
+}
+
+// Each entry's file is written before the index points to it, and the replaced file only removed after
+fn import_entries(index: &mut CacheIndex, cache_dir: &Path, entries: Vec<ArchiveEntry>, report: &mut ImportReport) -> Result<(), io::Error> {
+    for entry in entries {
+        let existing = index.entries.get_mut(&entry.key);
+        let pinned_locally = existing.as_ref().is_some_and(|local| local.pinned);
+        if let Some(local) = existing
+            && local.stored_at >= entry.stored_at
+        {
+            local.pinned |= entry.pinned;
+            report.kept_local += 1;
+            continue;
+        }
+
+        let compression = compression();
+        let filename = cache_filename(index, &entry.key, entry.slug.as_deref(), compression.0.extension());
+        write_lyrics_file(&cache_dir.join(&filename), &entry.lyrics, compression)?;
+        if index.entries.contains_key(&entry.key) {
+            report.updated += 1;
+        } else {
+            report.added += 1;
+        }
+        let replaced = index.entries.insert(entry.key, CacheEntry {
+            filename: filename.clone(),
+            last_accessed: entry.last_accessed,
+            stored_at: entry.stored_at,
+            source_url: entry.source_url,
+            pinned: entry.pinned || pinned_locally,
+            manual: entry.manual,
+            codec: compression.0,
+        });
+        // The local copy may have had another name or codec
+        if let Some(old_entry) = replaced
+            && old_entry.filename != filename
+            && let Err(e) = fs::remove_file(cache_dir.join(&old_entry.filename))
+        {
+            eprintln!("Failed to delete old cache file '{}': {}", old_entry.filename, e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::PoisonError;
+
+    // Tests that point the global cache at a temporary directory take turns
+    static CACHE_TEST_LOCK: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn dangling_entry_is_removed_on_access() {
+        let _turn = CACHE_TEST_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let dir = std::env::temp_dir().join(format!("lyricrs-cache-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        *CACHE_DIR.lock().unwrap() = Some(dir.clone());
+        let artists = vec!["Artist".to_string()];
+        let key = generate_key(&artists, "Gone");
+        let mut index = CacheIndex::default();
+        index.entries.insert(key.clone(), CacheEntry { filename: generate_filename(&key), last_accessed: 0, stored_at: 0, source_url: None, pinned: false, manual: false, codec: CacheCodec::Plain });
+        *CACHE_INDEX.lock().unwrap() = Some(index);
+
+        assert_eq!(get_lyrics_from_cache(&artists, "Gone"), None);
+        assert!(!CACHE_INDEX.lock().unwrap().as_ref().unwrap().entries.contains_key(&key));
+        let saved = load_index().unwrap();
+        assert!(!saved.entries.contains_key(&key));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refetch_keeps_manual_lyrics_until_confirmed() {
+        let _turn = CACHE_TEST_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let dir = std::env::temp_dir().join(format!("lyricrs-manual-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        *CACHE_DIR.lock().unwrap() = Some(dir.clone());
+        *CACHE_INDEX.lock().unwrap() = Some(CacheIndex::default());
+        let artists = vec!["Artist".to_string()];
+
+        assert!(store_manual_lyrics(&artists, "Obscure", "Typed by hand"));
+        assert!(!store_fetched_lyrics(&artists, "Obscure", "From the web", Some("https://genius.com/artist-obscure-lyrics")));
+        assert_eq!(get_lyrics_from_cache(&artists, "Obscure").as_deref(), Some("Typed by hand"));
+        assert!(is_song_manual(&artists, "Obscure") && is_song_pinned(&artists, "Obscure"));
+        // Replaced once the user confirms
+        store_lyrics_to_cache(&artists, "Obscure", "From the web", None);
+        assert_eq!(get_lyrics_from_cache(&artists, "Obscure").as_deref(), Some("From the web"));
+        assert!(!is_song_manual(&artists, "Obscure"));
+        // Web lyrics replace earlier web lyrics without asking
+        assert!(store_fetched_lyrics(&artists, "Obscure", "Corrected", None));
+        assert_eq!(get_lyrics_from_cache(&artists, "Obscure").as_deref(), Some("Corrected"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refetch_keeps_tap_synced_lyrics() {
+        let _turn = CACHE_TEST_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let dir = std::env::temp_dir().join(format!("lyricrs-tap-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        *CACHE_DIR.lock().unwrap() = Some(dir.clone());
+        *CACHE_INDEX.lock().unwrap() = Some(CacheIndex::default());
+        let artists = vec!["Artist".to_string()];
+        let lrc = crate::lyrics::tapped_lrc("First line\nSecond line", &[1500, 4250]);
+
+        // Saved the way finish_tap_sync saves it
+        assert!(store_manual_lyrics(&artists, "Tapped", &lrc));
+        assert!(!store_fetched_lyrics(&artists, "Tapped", "First line\nSecond line", None));
+        assert_eq!(get_lyrics_from_cache(&artists, "Tapped"), Some(lrc));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_import_keeps_the_index_in_step_with_the_files() {
+        let _turn = CACHE_TEST_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let dir = std::env::temp_dir().join(format!("lyricrs-import-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        *CACHE_DIR.lock().unwrap() = Some(dir.clone());
+        *CACHE_INDEX.lock().unwrap() = Some(CacheIndex::default());
+        set_file_names(CacheFileNames::Readable);
+        let first = generate_key(&["Artist".to_string()], "First");
+        let second = generate_key(&["Artist".to_string()], "Second");
+        // A directory where the second song's file goes makes its write fail
+        fs::create_dir_all(dir.join(generate_filename(&second))).unwrap();
+        let entry = |key: &str, slug: Option<&str>| ArchiveEntry {
+            key: key.to_string(),
+            last_accessed: 1,
+            stored_at: 1,
+            source_url: None,
+            pinned: false,
+            manual: false,
+            slug: slug.map(String::from),
+            lyrics: "Lyrics".to_string(),
+        };
+        let archive = CacheArchive { version: ARCHIVE_VERSION, entries: vec![entry(&first, Some("artist-first")), entry(&second, None)] };
+        let archive_path = dir.join("archive.json");
+        fs::write(&archive_path, serde_json::to_string(&archive).unwrap()).unwrap();
+
+        assert!(import_archive(&archive_path).is_err());
+        let saved = load_index().unwrap();
+        assert_eq!(saved.entries.len(), 1);
+        assert_eq!(saved.entries[&first].filename, format!("artist-first-{}.txt", &first[..SHORT_HASH_LEN]));
+        assert!(dir.join(&saved.entries[&first].filename).exists());
+
+        set_file_names(CacheFileNames::Hashed);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn version_0_index_loads_into_the_current_format() {
+        // As written by the first release: no version, entries with only a file name and access time
+        let v0 = r#"{"entries": {
+            "abc": {"filename": "abc.txt", "last_accessed": 1700000000},
+            "def": {"filename": "def.txt", "last_accessed": 1700000500, "some_future_field": true},
+            "bad": {"last_accessed": "yesterday"}
+        }}"#;
+        let (index, found_version, dropped) = parse_index(v0).unwrap();
+        assert_eq!((found_version, dropped), (0, 1));
+        assert_eq!(index.version, INDEX_VERSION);
+        assert_eq!(index.entries.len(), 2);
+        let entry = &index.entries["abc"];
+        assert_eq!((entry.filename.as_str(), entry.last_accessed, entry.stored_at), ("abc.txt", 1_700_000_000, 0));
+        assert!(!entry.pinned && entry.codec == CacheCodec::Plain);
+        assert!(index.skipped.is_empty());
+        // Written back with the version, it reads as current
+        let (reread, found_version, _) = parse_index(&serde_json::to_string(&index).unwrap()).unwrap();
+        assert_eq!((found_version, reread.entries.len()), (INDEX_VERSION, 2));
+        assert!(parse_index("[1, 2]").is_err());
+    }
+
+    #[test]
+    fn unwritable_cache_dir_falls_back_to_the_next_candidate() {
+        let base = std::env::temp_dir().join(format!("lyricrs-cache-dir-test-{}", std::process::id()));
+        fs::create_dir_all(&base).unwrap();
+        // A directory below a regular file can never be created, whoever runs the test (root ignores permissions)
+        let blocker = base.join("not-a-dir");
+        fs::write(&blocker, b"").unwrap();
+        let unwritable = blocker.join(CACHE_DIR_NAME);
+        let fallback = base.join("fallback");
+
+        assert!(!is_writable_dir(&unwritable));
+        assert_eq!(pick_writable_dir(&[unwritable.clone(), fallback.clone()]), Some(fallback.clone()));
+        assert!(!fallback.join(WRITE_PROBE_FILE_NAME).exists()); // The probe cleans up after itself
+        assert_eq!(pick_writable_dir(&[unwritable]), None);
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn eviction_skips_pinned_entries() {
+        let keys: Vec<String> = (0..4).map(|i| format!("key{}", i)).collect();
+        // The two oldest are pinned, so the oldest unpinned one goes
+        let entries = [(&keys[0], 1, true), (&keys[1], 2, true), (&keys[2], 4, false), (&keys[3], 3, false)];
+        assert_eq!(eviction_candidate(entries.into_iter()), Some("key3".to_string()));
+        // Nothing left to evict once everything is pinned
+        let entries = [(&keys[0], 1, true), (&keys[1], 2, true)];
+        assert_eq!(eviction_candidate(entries.into_iter()), None);
+    }
+
+    #[test]
+    fn readable_filenames_are_slugged_with_a_short_hash() {
+        let artists = vec!["Beyoncé".to_string()];
+        let key = generate_key(&artists, "Halo");
+        let filename = readable_filename(&key, &song_slug(&artists, "Halo"));
+        assert_eq!(filename, format!("beyonce-halo-{}.txt", &key[..SHORT_HASH_LEN]));
+        // Recovered on export, so an import can name the file the same way
+        assert_eq!(slug_from_filename(&format!("{}.gz", filename), &key), Some("beyonce-halo"));
+        assert_eq!(slugify("Motörhead - Straße"), "motorhead-strasse");
+        assert_eq!(slugify("Beyonce\u{301} ÆON"), "beyonce-aeon"); // Decomposed accent, uppercase ligature
+        assert_eq!(slug_from_filename(&generate_filename(&key), &key), None);
+    }
+
+    #[test]
+    fn readable_filenames_respect_length_limits_and_fall_back_to_hash() {
+        let artists = vec!["A".repeat(300)];
+        let key = generate_key(&artists, "Title");
+        let filename = readable_filename(&key, &song_slug(&artists, "Title"));
+        assert!(filename.len() <= MAX_SLUG_LEN + 1 + SHORT_HASH_LEN + ".txt".len());
+
+        let artists = vec!["아이유".to_string()];
+        let key = generate_key(&artists, "좋은 날");
+        assert_eq!(readable_filename(&key, &song_slug(&artists, "좋은 날")), generate_filename(&key));
+    }
+
+    fn assert_round_trip(codec: CacheCodec) {
+        let lyrics = "[Verse 1]\nNaïve line, 아이유\n\n[Chorus]\nRepeat ".repeat(20);
+        for level in [CompressionLevel::Fast, CompressionLevel::Best] {
+            let encoded = encode_lyrics(&lyrics, codec, level).unwrap();
+            assert_eq!(decode_lyrics(&encoded, codec).unwrap(), lyrics, "{:?} {:?}", codec, level);
+            if codec != CacheCodec::Plain {
+                assert!(encoded.len() < lyrics.len());
+            }
+        }
+    }
+
+    #[test]
+    fn plain_round_trip() {
+        assert_round_trip(CacheCodec::Plain);
+    }
+
+    #[test]
+    fn gzip_round_trip() {
+        assert_round_trip(CacheCodec::Gzip);
+        assert!(decode_lyrics(b"not gzip", CacheCodec::Gzip).is_err());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_round_trip() {
+        assert_round_trip(CacheCodec::Zstd);
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn zstd_falls_back_to_gzip_without_the_feature() {
+        assert_eq!(CacheCodec::Zstd.effective(), CacheCodec::Gzip);
+        assert!(decode_lyrics(b"anything", CacheCodec::Zstd).is_err());
+    }
+}
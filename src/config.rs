@@ -0,0 +1,466 @@
+// Persistent user settings (stored as JSON in the user's config directory)
+use std::{
+    fs,
+    io,
+    path::PathBuf,
+    time::Duration,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::cache::{CacheCodec, CacheFileNames, CacheMode, CompressionLevel};
+use crate::lyrics;
+use crate::nowplaying::SourceKind;
+use crate::playlog::PlayLogFormat;
+use crate::spotify::PlaybackEndpoint;
+
+const CONFIG_DIR_NAME: &str = "lyricrs";
+const SETTINGS_FILE_NAME: &str = "settings.json";
+const HIGH_CONTRAST_FONT_SIZE: f32 = 24.0; // Minimum lyrics size the high-contrast preset sets
+const LINE_OUTPUT_FILE_NAME: &str = "current_line.txt"; // Default target of the line output
+
+/// What the lyrics area shows when there are no lyrics (nothing playing, or none found).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlaceholderMode {
+    #[default]
+    Off, // Leave the area empty (errors are shown as text)
+    Message, // Custom message / ASCII art
+    AlbumArt, // The current song's cover, as large as fits
+}
+
+/// How the blank lines between stanzas are rendered.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LyricsSpacing {
+    Compact, // Every line directly below the previous one
+    #[default]
+    Spaced, // One blank line between stanzas
+}
+
+/// Where the current synced line is kept when the lyrics follow playback.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScrollAnchor {
+    Top,
+    #[default]
+    Center,
+    Bottom,
+}
+
+/// Whether synced (LRC) timestamps are used when the lyrics have them.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LyricsMode {
+    #[default]
+    Auto, // Synced when timestamps are available, plain otherwise
+    Synced, // Same, but point out when a song only has plain lyrics
+    Plain, // Ignore timestamps: no highlighting, just text to read
+}
+
+/// What the lyrics do while playback is paused.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PausedLyrics {
+    #[default]
+    Keep, // Shown as usual
+    Dim, // Shown faded
+    Hide, // Replaced by a "Paused" note until playback resumes
+}
+
+/// Direction of lyric lines. Auto right-aligns lines written mostly in RTL scripts (Arabic, Hebrew).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextDirection {
+    #[default]
+    Auto,
+    LeftToRight,
+    RightToLeft,
+}
+
+/// How the window height is chosen. Manual leaves it to the user and restores their last size on start;
+/// FitContent grows and shrinks the window with the lyrics, e.g. for a thin single-line strip.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WindowSizing {
+    #[default]
+    #[serde(alias = "Fixed")] // Earlier name, which suggested a locked size
+    Manual,
+    FitContent,
+}
+
+/// Unit of `font_size`. Logical points are scaled by each monitor's display scaling (egui's default), so the
+/// pixel size changes when the window moves to a differently scaled monitor; physical pixels stay the same size.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FontUnits {
+    #[default]
+    Logical,
+    Physical,
+}
+
+// Missing fields fall back to their defaults, so older settings files keep loading
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Settings {
+    pub high_contrast: bool, // Accessibility theme: solid black, white bold text, yellow highlight; overrides opacity
+    pub opacity: f32, // Window opacity while focused or hovered (0.0 to 1.0)
+    pub fade_when_idle: bool, // Fade to idle_opacity while the window is unfocused and not hovered
+    pub idle_opacity: f32,
+    pub auto_hide_when_idle: bool, // Hide the window once nothing has played for auto_hide_after_secs; the next track shows it again
+    pub auto_hide_after_secs: u64,
+    pub now_playing_osd: bool, // Pop up the new song's cover, title and artists for a few seconds on song change
+    pub now_playing_osd_secs: u64,
+    pub lyrics_padding: f32, // Inner margin around the lyrics scroll area (points)
+    pub lyrics_background: Option<[u8; 4]>, // RGBA fill for the lyrics area only; None = window background
+    pub keep_lyrics_on_change: bool, // Dim the old lyrics on song change instead of clearing them
+    pub font_size: f32, // Lyrics text size (points, or pixels with FontUnits::Physical)
+    pub font_units: FontUnits,
+    pub lyrics_spacing: LyricsSpacing,
+    pub lyrics_mode: LyricsMode,
+    pub paused_lyrics: PausedLyrics,
+    pub max_text_width: Option<f32>, // Wrap lyrics at this width (points), centered; None = window width
+    pub window_sizing: WindowSizing,
+    pub window_size: Option<[f32; 2]>, // Last inner size in Manual mode (points); None = default size
+    pub text_direction: TextDirection, // Manual override for ambiguous (mixed-script) lyrics
+    pub lyrics_format: lyrics::LyricsFormat, // Display-only clean-up (spaces, trailing whitespace, capitals)
+    pub single_line_mode: bool, // Show only the current synced line (for a thin overlay strip)
+    pub ticker_speed: f32, // Marquee speed for single-line mode when the line doesn't fit (points per second)
+    pub artist_separator: String, // Joins artist names in displayed text only (cache keys and Genius slugs are unaffected)
+    pub bold_lyrics: bool, // Render lyrics with egui's strong style (egui can't set the font's weight axis)
+    pub mark_annotations: bool, // Underline lines Genius annotates (kept for lyrics fetched while this is on)
+    pub show_timestamps: bool, // Prefix synced lines with their dimmed [mm:ss] start time
+    pub karaoke_wipe: bool, // Fill the current synced line left to right as it's sung
+    pub line_fade: bool, // Fade a synced line and its highlight in as it becomes current (off = instant switch)
+    pub line_fade_ms: u32,
+    pub scroll_anchor: ScrollAnchor, // Position of the highlighted line for auto-scroll and re-center
+    pub lyrics_selectors: Vec<String>, // CSS selectors for Genius lyrics containers, tried in order
+    pub promo_phrases: Vec<String>, // Genius promo text stripped from lyrics (`*` = any text within the line)
+    pub slug_strip_tags: Vec<String>, // Trailing title tags like "(Explicit)" left out of Genius page slugs
+    pub min_match_confidence: f32, // Reject search results that match the song less than this (0.0 to 1.0)
+    pub preferred_lyrics_language: String, // Language tag like "en" for providers that offer versions; empty = any
+    pub slug_all_artists: bool, // Use every artist (up to max_slug_artists) in the Genius URL instead of only the primary one
+    pub max_slug_artists: usize, // Cap on artists in the Genius page slug (big collaborations rarely list them all)
+    pub retry_with_featured_artists: bool, // Retry a missed lookup with "(feat. X)" from the title added to the artists
+    pub best_match: bool, // Compare the first few successful lookups and keep the longest (slower)
+    pub genius_base_url: String, // Site root for lyrics pages, e.g. a mirror (default genius.com)
+    pub genius_alternate_hosts: Vec<String>, // Regional/other Genius sites tried for the same page when the base URL 404s
+    pub selector_update_url: String, // Optional list of working lyrics selectors fetched at startup; empty = off
+    pub cache_mode: CacheMode, // Disk (persistent) or memory-only (ephemeral, this session only)
+    pub cache_file_names: CacheFileNames, // Naming of newly written cache files
+    pub cache_codec: CacheCodec, // Compression of newly written cache files (existing ones keep theirs)
+    pub cache_compression_level: CompressionLevel,
+    pub verify_cache_on_start: bool, // Prune index entries whose files are gone when the app starts
+    pub refresh_stale: bool, // Re-fetch cached lyrics in the background once they're older than `stale_after_days`
+    pub stale_after_days: u32,
+    pub now_playing_source: SourceKind, // Spotify, last.fm, MPRIS, the Spotify app (macOS) or Remote (applies after restart)
+    pub fallback_sources: Vec<SourceKind>, // Tried in this order when the main source fails, e.g. [Mpris] for API hiccups
+    pub lastfm_user: String,
+    pub lastfm_api_key: String, // Empty = LASTFM_API_KEY from .env
+    pub playback_endpoint: PlaybackEndpoint, // Spotify endpoint/scope used for polling (applies after restart)
+    pub use_keyring: bool, // Keep Spotify credentials and the token in the OS keyring (needs the `keyring` feature, applies after restart)
+    pub placeholder: PlaceholderMode,
+    pub placeholder_text: String, // Shown in monospace for PlaceholderMode::Message
+    pub preferred_device: Option<(String, String)>, // (id, name) of the device to follow; None = active device
+    pub show_up_next: bool, // Show the next queued track (needs extra scopes, applies after restart)
+    pub album_prefetch: bool, // When playing through an album, fetch lyrics for its next tracks in the background
+    pub album_prefetch_tracks: usize, // How many of the following tracks to prefetch (1-10)
+    pub fetching_paused: bool, // Keep polling Spotify but skip all lyrics lookups (cache and web)
+    pub startup_poll: bool, // Poll Spotify once before the window opens so the current song shows immediately
+    pub show_volume: bool, // Small volume indicator under the song title
+    pub volume_control: bool, // Turn the indicator into a slider (needs an extra scope, applies after restart)
+    pub reset_on_repeat: bool, // Jump the lyrics back to the top when the same song restarts
+    pub fetch_deadline_secs: u64, // Give up on a song's lyrics after this long
+    pub auto_retry_failed: bool, // Retry a failed lyrics fetch while the same song keeps playing
+    pub auto_retry_delay_secs: u64, // Wait between automatic retries
+    pub auto_retry_max: u32, // Automatic retries per song before leaving it failed
+    pub poll_jitter_ms: u64, // Random ± offset on the poll interval, so instances don't hit Spotify in sync (0 = off)
+    pub max_fetch_attempts: usize, // Lookups tried per song before reporting "not found"
+    pub play_log: bool, // Append every detected song to the listening log file
+    pub play_log_format: PlayLogFormat,
+    pub history_auto_return: bool, // Leave the history view when a new song starts playing
+    pub line_output: bool, // Write the current synced line to line_output_path (e.g. for a Stream Deck)
+    pub line_output_path: String, // File or named pipe; empty = current_line.txt in the config dir
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            high_contrast: false,
+            opacity: 1.0,
+            fade_when_idle: false,
+            idle_opacity: 0.3,
+            auto_hide_when_idle: false,
+            auto_hide_after_secs: 120,
+            now_playing_osd: false,
+            now_playing_osd_secs: 4,
+            lyrics_padding: 8.0,
+            lyrics_background: None,
+            keep_lyrics_on_change: false,
+            font_size: 14.0,
+            font_units: FontUnits::Logical,
+            lyrics_spacing: LyricsSpacing::Spaced,
+            lyrics_mode: LyricsMode::Auto,
+            paused_lyrics: PausedLyrics::Keep,
+            max_text_width: None,
+            window_sizing: WindowSizing::Manual,
+            window_size: None,
+            text_direction: TextDirection::Auto,
+            lyrics_format: lyrics::LyricsFormat::default(),
+            single_line_mode: false,
+            ticker_speed: 40.0,
+            artist_separator: ", ".to_string(),
+            bold_lyrics: false,
+            mark_annotations: false,
+            show_timestamps: false,
+            karaoke_wipe: true,
+            line_fade: true,
+            line_fade_ms: 200,
+            scroll_anchor: ScrollAnchor::Center,
+            lyrics_selectors: lyrics::DEFAULT_LYRICS_SELECTORS.iter().map(|s| s.to_string()).collect(),
+            promo_phrases: lyrics::DEFAULT_PROMO_PHRASES.iter().map(|s| s.to_string()).collect(),
+            slug_strip_tags: lyrics::DEFAULT_SLUG_STRIP_TAGS.iter().map(|s| s.to_string()).collect(),
+            min_match_confidence: lyrics::DEFAULT_MIN_MATCH_CONFIDENCE,
+            preferred_lyrics_language: String::new(),
+            slug_all_artists: false,
+            max_slug_artists: lyrics::DEFAULT_MAX_SLUG_ARTISTS,
+            retry_with_featured_artists: true,
+            best_match: false,
+            genius_base_url: lyrics::GENIUS_BASE_URL.to_string(),
+            genius_alternate_hosts: Vec::new(),
+            selector_update_url: String::new(),
+            cache_mode: CacheMode::Disk,
+            cache_file_names: CacheFileNames::Hashed,
+            cache_codec: CacheCodec::Plain,
+            cache_compression_level: CompressionLevel::Fast,
+            verify_cache_on_start: false,
+            refresh_stale: false,
+            stale_after_days: 30,
+            now_playing_source: SourceKind::Spotify,
+            fallback_sources: Vec::new(),
+            lastfm_user: String::new(),
+            lastfm_api_key: String::new(),
+            playback_endpoint: PlaybackEndpoint::PlaybackState,
+            use_keyring: false,
+            placeholder: PlaceholderMode::Off,
+            placeholder_text: "♪ No lyrics ♪".to_string(),
+            preferred_device: None,
+            show_up_next: false,
+            album_prefetch: false,
+            album_prefetch_tracks: 3,
+            fetching_paused: false,
+            startup_poll: true,
+            show_volume: false,
+            volume_control: false,
+            reset_on_repeat: true,
+            fetch_deadline_secs: 10,
+            auto_retry_failed: false,
+            auto_retry_delay_secs: 30,
+            auto_retry_max: 3,
+            poll_jitter_ms: 500,
+            max_fetch_attempts: 3,
+            play_log: false,
+            play_log_format: PlayLogFormat::Jsonl,
+            history_auto_return: true,
+            line_output: false,
+            line_output_path: String::new(),
+        }
+    }
+}
+
+impl Settings {
+    /// Where the line output writes: the configured path, or current_line.txt in the config dir.
+    pub fn line_output_path(&self) -> Option<PathBuf> {
+        let configured = self.line_output_path.trim();
+        if configured.is_empty() {
+            config_dir().ok().map(|dir| dir.join(LINE_OUTPUT_FILE_NAME))
+        } else {
+            Some(PathBuf::from(configured))
+        }
+    }
+
+    /// The configured Genius base URL, or genius.com if it's blank or not an http(s) URL.
+    pub fn genius_base_url(&self) -> &str {
+        let url = self.genius_base_url.trim();
+        if url.starts_with("http://") || url.starts_with("https://") {
+            url
+        } else {
+            lyrics::GENIUS_BASE_URL
+        }
+    }
+
+    /// The alternate Genius hosts that are http(s) URLs, without duplicates or the base URL itself.
+    pub fn genius_alternate_hosts(&self) -> Vec<String> {
+        let base = self.genius_base_url().trim_end_matches('/');
+        let mut hosts: Vec<String> = Vec::new();
+        for host in &self.genius_alternate_hosts {
+            let host = host.trim().trim_end_matches('/');
+            if (host.starts_with("http://") || host.starts_with("https://")) && host != base && !hosts.iter().any(|h| h == host) {
+                hosts.push(host.to_string());
+            }
+        }
+        hosts
+    }
+
+    /// Turns on high contrast and the settings that go with it: large bold text, no transparency or fading,
+    /// no separate lyrics background. Turning high contrast off later keeps these, so they can be tuned.
+    pub fn apply_high_contrast_preset(&mut self) {
+        self.high_contrast = true;
+        self.font_size = self.font_size.max(HIGH_CONTRAST_FONT_SIZE);
+        self.bold_lyrics = true;
+        self.opacity = 1.0;
+        self.fade_when_idle = false;
+        self.lyrics_background = None;
+    }
+
+    /// Lyrics text size in egui points on a display with this scale factor.
+    pub fn lyrics_font_size(&self, pixels_per_point: f32) -> f32 {
+        match self.font_units {
+            FontUnits::Logical => self.font_size,
+            FontUnits::Physical => self.font_size / pixels_per_point.max(0.1),
+        }
+    }
+
+    /// Artist names joined for display, e.g. "A, B", "A & B" or "A feat. B".
+    pub fn display_artists(&self, artists: &[String]) -> String {
+        artists.join(&self.artist_separator)
+    }
+
+    pub fn fetch_options(&self) -> lyrics::FetchOptions {
+        lyrics::FetchOptions {
+            base_url: self.genius_base_url().to_string(),
+            alternate_hosts: self.genius_alternate_hosts(),
+            selectors: lyrics::merge_selectors(&lyrics::updated_selectors(), &self.lyrics_selectors),
+            promo_phrases: self.promo_phrases.clone(),
+            min_confidence: self.min_match_confidence.clamp(0.0, 1.0),
+            retry_with_features: self.retry_with_featured_artists,
+            max_slug_artists: self.max_slug_artists.max(1),
+            best_match: self.best_match,
+            mark_annotations: self.mark_annotations,
+            preferred_language: Some(self.preferred_lyrics_language.trim()).filter(|language| !language.is_empty()).map(String::from),
+            deadline: Duration::from_secs(self.fetch_deadline_secs),
+            max_attempts: self.max_fetch_attempts,
+        }
+    }
+}
+
+// --- Helper Functions ---
+
+/// The app's config directory (created if needed), also home to the listening log.
+pub fn config_dir() -> Result<PathBuf, io::Error> {
+    // Fall back to the current dir if the system config dir is unavailable (same as the cache)
+    let base_path = dirs::config_dir().unwrap_or_else(|| PathBuf::from("."));
+    let dir = base_path.join(CONFIG_DIR_NAME);
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+fn get_settings_path() -> Result<PathBuf, io::Error> {
+    Ok(config_dir()?.join(SETTINGS_FILE_NAME))
+}
+
+// --- Public Config API ---
+
+/// Loads saved settings, falling back to defaults if the file is missing or unreadable.
+pub fn load_settings() -> Settings {
+    let path = match get_settings_path() {
+        Ok(path) => path,
+        Err(e) => {
+            eprintln!("Failed to locate settings file, using defaults: {}", e);
+            return Settings::default();
+        }
+    };
+    if !path.exists() {
+        return Settings::default();
+    }
+
+    match fs::read_to_string(&path).map(|content| serde_json::from_str(&content)) {
+        Ok(Ok(settings)) => {
+            println!("Loaded settings from {}", path.display());
+            settings
+        }
+        Ok(Err(e)) => {
+            eprintln!("Settings file '{}' is invalid, using defaults: {}", path.display(), e);
+            Settings::default()
+        }
+        Err(e) => {
+            eprintln!("Failed to read settings file '{}', using defaults: {}", path.display(), e);
+            Settings::default()
+        }
+    }
+}
+
+pub fn save_settings(settings: &Settings) {
+    let result = get_settings_path().and_then(|path| {
+        let content = serde_json::to_string_pretty(settings)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(&path, content)
+    });
+    if let Err(e) = result {
+        eprintln!("Failed to save settings: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fetch_options_use_configured_base_url() {
+        let settings = Settings { genius_base_url: " http://127.0.0.1:8080/ ".to_string(), ..Settings::default() };
+        assert_eq!(settings.fetch_options().base_url, "http://127.0.0.1:8080/");
+    }
+
+    #[test]
+    fn alternate_hosts_skip_invalid_and_duplicate_entries() {
+        let settings = Settings {
+            genius_alternate_hosts: vec![
+                "https://genius.com/".to_string(),
+                " https://genius.example.jp/ ".to_string(),
+                "genius.example.de".to_string(),
+                "https://genius.example.jp".to_string(),
+            ],
+            ..Settings::default()
+        };
+        assert_eq!(settings.fetch_options().alternate_hosts, vec!["https://genius.example.jp"]);
+    }
+
+    #[test]
+    fn invalid_base_url_falls_back_to_genius() {
+        for url in ["", "   ", "genius.com", "ftp://example.com"] {
+            let settings = Settings { genius_base_url: url.to_string(), ..Settings::default() };
+            assert_eq!(settings.fetch_options().base_url, lyrics::GENIUS_BASE_URL);
+        }
+    }
+
+    #[test]
+    fn physical_font_size_keeps_pixels_constant() {
+        let settings = Settings { font_size: 24.0, ..Settings::default() };
+        assert_eq!(settings.lyrics_font_size(2.0), 24.0); // Logical: 48 px on a 200% display
+        let settings = Settings { font_units: FontUnits::Physical, ..settings };
+        assert_eq!(settings.lyrics_font_size(1.0), 24.0);
+        assert_eq!(settings.lyrics_font_size(2.0), 12.0); // Still 24 px
+    }
+
+    #[test]
+    fn display_artists_uses_configured_separator() {
+        let artists = vec!["Beyoncé".to_string(), "Jay-Z".to_string()];
+        assert_eq!(Settings::default().display_artists(&artists), "Beyoncé, Jay-Z");
+        let settings = Settings { artist_separator: " feat. ".to_string(), ..Settings::default() };
+        assert_eq!(settings.display_artists(&artists), "Beyoncé feat. Jay-Z");
+    }
+
+    #[test]
+    fn high_contrast_preset_only_grows_text() {
+        let mut settings = Settings { font_size: 32.0, opacity: 0.4, fade_when_idle: true, ..Settings::default() };
+        settings.apply_high_contrast_preset();
+        assert!(settings.high_contrast && settings.bold_lyrics && !settings.fade_when_idle);
+        assert_eq!(settings.opacity, 1.0);
+        assert_eq!(settings.font_size, 32.0);
+        let mut settings = Settings::default();
+        settings.apply_high_contrast_preset();
+        assert_eq!(settings.font_size, HIGH_CONTRAST_FONT_SIZE);
+    }
+
+    #[test]
+    fn older_settings_files_get_new_defaults() {
+        let settings: Settings = serde_json::from_str(r#"{ "font_size": 20.0 }"#).unwrap();
+        assert_eq!(settings.font_size, 20.0);
+        assert_eq!(settings.genius_base_url, lyrics::GENIUS_BASE_URL);
+        assert!(!settings.refresh_stale);
+        assert_eq!(settings.stale_after_days, 30);
+        let settings: Settings = serde_json::from_str(r#"{ "window_sizing": "Fixed" }"#).unwrap();
+        assert_eq!(settings.window_sizing, WindowSizing::Manual);
+    }
+}
@@ -1,16 +1,61 @@
 use reqwest;
-use scraper::{Html, Selector, Node};
+use scraper::{ElementRef, Html, Selector, Node};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+pub const GENIUS_BASE_URL: &str = "https://genius.com"; // Lyrics pages live directly under this
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(30); // Never wait longer than this for a single retry
+const RATE_LIMIT_COOLDOWN: Duration = Duration::from_secs(60); // Back off this long if the retry is throttled too
+pub const DEFAULT_MIN_MATCH_CONFIDENCE: f32 = 0.5;
+pub const DEFAULT_MAX_SLUG_ARTISTS: usize = 3; // Genius slugs rarely credit more; longer ones are guaranteed 404s
+const BEST_MATCH_CANDIDATES: usize = 2; // "Best match" compares this many successful lookups at most
+
+// Requests to a host (by origin) are held back until this instant after it answers 429
+static RATE_LIMITED_UNTIL: Mutex<BTreeMap<String, Instant>> = Mutex::new(BTreeMap::new());
 
 // --- Genius URL Formatting ---
 
+/// Title tags left out of Genius page slugs, e.g. "Song (Explicit)" -> "song". Only a whole parenthetical or
+/// bracketed tag at the end is removed, so a song called "Explicit Content" keeps its name.
+pub const DEFAULT_SLUG_STRIP_TAGS: &[&str] = &["explicit", "explicit version", "clean", "clean version"];
+
+// The configured strip list; None until set (DEFAULT_SLUG_STRIP_TAGS)
+static SLUG_STRIP_TAGS: Mutex<Option<Vec<String>>> = Mutex::new(None);
+
+/// Sets the trailing title tags left out of slugs (and ignored when comparing search results).
+pub fn set_slug_strip_tags(tags: &[String]) {
+    *SLUG_STRIP_TAGS.lock().unwrap() = Some(tags.to_vec());
+}
+
+/// Recovers the fetch state (rate limits, strip tags, selector update) after a panic while it was locked.
+pub fn clear_poison() {
+    RATE_LIMITED_UNTIL.clear_poison();
+    SLUG_STRIP_TAGS.clear_poison();
+    UPDATED_SELECTORS.clear_poison();
+}
+
+// Removes "(feat. X)"-style credits anywhere and the listed tags at the end ("Song (Explicit) [Clean]").
+fn strip_title_tags(input: &str, tags: &[String]) -> String {
+    let re_featuring = Regex::new(r"\s*[(\[](?:feat\.?|ft\.?|featuring|with)\s[^)\]]*[)\]]").unwrap();
+    let mut stripped = re_featuring.replace_all(input, " ").trim_end().to_string();
+    let alternatives: Vec<String> = tags.iter().map(|tag| tag.trim().to_lowercase()).filter(|tag| !tag.is_empty()).map(|tag| regex::escape(&tag)).collect();
+    if !alternatives.is_empty() {
+        let re_tags = Regex::new(&format!(r"(?i)(?:\s*[(\[](?:{})[)\]])+\s*$", alternatives.join("|"))).unwrap();
+        stripped = re_tags.replace(&stripped, "").to_string();
+    }
+    stripped
+}
+
 // Formats a single component (artist name or title) for the Genius URL path.
 fn format_genius_path_component(input: &str) -> String {
-    let mut processed = input.to_lowercase();
-
-    // Remove specific unwanted parentheticals like (feat.), (with), (explicit)
-    let re_unwanted_paren = Regex::new(r"\s*\((feat|ft|with|explicit)[^)]*\)\s*").unwrap();
-    processed = re_unwanted_paren.replace_all(&processed, "").to_string();
+    let tags = SLUG_STRIP_TAGS.lock().unwrap().clone().unwrap_or_else(|| DEFAULT_SLUG_STRIP_TAGS.iter().map(|s| s.to_string()).collect());
+    let mut processed = strip_title_tags(&input.to_lowercase(), &tags);
 
     // Remove specific common suffixes like "- radio edit", "- live version"
     let re_suffix = Regex::new(r"\s+-\s+(radio edit|live|acoustic|version|edit|mix)\b.*").unwrap();
@@ -34,11 +79,12 @@ fn format_genius_path_component(input: &str) -> String {
     processed
 }
 
-// Builds the Genius URL using a list of artists.
-fn build_genius_url(artists: &[String], title: &str) -> String {
+// Builds the Genius URL using the first `max_artists` of a list of artists, under `base_url` (normally GENIUS_BASE_URL).
+pub fn build_genius_url(base_url: &str, artists: &[String], title: &str, max_artists: usize) -> String {
     // Format each artist name individually
     let formatted_artist_names: Vec<String> = artists
         .iter()
+        .take(max_artists.max(1))
         .map(|a| format_genius_path_component(a))
         .collect();
 
@@ -49,104 +95,993 @@ fn build_genius_url(artists: &[String], title: &str) -> String {
     let formatted_title = format_genius_path_component(title);
 
     // Combine for the final URL
-    format!("https://genius.com/{}-{}-lyrics", joined_artists, formatted_title)
+    format!("{}/{}-{}-lyrics", base_url.trim_end_matches('/'), joined_artists, formatted_title)
+}
+
+// Artists credited in a "(feat. X & Y)" / "(with X)" parenthetical of a title, in order
+fn featured_artists_in_title(title: &str) -> Vec<String> {
+    let re_featured = Regex::new(r"(?i)[(\[]\s*(?:feat\.?|ft\.?|featuring|with)\s+([^)\]]+)[)\]]").unwrap();
+    let re_separator = Regex::new(r"(?i)\s*(?:,|&|\band\b)\s*").unwrap();
+    re_featured
+        .captures_iter(title)
+        .flat_map(|captures| re_separator.split(&captures[1]).map(|name| name.trim().to_string()).collect::<Vec<_>>())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// The artist list for a retry with the title's featured artists appended, or None if the title has
+/// none the list doesn't already contain (Spotify often only credits them in the title).
+pub fn artists_with_title_features(artists: &[String], title: &str) -> Option<Vec<String>> {
+    let mut extended = artists.to_vec();
+    for featured in featured_artists_in_title(title) {
+        if !extended.iter().any(|artist| artist.eq_ignore_ascii_case(&featured)) {
+            extended.push(featured);
+        }
+    }
+    (extended.len() > artists.len()).then_some(extended)
 }
 
 // --- HTML Fetching & Parsing --- (Keep fetch_lyrics_html and parse_and_extract_genius_lyrics as they are)
 
-async fn fetch_lyrics_html(url: &str) -> Result<String, reqwest::Error> {
-    let client = reqwest::Client::builder()
+#[derive(Debug)]
+enum FetchError {
+    /// Genius answered 429 (or we're still cooling down from one).
+    RateLimited { retry_after: Duration },
+    Http(reqwest::Error),
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::RateLimited { retry_after } => write!(f, "rate limited by Genius (429), retry in {}s", retry_after.as_secs()),
+            FetchError::Http(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(e: reqwest::Error) -> Self {
+        FetchError::Http(e)
+    }
+}
+
+// Reads `Retry-After` as either delay-seconds or an HTTP date, capped at MAX_RETRY_AFTER.
+fn parse_retry_after(response: &reqwest::Response) -> Duration {
+    let value = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim);
+    let delay = match value {
+        Some(v) => match v.parse::<u64>() {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(_) => chrono::DateTime::parse_from_rfc2822(v)
+                .ok()
+                .and_then(|date| (date.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().ok())
+                .unwrap_or(MAX_RETRY_AFTER),
+        },
+        None => MAX_RETRY_AFTER,
+    };
+    delay.min(MAX_RETRY_AFTER)
+}
+
+// "https://genius.com" for any page on genius.com, so a cooldown covers the whole host
+fn rate_limit_key(url: &str) -> String {
+    reqwest::Url::parse(url).map(|url| url.origin().ascii_serialization()).unwrap_or_else(|_| url.to_string())
+}
+
+fn set_rate_limited_for(url: &str, duration: Duration) {
+    RATE_LIMITED_UNTIL.lock().unwrap().insert(rate_limit_key(url), Instant::now() + duration);
+}
+
+// Don't hit a host again while its cooldown is active
+fn check_rate_limit(url: &str) -> Result<(), FetchError> {
+    if let Some(&until) = RATE_LIMITED_UNTIL.lock().unwrap().get(&rate_limit_key(url)) {
+        let now = Instant::now();
+        if until > now {
+            return Err(FetchError::RateLimited { retry_after: until - now });
+        }
+    }
+    Ok(())
+}
+
+fn genius_site_client() -> Result<reqwest::Client, reqwest::Error> {
+    reqwest::Client::builder()
         .user_agent("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/100.0.4896.88 Safari/537.36")
-        .build()?;
-    let response = client.get(url).timeout(std::time::Duration::from_secs(15)).send().await?;
-    response.error_for_status()?.text().await
+        .build()
 }
 
-fn parse_and_extract_genius_lyrics(html: &str) -> Result<String, String> {
-    let html_doc = Html::parse_document(html);
-    let selector_str = "div[data-lyrics-container='true']";
-    let selector = Selector::parse(selector_str)
-        .map_err(|e| format!("Invalid CSS selector '{}': {:?}", selector_str, e))?;
-
-    let mut raw_lyrics = String::new();
-    let lyrics_containers = html_doc.select(&selector);
-    let mut container_count = 0;
-
-    for container in lyrics_containers {
-        container_count += 1;
-        for node in container.children() {
-            match node.value() {
-                Node::Text(text) => {
-                    raw_lyrics.push_str(&text);
-                }
-                Node::Element(element) => {
-                    match element.name() {
-                        "br" => raw_lyrics.push('\n'),
-                        "a" => { // Handle Genius annotations/links
-                            if let Some(a_ref) = scraper::ElementRef::wrap(node) {
-                                for text_node in a_ref.text() {
-                                     raw_lyrics.push_str(text_node);
-                                }
+// Returns the page HTML and its final URL (after any redirects). A retry that couldn't finish before
+// `give_up_at` is skipped, so the caller sees the rate limit rather than running out of time.
+async fn fetch_lyrics_html(url: &str, give_up_at: Instant) -> Result<(String, String), FetchError> {
+    check_rate_limit(url)?;
+
+    let client = genius_site_client()?;
+    let mut response = client.get(url).timeout(Duration::from_secs(15)).send().await?;
+
+    if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        // Wait as instructed, then retry exactly once
+        let retry_after = parse_retry_after(&response);
+        set_rate_limited_for(url, retry_after);
+        if Instant::now() + retry_after >= give_up_at {
+            return Err(FetchError::RateLimited { retry_after });
+        }
+        println!("Genius rate limited the request, retrying in {}s", retry_after.as_secs());
+        tokio::time::sleep(retry_after).await;
+        response = client.get(url).timeout(Duration::from_secs(15)).send().await?;
+
+        if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = parse_retry_after(&response).max(RATE_LIMIT_COOLDOWN);
+            set_rate_limited_for(url, retry_after);
+            return Err(FetchError::RateLimited { retry_after });
+        }
+    }
+
+    let response = response.error_for_status()?;
+    let final_url = response.url().to_string();
+    Ok((response.text().await?, final_url))
+}
+
+/// Known lyrics container selectors, newest Genius layout first.
+pub const DEFAULT_LYRICS_SELECTORS: &[&str] = &[
+    "div[data-lyrics-container='true']", // Current layout
+    "div[class^='Lyrics__Container']", // Same layout if the data attribute is dropped
+    "div.lyrics", // Legacy layout (<div class="lyrics"><p>...</p></div>)
+];
+
+/// Wrap text that Genius annotates (its `<a>` links) when annotations are kept. Private-use characters,
+/// so they can't clash with lyrics; `parse_lyric_lines` turns them into `LyricLine::annotated`.
+pub const ANNOTATION_START: char = '\u{E000}';
+pub const ANNOTATION_END: char = '\u{E001}';
+
+/// Removes annotation marks, for lyrics text that leaves the app (clipboard, editing, exports).
+pub fn strip_annotation_marks(lyrics: &str) -> String {
+    lyrics.replace([ANNOTATION_START, ANNOTATION_END], "")
+}
+
+// Appends the lyric text under `node`: text, <br> line breaks, annotation links and paragraphs.
+// With `mark_annotations`, annotated text is wrapped in ANNOTATION_START/ANNOTATION_END.
+fn append_container_text(element: ElementRef<'_>, raw_lyrics: &mut String, mark_annotations: bool) {
+    for child in element.children() {
+        match child.value() {
+            Node::Text(text) => {
+                raw_lyrics.push_str(text);
+            }
+            Node::Element(element) => {
+                match element.name() {
+                    "br" => raw_lyrics.push('\n'),
+                    "a" => { // Handle Genius annotations/links
+                        if let Some(a_ref) = ElementRef::wrap(child) {
+                            let text: String = a_ref.text().collect();
+                            if mark_annotations && !text.trim().is_empty() {
+                                raw_lyrics.push(ANNOTATION_START);
+                                raw_lyrics.push_str(&text);
+                                raw_lyrics.push(ANNOTATION_END);
+                            } else {
+                                raw_lyrics.push_str(&text);
                             }
                         }
-                        _ => {}
                     }
+                    "p" => { // Legacy layout wraps lyrics in paragraphs
+                        if let Some(p_ref) = ElementRef::wrap(child) {
+                            append_container_text(p_ref, raw_lyrics, mark_annotations);
+                            raw_lyrics.push('\n');
+                        }
+                    }
+                    _ => {}
                 }
-                _ => {}
             }
+            _ => {}
         }
-        raw_lyrics.push('\n');
     }
-    raw_lyrics = raw_lyrics.trim_end().to_string();
+}
+
+// Tries each selector in order and returns the text of the first one whose containers hold any lyrics.
+fn parse_and_extract_genius_lyrics(html: &str, selectors: &[String], mark_annotations: bool) -> Result<String, String> {
+    let html_doc = Html::parse_document(html);
+    let mut found_empty_container = false;
 
-    if container_count == 0 {
-        Err(format!("Could not find lyrics container matching selector '{}'. Website structure may have changed.", selector_str))
-    } else if raw_lyrics.trim().is_empty() {
-        Err(format!("Found lyrics container matching selector '{}', but it contained no text.", selector_str))
+    for selector_str in selectors.iter().map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        let selector = match Selector::parse(selector_str) {
+            Ok(selector) => selector,
+            Err(e) => {
+                eprintln!("Skipping invalid CSS selector '{}': {:?}", selector_str, e);
+                continue;
+            }
+        };
+
+        let mut raw_lyrics = String::new();
+        let mut container_count = 0;
+        for container in html_doc.select(&selector) {
+            container_count += 1;
+            append_container_text(container, &mut raw_lyrics, mark_annotations);
+            raw_lyrics.push('\n');
+        }
+        let raw_lyrics = raw_lyrics.trim_end().to_string();
+
+        if container_count == 0 {
+            continue;
+        }
+        if raw_lyrics.trim().is_empty() {
+            found_empty_container = true;
+            continue;
+        }
+        println!("Extracted lyrics using selector '{}'", selector_str);
+        return Ok(raw_lyrics);
+    }
+
+    if found_empty_container {
+        Err("Found a lyrics container, but it contained no text.".to_string())
     } else {
-        Ok(raw_lyrics)
+        Err(format!("Could not find a lyrics container matching any of {} selectors. Website structure may have changed.", selectors.len()))
+    }
+}
+
+// --- Selector Updates ---
+// Opt-in: a list of working selectors published at a user-configured URL (e.g. a gist), so a Genius layout
+// change can be fixed without a new release. The last good list is cached in the settings folder.
+
+const SELECTOR_UPDATE_FILE_NAME: &str = "selector_update.json";
+const MAX_UPDATED_SELECTORS: usize = 20; // More than this isn't a selector list
+const MAX_SELECTOR_LEN: usize = 200;
+
+// Fetched (or cached) selectors, tried before the configured ones
+static UPDATED_SELECTORS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Validates a published selector list, a JSON array of strings. Anything else (e.g. an error page, whose
+/// words would pass as tag selectors) is rejected, as is a list without any valid selector; invalid ones are skipped.
+pub fn parse_selector_update(body: &str) -> Result<Vec<String>, String> {
+    let candidates: Vec<String> = serde_json::from_str(body).map_err(|e| format!("Selector update is not a JSON list of strings: {}", e))?;
+    let mut selectors: Vec<String> = Vec::new();
+    for candidate in candidates.iter().map(|s| s.trim()) {
+        if candidate.is_empty() || selectors.iter().any(|s| s == candidate) {
+            continue;
+        }
+        if candidate.len() > MAX_SELECTOR_LEN || Selector::parse(candidate).is_err() {
+            eprintln!("Skipping invalid selector from update: '{}'", candidate);
+            continue;
+        }
+        selectors.push(candidate.to_string());
+    }
+    if selectors.is_empty() {
+        return Err("Selector update contains no valid selectors.".to_string());
     }
+    if selectors.len() > MAX_UPDATED_SELECTORS {
+        return Err(format!("Selector update lists {} selectors (at most {} allowed).", selectors.len(), MAX_UPDATED_SELECTORS));
+    }
+    Ok(selectors)
+}
+
+/// Updated selectors first, then the configured ones that aren't among them.
+pub fn merge_selectors(updated: &[String], configured: &[String]) -> Vec<String> {
+    let mut merged = updated.to_vec();
+    for selector in configured {
+        if !merged.contains(selector) {
+            merged.push(selector.clone());
+        }
+    }
+    merged
+}
+
+/// Selectors from the last successful update (empty without one).
+pub fn updated_selectors() -> Vec<String> {
+    UPDATED_SELECTORS.lock().unwrap().clone()
+}
+
+fn selector_update_path() -> Option<std::path::PathBuf> {
+    crate::config::config_dir().ok().map(|dir| dir.join(SELECTOR_UPDATE_FILE_NAME))
+}
+
+/// Uses the cached list from the last successful update, if there is a valid one.
+pub fn load_cached_selector_update() {
+    let Some(path) = selector_update_path() else {
+        return;
+    };
+    let Ok(body) = std::fs::read_to_string(&path) else {
+        return;
+    };
+    match parse_selector_update(&body) {
+        Ok(selectors) => *UPDATED_SELECTORS.lock().unwrap() = selectors,
+        Err(e) => eprintln!("Ignoring cached selector update {}: {}", path.display(), e),
+    }
+}
+
+/// Fetches the selector list from `url`, validates and caches it. On failure the cached (or built-in)
+/// selectors stay in use. Returns the number of selectors received.
+pub async fn update_selectors(url: &str) -> Result<usize, String> {
+    let body = reqwest::Client::new()
+        .get(url)
+        .timeout(Duration::from_secs(15))
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Selector update failed: {}", e))?
+        .text()
+        .await
+        .map_err(|e| format!("Selector update failed: {}", e))?;
+    let selectors = parse_selector_update(&body)?;
+    if let Some(path) = selector_update_path() {
+        let json = serde_json::to_string_pretty(&selectors).map_err(|e| format!("Failed to serialize selector update: {}", e))?;
+        if let Err(e) = std::fs::write(&path, json) {
+            eprintln!("Failed to cache selector update {}: {}", path.display(), e);
+        }
+    }
+    let count = selectors.len();
+    *UPDATED_SELECTORS.lock().unwrap() = selectors;
+    Ok(count)
 }
 
 // --- Lyrics Cleaning --- (Keep clean_genius_lyrics as it is)
 
-fn clean_genius_lyrics(raw_lyrics: &str) -> String {
-    let re_headers = Regex::new(r"\s*\[.*?\]\s*\n?").unwrap();
+/// Genius promo text that leaks into lyrics containers. `*` matches any text within the line.
+pub const DEFAULT_PROMO_PHRASES: &[&str] = &[
+    "You might also like",
+    "See * LiveGet tickets as low as $*",
+];
+
+// Removes a promo phrase only where it can't be a lyric: on a line of its own, or glued to the end of
+// a lyric line without a space ("last lineYou might also like"). Matching is case-sensitive.
+fn strip_promo_phrases(lyrics: &str, phrases: &[String]) -> String {
+    let patterns: Vec<Regex> = phrases
+        .iter()
+        .map(|phrase| phrase.trim())
+        .filter(|phrase| !phrase.is_empty())
+        .filter_map(|phrase| {
+            let phrase_pattern = phrase.split('*').map(regex::escape).collect::<Vec<_>>().join("[^\n]*?");
+            Regex::new(&format!(r"(?m)^(?P<lyric>[^\n]*?\S)??[ \t]*{}[ \t]*$(?P<newline>\n?)", phrase_pattern)).ok()
+        })
+        .collect();
+    let mut cleaned = lyrics.to_string();
+    // Phrases can be chained on one line ("...$20You might also like"), so repeat until nothing changes
+    loop {
+        let before = cleaned.clone();
+        for pattern in &patterns {
+            cleaned = pattern
+                .replace_all(&cleaned, |caps: &regex::Captures| {
+                    match caps.name("lyric") {
+                        // A space before the phrase means it's part of the lyric line
+                        Some(lyric) if caps[0][lyric.len()..].starts_with([' ', '\t']) => caps[0].to_string(),
+                        Some(lyric) => format!("{}{}", lyric.as_str(), &caps["newline"]),
+                        None => String::new(), // The whole line, so no blank line is left behind
+                    }
+                })
+                .into_owned();
+        }
+        if cleaned == before {
+            return cleaned;
+        }
+    }
+}
+
+fn clean_genius_lyrics(raw_lyrics: &str, promo_phrases: &[String]) -> String {
+    // Section headers ("[Chorus]") sit on their own line and start a new stanza, so they become a blank line
+    let re_headers = Regex::new(r"(?m)^[ \t]*\[[^\]\n]*\][ \t]*$").unwrap();
     let no_headers = re_headers.replace_all(raw_lyrics, "");
-    let re_newlines = Regex::new(r"\n{2,}").unwrap();
-    let collapsed_newlines = re_newlines.replace_all(&no_headers, "\n");
+    // Inline annotations like "[?]" are dropped as well
+    let re_inline = Regex::new(r"[ \t]*\[[^\]\n]*\]").unwrap();
+    let no_headers = re_inline.replace_all(&no_headers, "");
+    let no_promos = strip_promo_phrases(&no_headers, promo_phrases);
+    // Keep at most one blank line between stanzas (whitespace-only lines count as blank)
+    let re_newlines = Regex::new(r"\n(?:[ \t]*\n){2,}").unwrap();
+    let collapsed_newlines = re_newlines.replace_all(&no_promos, "\n\n");
     collapsed_newlines.trim().to_string()
 }
 
+/// Drops the blank lines between stanzas, for the "compact" lyrics spacing.
+pub fn compact_stanzas(lyrics: &str) -> String {
+    lyrics
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// --- Display Formatting ---
+
+/// Optional clean-up applied to lyrics before display (cached copies stay as fetched). All off by default.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct LyricsFormat {
+    pub normalize_spaces: bool, // Odd spaces (non-breaking, tabs, ...) become one plain space
+    pub trim_lines: bool, // Drop trailing whitespace on each line
+    pub capitalize_lines: bool, // Uppercase the first letter of each line
+    pub collapse_repeats: bool, // Merge runs of identical consecutive lines into one with a "×N" marker
+}
+
+// Uppercases the first letter of the line (after an LRC tag and opening punctuation), unless the
+// first word already mixes cases on purpose ("iPhone", "eBay").
+fn capitalize_line_start(line: &str) -> String {
+    let re_start = Regex::new(r#"^(\[\d+:\d{1,2}(?:[.:]\d{1,3})?\]\s*)?[\s("'¿¡“‘\x{E000}-]*"#).unwrap();
+    let prefix_len = re_start.find(line).map_or(0, |m| m.end());
+    let (prefix, rest) = line.split_at(prefix_len);
+    let first_word = rest.split(|c: char| !c.is_alphanumeric() && c != '\'').next().unwrap_or_default();
+    let mut chars = rest.chars();
+    match chars.next() {
+        Some(first) if first.is_lowercase() && !first_word.chars().any(char::is_uppercase) => {
+            format!("{}{}{}", prefix, first.to_uppercase(), chars.as_str())
+        }
+        _ => line.to_string(),
+    }
+}
+
+/// Applies the enabled `LyricsFormat` transforms line by line.
+pub fn format_lyrics(lyrics: &str, format: &LyricsFormat) -> String {
+    if *format == LyricsFormat::default() {
+        return lyrics.to_string();
+    }
+    let re_spaces = Regex::new(r"[\t\p{Zs}]+").unwrap();
+    let lines = lyrics
+        .lines()
+        .map(|line| {
+            let mut line = line.to_string();
+            if format.normalize_spaces {
+                line = re_spaces.replace_all(&line, " ").into_owned();
+            }
+            if format.trim_lines {
+                line.truncate(line.trim_end().len());
+            }
+            if format.capitalize_lines {
+                line = capitalize_line_start(&line);
+            }
+            line
+        })
+        .collect::<Vec<_>>();
+    let lines = if format.collapse_repeats { collapse_repeated_lines(lines) } else { lines };
+    lines.join("\n")
+}
+
+// Keeps the first of each run of identical lines (compared without their LRC tag, so a synced line
+// stays highlighted until the next different one) and marks it "×N". Blank lines are left alone.
+fn collapse_repeated_lines(lines: Vec<String>) -> Vec<String> {
+    let re_tag = Regex::new(r"^\[\d+:\d{1,2}(?:[.:]\d{1,3})?\]\s*").unwrap();
+    let text_of = |line: &str| re_tag.find(line).map_or(line, |tag| &line[tag.end()..]).to_string();
+    let mut collapsed: Vec<(String, usize)> = Vec::new();
+    for line in lines {
+        match collapsed.last_mut() {
+            Some((last, count)) if !text_of(&line).trim().is_empty() && text_of(last) == text_of(&line) => *count += 1,
+            _ => collapsed.push((line, 1)),
+        }
+    }
+    collapsed
+        .into_iter()
+        .map(|(line, count)| if count > 1 { format!("{} ×{}", line, count) } else { line })
+        .collect()
+}
+
+// --- Lyric Lines ---
+
+/// A single lyric line, with its start time when the lyrics are synced (LRC `[mm:ss.xx]` tags).
+#[derive(Clone, Debug, PartialEq)]
+pub struct LyricLine {
+    pub time_ms: Option<u32>,
+    pub text: String,
+    pub annotated: bool, // Genius annotates (part of) this line; only known when annotations were kept
+}
+
+/// Splits lyrics into lines, extracting a leading LRC timestamp from each line if present.
+/// Annotation marks are removed from the text and set `annotated` instead.
+pub fn parse_lyric_lines(lyrics: &str) -> Vec<LyricLine> {
+    let re_timestamp = Regex::new(r"^\[(\d+):(\d{1,2})(?:[.:](\d{1,3}))?\]\s*").unwrap();
+    let mut in_annotation = false; // An annotation can run over several lines
+    lyrics
+        .lines()
+        .map(|line| {
+            let annotated = in_annotation || line.contains(ANNOTATION_START);
+            if let Some(last_mark) = line.rfind([ANNOTATION_START, ANNOTATION_END]) {
+                in_annotation = line[last_mark..].starts_with(ANNOTATION_START);
+            }
+            (strip_annotation_marks(line), annotated)
+        })
+        .map(|(line, annotated)| match re_timestamp.captures(&line) {
+            Some(caps) => {
+                let minutes: u32 = caps[1].parse().unwrap_or(0);
+                let seconds: u32 = caps[2].parse().unwrap_or(0);
+                // Fractions can be hundredths ("12.34") or milliseconds ("12.345")
+                let fraction_ms = caps.get(3).map_or(0, |m| {
+                    let digits = m.as_str();
+                    digits.parse::<u32>().unwrap_or(0) * 10u32.pow(3 - digits.len() as u32)
+                });
+                LyricLine {
+                    time_ms: Some(minutes * 60_000 + seconds * 1000 + fraction_ms),
+                    text: line[caps[0].len()..].to_string(),
+                    annotated,
+                }
+            }
+            None => LyricLine { time_ms: None, text: line, annotated },
+        })
+        .collect()
+}
+
+// Hebrew, Arabic, Syriac, Thaana, NKo, Samaritan, Mandaic and their presentation forms
+fn is_rtl_char(c: char) -> bool {
+    matches!(c, '\u{0590}'..='\u{08FF}' | '\u{FB1D}'..='\u{FDFF}' | '\u{FE70}'..='\u{FEFF}')
+}
+
+/// Whether a line is mostly right-to-left script (Arabic, Hebrew, ...). Digits, punctuation and
+/// spaces are neutral; a line with as many Latin letters as RTL ones stays left-to-right.
+pub fn is_predominantly_rtl(text: &str) -> bool {
+    let (rtl, ltr) = text.chars().filter(|c| c.is_alphabetic()).fold((0, 0), |(rtl, ltr), c| {
+        if is_rtl_char(c) { (rtl + 1, ltr) } else { (rtl, ltr + 1) }
+    });
+    rtl > ltr
+}
+
+/// Position of the line at `index` among the non-blank lines, as (1-based number, total), for a "line 12 / 48"
+/// indicator. A blank line (stanza break) counts as the line before it.
+pub fn line_position(lines: &[LyricLine], index: usize) -> (usize, usize) {
+    let is_text = |line: &&LyricLine| !line.text.trim().is_empty();
+    let number = lines.iter().take(index + 1).filter(is_text).count().max(1);
+    (number, lines.iter().filter(is_text).count())
+}
+
+/// Formats a line's start time as a `[mm:ss]` tag for display.
+pub fn format_timestamp(time_ms: u32) -> String {
+    let seconds = time_ms / 1000;
+    format!("[{:02}:{:02}]", seconds / 60, seconds % 60)
+}
+
+/// Where line `index` of `old` went in `new` (e.g. after a refresh fixed a few lines): the closest line with
+/// the same text, else the closest match for one of the lines after it, else the same relative position.
+pub fn matching_line(old: &[LyricLine], index: usize, new: &[LyricLine]) -> usize {
+    if new.is_empty() {
+        return 0;
+    }
+    let scale = new.len() as f32 / old.len().max(1) as f32; // Where a line would be if the changes were spread evenly
+    for (offset, line) in old.iter().enumerate().skip(index) {
+        let text = line.text.trim();
+        if text.is_empty() {
+            continue;
+        }
+        let expected = offset as f32 * scale;
+        let closest = new
+            .iter()
+            .enumerate()
+            .filter(|(_, candidate)| candidate.text.trim() == text)
+            .map(|(position, _)| position)
+            .min_by(|a, b| (*a as f32 - expected).abs().total_cmp(&(*b as f32 - expected).abs()));
+        if let Some(position) = closest {
+            return position.saturating_sub(offset - index);
+        }
+    }
+    ((index as f32 * scale) as usize).min(new.len() - 1)
+}
+
+/// Builds LRC text from plain lyrics and tapped start times: the taps go to the non-blank lines in order.
+/// Blank lines, and lines after the last tap, are kept without a timestamp.
+pub fn tapped_lrc(lyrics: &str, taps: &[u32]) -> String {
+    let mut taps = taps.iter();
+    lyrics
+        .lines()
+        .map(|line| {
+            let tap = if line.trim().is_empty() { None } else { taps.next() };
+            match tap {
+                Some(&time_ms) => format!("[{:02}:{:02}.{:02}]{}", time_ms / 60_000, time_ms / 1000 % 60, time_ms % 1000 / 10, line),
+                None => line.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// How far playback is through the synced line at `index` (0.0 to 1.0), measured up to the next line's timestamp.
+/// None when the line is untimed or no later line has a timestamp to end it.
+pub fn line_progress(lines: &[LyricLine], index: usize, progress_ms: u32) -> Option<f32> {
+    let start = lines.get(index)?.time_ms?;
+    let end = lines[index + 1..].iter().filter_map(|line| line.time_ms).find(|&time| time > start)?;
+    Some((progress_ms.saturating_sub(start) as f32 / (end - start) as f32).clamp(0.0, 1.0))
+}
+
+/// Returns the index of the synced line playing at `progress_ms`, if the lines carry timestamps.
+pub fn current_line_index(lines: &[LyricLine], progress_ms: u32) -> Option<usize> {
+    lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| line.time_ms.is_some_and(|t| t <= progress_ms))
+        .max_by_key(|(_, line)| line.time_ms)
+        .map(|(index, _)| index)
+}
+
+// --- Genius API Search ---
+
+#[derive(Deserialize)]
+struct GeniusSearchResponse {
+    response: GeniusSearchHits,
+}
+
+#[derive(Deserialize)]
+struct GeniusSearchHits {
+    hits: Vec<GeniusSearchHit>,
+}
+
+#[derive(Deserialize)]
+struct GeniusSearchHit {
+    #[serde(rename = "type")]
+    hit_type: String,
+    result: GeniusSong,
+}
+
+#[derive(Deserialize)]
+struct GeniusSong {
+    title: String,
+    url: String,
+    #[serde(default)]
+    artist_names: String, // e.g. "Artist One & Artist Two"
+    #[serde(default)]
+    language: Option<String>, // e.g. "en", "ko" or "romanization" (Genius files translations as separate songs)
+}
+
+/// Whether a provider's language tag is the preferred language. Case-insensitive, and a region in either
+/// ("en-US") still matches the bare language ("en").
+pub fn language_matches(preferred: &str, offered: &str) -> bool {
+    let primary = |tag: &str| tag.trim().split(['-', '_']).next().unwrap_or_default().to_lowercase();
+    primary(preferred) == primary(offered)
+}
+
+// Dice coefficient of the character bigrams of two normalized names (1.0 = same, 0.0 = nothing in common)
+fn similarity(a: &str, b: &str) -> f32 {
+    let (a, b) = (format_genius_path_component(a), format_genius_path_component(b));
+    if a == b {
+        return 1.0;
+    }
+    let bigrams = |s: &str| s.as_bytes().windows(2).map(|pair| (pair[0], pair[1])).collect::<Vec<_>>();
+    let (a, mut b) = (bigrams(&a), bigrams(&b));
+    let total = a.len() + b.len();
+    if total == 0 {
+        return 0.0;
+    }
+    let mut shared = 0;
+    for pair in a {
+        if let Some(position) = b.iter().position(|&other| other == pair) {
+            b.swap_remove(position);
+            shared += 1;
+        }
+    }
+    (2 * shared) as f32 / total as f32
+}
+
+/// How well a search result matches the requested song, from 0.0 to 1.0, by title and artist similarity.
+/// Unknown parts (no artists requested or returned) are left out of the score.
+pub fn match_confidence(wanted_artists: &[String], wanted_title: &str, found_artists: &str, found_title: &str) -> f32 {
+    let title_score = similarity(wanted_title, found_title);
+    if wanted_artists.is_empty() || found_artists.trim().is_empty() {
+        return title_score;
+    }
+    // The result may list features the request doesn't (or the other way round), so the best artist counts
+    let artist_score = wanted_artists
+        .iter()
+        .map(|artist| if format_genius_path_component(found_artists).contains(&format_genius_path_component(artist)) { 1.0 } else { similarity(artist, found_artists) })
+        .fold(0.0, f32::max);
+    0.7 * title_score + 0.3 * artist_score
+}
+
+// Token for the official Genius API (optional, from .env).
+fn genius_api_token() -> Option<String> {
+    std::env::var("GENIUS_ACCESS_TOKEN").ok().filter(|token| !token.trim().is_empty())
+}
+
+// Searches the Genius API and returns the lyrics page URL of the best matching song.
+async fn search_genius_song_url(query: &str, artists: &[String], token: &str, options: &FetchOptions) -> Result<Option<String>, String> {
+    let client = reqwest::Client::new();
+    let response = client
+        .get("https://api.genius.com/search")
+        .query(&[("q", query)])
+        .bearer_auth(token)
+        .timeout(Duration::from_secs(15))
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Genius search failed: {}", e))?;
+    let search: GeniusSearchResponse = response
+        .json()
+        .await
+        .map_err(|e| format!("Unexpected Genius search response: {}", e))?;
+
+    Ok(pick_song_url(search.response.hits, artists, query, options))
+}
+
+// The song hit that matches best, if it reaches the minimum confidence (ties go to the higher-ranked hit).
+// Loose matches are rejected so the lookup falls through instead of showing another song's lyrics.
+// With a preferred language, hits in that language win over better-scoring ones in another; if none is in
+// it, any language will do.
+fn pick_song_url(hits: Vec<GeniusSearchHit>, artists: &[String], title: &str, options: &FetchOptions) -> Option<String> {
+    let mut confident: Vec<(f32, GeniusSong)> = Vec::new();
+    for song in hits.into_iter().filter(|hit| hit.hit_type == "song").map(|hit| hit.result) {
+        let confidence = match_confidence(artists, title, &song.artist_names, &song.title);
+        if confidence < options.min_confidence {
+            println!("Ignoring search result '{} - {}' (match confidence {:.2})", song.artist_names, song.title, confidence);
+        } else {
+            confident.push((confidence, song));
+        }
+    }
+    if let Some(preferred) = &options.preferred_language {
+        let in_language = |song: &GeniusSong| song.language.as_deref().is_some_and(|language| language_matches(preferred, language));
+        if confident.iter().any(|(_, song)| in_language(song)) {
+            confident.retain(|(_, song)| {
+                let keep = in_language(song);
+                if !keep {
+                    println!("Ignoring search result '{} - {}' (language {:?}, want {})", song.artist_names, song.title, song.language, preferred);
+                }
+                keep
+            });
+        } else if !confident.is_empty() {
+            println!("No search result in {}, using what's available", preferred);
+        }
+    }
+    let mut best: Option<(f32, GeniusSong)> = None;
+    for (confidence, song) in confident {
+        if best.as_ref().is_none_or(|(best_confidence, _)| confidence > *best_confidence) {
+            best = Some((confidence, song));
+        }
+    }
+    best.map(|(_, song)| song.url)
+}
+
+#[derive(Deserialize)]
+struct GeniusSiteSearchResponse {
+    response: GeniusSiteSearchSections,
+}
+
+#[derive(Deserialize)]
+struct GeniusSiteSearchSections {
+    sections: Vec<GeniusSearchHits>,
+}
+
+// Searches the genius.com website (no token needed), as its search box does, for slugs that were slightly off.
+async fn search_genius_site(base_url: &str, artists: &[String], title: &str, options: &FetchOptions) -> Result<Option<String>, String> {
+    check_rate_limit(base_url).map_err(|e| e.to_string())?;
+    let client = genius_site_client().map_err(|e| format!("Genius search failed: {}", e))?;
+    let query = format!("{} {}", artists.join(" "), title);
+    let response = client
+        .get(format!("{}/api/search/song", base_url.trim_end_matches('/')))
+        .query(&[("q", query.as_str())])
+        .timeout(Duration::from_secs(15))
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Genius search failed: {}", e))?;
+    let search: GeniusSiteSearchResponse = response
+        .json()
+        .await
+        .map_err(|e| format!("Unexpected Genius search response: {}", e))?;
+
+    let hits = search.response.sections.into_iter().flat_map(|section| section.hits).collect();
+    Ok(pick_song_url(hits, artists, title, options))
+}
+
 // --- Public API ---
 
-/// Fetches lyrics from Genius for the given artists and title.
-/// Returns the cleaned lyrics or an error string.
-pub async fn fetch_and_parse_lyrics(artists: &[String], title: &str) -> Result<String, String> {
-    // Check if artist list is empty, which shouldn't happen with valid Spotify data
-    if artists.is_empty() {
-        return Err("Cannot fetch lyrics: Artist list is empty.".to_string());
+/// A lyrics source. Genius is the only one so far.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Provider {
+    Genius,
+}
+
+impl Provider {
+    /// Every provider, in the order they're tried.
+    pub const ALL: [Provider; 1] = [Provider::Genius];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Provider::Genius => "Genius",
+        }
     }
+}
 
-    let url = build_genius_url(artists, title);
-    println!("Attempting to fetch lyrics from: {}", url);
+/// Lyrics found for a song, with the page they came from (after redirects and search fallbacks).
+#[derive(Clone, Debug, PartialEq)]
+pub struct FetchedLyrics {
+    pub lyrics: String,
+    pub url: String,
+    pub provider: Provider,
+}
+
+/// Per-request lyrics fetching options, built from the user's settings.
+#[derive(Clone, Debug)]
+pub struct FetchOptions {
+    pub base_url: String, // Genius site root for lyrics pages (a mock server in tests)
+    pub alternate_hosts: Vec<String>, // Other Genius site roots tried, in order, for the same page when base_url 404s
+    pub selectors: Vec<String>, // Lyrics container selectors, tried in order
+    pub promo_phrases: Vec<String>, // Promo text removed while cleaning (see DEFAULT_PROMO_PHRASES)
+    pub min_confidence: f32, // Search results scoring below this (see match_confidence) are rejected
+    pub retry_with_features: bool, // After the slug misses, retry with the title's "(feat. X)" artists added
+    pub max_slug_artists: usize, // Artists used in the page slug; the rest are left out
+    pub best_match: bool, // Keep looking after the first hit and use the most complete of the first few results
+    pub mark_annotations: bool, // Keep which text Genius annotates (see ANNOTATION_START)
+    pub preferred_language: Option<String>, // Language tag ("en") to pick among versions where a provider reports one
+    pub deadline: Duration, // Overall time budget per song, across all attempts
+    pub max_attempts: usize, // Stop after this many lookups even if time is left
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        Self {
+            base_url: GENIUS_BASE_URL.to_string(),
+            alternate_hosts: Vec::new(),
+            selectors: DEFAULT_LYRICS_SELECTORS.iter().map(|s| s.to_string()).collect(),
+            promo_phrases: DEFAULT_PROMO_PHRASES.iter().map(|s| s.to_string()).collect(),
+            min_confidence: DEFAULT_MIN_MATCH_CONFIDENCE,
+            retry_with_features: true,
+            max_slug_artists: DEFAULT_MAX_SLUG_ARTISTS,
+            best_match: false,
+            mark_annotations: false,
+            preferred_language: None,
+            deadline: Duration::from_secs(10),
+            max_attempts: 3,
+        }
+    }
+}
+
+// One way of locating a song's lyrics, tried in order until one succeeds
+#[derive(Debug)]
+enum LookupAttempt {
+    Page(String), // A Genius lyrics page URL guessed from the slug
+    TitleSearch, // Genius API search by title (needs GENIUS_ACCESS_TOKEN)
+}
+
+// A provider's lookups for a song, most reliable first.
+fn lookup_attempts(provider: Provider, artists: &[String], title: &str, options: &FetchOptions) -> Vec<LookupAttempt> {
+    match provider {
+        // No artists (e.g. local files): the slug can't be guessed, but the Genius API can search by title
+        Provider::Genius if artists.is_empty() => vec![LookupAttempt::TitleSearch],
+        Provider::Genius => {
+            let mut urls = vec![build_genius_url(&options.base_url, artists, title, options.max_slug_artists)];
+            // Big collaborations are usually filed under the primary artist alone
+            urls.push(build_genius_url(&options.base_url, artists, title, 1));
+            // "Song (feat. Y)" by X often lives at /x-and-y-song-lyrics
+            if options.retry_with_features
+                && let Some(extended) = artists_with_title_features(artists, title)
+            {
+                urls.push(build_genius_url(&options.base_url, &extended, title, options.max_slug_artists));
+            }
+            // The same slug twice (e.g. a single artist) is only tried once
+            let mut unique: Vec<String> = Vec::new();
+            for url in urls {
+                if !unique.contains(&url) {
+                    unique.push(url);
+                }
+            }
+            unique.into_iter().map(LookupAttempt::Page).collect()
+        }
+    }
+}
+
+async fn run_lookup_attempt(attempt: &LookupAttempt, artists: &[String], title: &str, options: &FetchOptions, give_up_at: Instant) -> Result<FetchedLyrics, String> {
+    match attempt {
+        LookupAttempt::Page(url) => {
+            println!("Attempting to fetch lyrics from: {}", url);
+            match fetch_lyrics_html(url, give_up_at).await {
+                Ok(page) => extract_lyrics(page, options),
+                // Near-miss slug: Genius's own search usually knows the right page
+                Err(FetchError::Http(e)) if e.status() == Some(reqwest::StatusCode::NOT_FOUND) => {
+                    if let Some(fetched) = fetch_from_alternate_hosts(url, options, give_up_at).await {
+                        return Ok(fetched);
+                    }
+                    println!("{} not found, searching Genius for the right page...", url);
+                    match search_genius_site(&options.base_url, artists, title, options).await {
+                        Ok(Some(found)) if found != *url => fetch_lyrics_from_url(&found, options, give_up_at).await,
+                        Ok(_) => Err(describe_fetch_error(url, FetchError::Http(e))),
+                        Err(search_error) => {
+                            println!("{}", search_error);
+                            Err(describe_fetch_error(url, FetchError::Http(e)))
+                        }
+                    }
+                }
+                Err(e) => Err(describe_fetch_error(url, e)),
+            }
+        }
+        LookupAttempt::TitleSearch => {
+            let Some(token) = genius_api_token() else {
+                return Err("Cannot fetch lyrics: Artist list is empty.\nHint: Set GENIUS_ACCESS_TOKEN in .env to enable title-only search.".to_string());
+            };
+            println!("Searching Genius by title for '{}'...", title);
+            let url = search_genius_song_url(title, artists, &token, options)
+                .await?
+                .ok_or_else(|| format!("Genius search found no song matching '{}'.", title))?;
+            fetch_lyrics_from_url(&url, options, give_up_at).await
+        }
+    }
+}
+
+// The same page on the alternate Genius hosts (regional sites, pages filed elsewhere), tried in order after
+// the primary host 404s. Failures are only logged: the caller falls back to searching the primary host.
+async fn fetch_from_alternate_hosts(url: &str, options: &FetchOptions, give_up_at: Instant) -> Option<FetchedLyrics> {
+    let page_path = url.strip_prefix(options.base_url.trim_end_matches('/'))?;
+    for host in &options.alternate_hosts {
+        let alternate = format!("{}{}", host.trim_end_matches('/'), page_path);
+        if alternate == url {
+            continue;
+        }
+        println!("Trying alternate Genius host: {}", alternate);
+        match fetch_lyrics_html(&alternate, give_up_at).await {
+            Ok(page) => match extract_lyrics(page, options) {
+                Ok(fetched) => return Some(fetched),
+                Err(e) => println!("{}", e),
+            },
+            Err(e) => println!("{}", describe_fetch_error(&alternate, e)),
+        }
+    }
+    None
+}
 
-    match fetch_lyrics_html(&url).await {
-        Ok(html) => {
-            println!("Successfully fetched HTML ({} bytes)", html.len());
-            match parse_and_extract_genius_lyrics(&html) {
-                Ok(raw_lyrics) => {
-                    let cleaned = clean_genius_lyrics(&raw_lyrics);
-                    if cleaned.is_empty() {
-                         Err("Extracted lyrics were empty after cleaning.".to_string())
-                    } else {
-                        Ok(cleaned)
+/// Fetches lyrics from Genius for the given artists and title.
+/// Returns the cleaned lyrics and their page URL, or an error string.
+/// Gives up after `options.max_attempts` lookups or once `options.deadline` has passed, whichever comes first.
+pub async fn fetch_and_parse_lyrics(artists: &[String], title: &str, options: &FetchOptions) -> Result<FetchedLyrics, String> {
+    let attempts: Vec<LookupAttempt> = Provider::ALL
+        .into_iter()
+        .flat_map(|provider| lookup_attempts(provider, artists, title, options))
+        .collect();
+    let wanted = if options.best_match { BEST_MATCH_CANDIDATES } else { 1 };
+    let mut found = Vec::new(); // Outlives the timeout, so a deadline in best-match mode keeps what was found
+    let give_up_at = Instant::now() + options.deadline;
+    let lookups = async {
+        let mut last_error = format!("No lyrics lookups to try for '{}'.", title);
+        for attempt in attempts.iter().take(options.max_attempts.max(1)) {
+            match run_lookup_attempt(attempt, artists, title, options, give_up_at).await {
+                Ok(fetched) => {
+                    found.push(fetched);
+                    if found.len() >= wanted {
+                        break;
                     }
                 }
-                Err(e) => Err(format!("Parsing error: {}", e)),
+                Err(e) => {
+                    println!("Lookup {:?} failed: {}", attempt, e);
+                    last_error = e;
+                }
             }
         }
-        Err(e) => {
+        last_error
+    };
+
+    let result = tokio::time::timeout(options.deadline, lookups).await;
+    if found.len() > 1 {
+        println!("Best match: comparing {} results for '{}'", found.len(), title);
+    }
+    match (most_complete(found), result) {
+        (Some(fetched), _) => Ok(fetched),
+        (None, Ok(last_error)) => Err(last_error),
+        (None, Err(_)) => Err(format!("Lyrics not found: gave up after {}s.", options.deadline.as_secs())),
+    }
+}
+
+// The result with the most lyric lines (full versions beat clean/radio edits); the earliest wins ties
+fn most_complete(results: Vec<FetchedLyrics>) -> Option<FetchedLyrics> {
+    results.into_iter().rev().max_by_key(|fetched| fetched.lyrics.lines().filter(|line| !line.trim().is_empty()).count())
+}
+
+// Fetches, parses and cleans a single Genius lyrics page.
+async fn fetch_lyrics_from_url(url: &str, options: &FetchOptions, give_up_at: Instant) -> Result<FetchedLyrics, String> {
+    println!("Attempting to fetch lyrics from: {}", url);
+
+    match fetch_lyrics_html(url, give_up_at).await {
+        Ok(page) => extract_lyrics(page, options),
+        Err(e) => Err(describe_fetch_error(url, e)),
+    }
+}
+
+// Parses and cleans a fetched page (HTML and final URL).
+fn extract_lyrics((html, final_url): (String, String), options: &FetchOptions) -> Result<FetchedLyrics, String> {
+    println!("Successfully fetched HTML ({} bytes) from {}", html.len(), final_url);
+    match parse_and_extract_genius_lyrics(&html, &options.selectors, options.mark_annotations) {
+        Ok(raw_lyrics) => {
+            let cleaned = clean_genius_lyrics(&raw_lyrics, &options.promo_phrases);
+            if cleaned.is_empty() {
+                 Err("Extracted lyrics were empty after cleaning.".to_string())
+            } else {
+                Ok(FetchedLyrics { lyrics: cleaned, url: final_url, provider: Provider::Genius })
+            }
+        }
+        Err(e) => Err(format!("Parsing error: {}", e)),
+    }
+}
+
+// Turns a fetch failure into a user-facing message with a hint.
+fn describe_fetch_error(url: &str, error: FetchError) -> String {
+    match error {
+        FetchError::RateLimited { retry_after } => {
+            format!("Rate limited by Genius while fetching {}.\nHint: Too many requests (429). Lyrics fetching resumes in about {}s.", url, retry_after.as_secs())
+        }
+        FetchError::Http(e) => {
             let mut error_msg = format!("Network error fetching {}: {}", url, e);
              if let Some(status) = e.status() {
                 if status == reqwest::StatusCode::NOT_FOUND {
@@ -157,7 +1092,526 @@ pub async fn fetch_and_parse_lyrics(artists: &[String], title: &str) -> Result<S
             } else if e.is_timeout() {
                  error_msg.push_str("\nHint: Request timed out.");
             }
-            Err(error_msg)
+            error_msg
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    // Saved Genius pages, trimmed to the parts the parser looks at
+    const CURRENT_LAYOUT: &str = include_str!("../tests/fixtures/genius_current.html");
+    const CLASS_ONLY_LAYOUT: &str = include_str!("../tests/fixtures/genius_class_only.html");
+    const LEGACY_LAYOUT: &str = include_str!("../tests/fixtures/genius_legacy.html");
+    const NO_LYRICS: &str = include_str!("../tests/fixtures/genius_no_lyrics.html");
+    const PROMO_LAYOUT: &str = include_str!("../tests/fixtures/genius_promo.html");
+
+    const CURRENT_LAYOUT_LYRICS: &str = "First line of the verse\nAn annotated line\nLast line of the verse\n\nChorus line one\nChorus line two\n\nSecond verse line\nFinal line";
+
+    fn default_selectors() -> Vec<String> {
+        FetchOptions::default().selectors
+    }
+
+    fn extract_and_clean(html: &str) -> Result<String, String> {
+        parse_and_extract_genius_lyrics(html, &default_selectors(), false).map(|raw| clean_genius_lyrics(&raw, &FetchOptions::default().promo_phrases))
+    }
+
+    // --- URL building ---
+
+    #[test]
+    fn title_features_are_added_to_the_artists() {
+        let artists = vec!["Artist".to_string()];
+        assert_eq!(artists_with_title_features(&artists, "Song (feat. Y)"), Some(vec!["Artist".to_string(), "Y".to_string()]));
+        assert_eq!(
+            artists_with_title_features(&artists, "Song [ft. Y & Z]"),
+            Some(vec!["Artist".to_string(), "Y".to_string(), "Z".to_string()])
+        );
+        // Already credited, or nothing featured: no retry
+        assert_eq!(artists_with_title_features(&["Artist".to_string(), "Y".to_string()], "Song (feat. y)"), None);
+        assert_eq!(artists_with_title_features(&artists, "Song (Live)"), None);
+    }
+
+    #[test]
+    fn path_component_drops_features_and_punctuation() {
+        assert_eq!(format_genius_path_component("Don't Stop Me Now (feat. Someone)"), "don-t-stop-me-now");
+        assert_eq!(format_genius_path_component("Song Title - Radio Edit"), "song-title");
+        assert_eq!(format_genius_path_component("Simon & Garfunkel"), "simon-and-garfunkel");
+        assert_eq!(format_genius_path_component("  --Hello...World--  "), "hello-world");
+    }
+
+    #[test]
+    fn only_known_trailing_tags_are_stripped() {
+        let defaults: Vec<String> = DEFAULT_SLUG_STRIP_TAGS.iter().map(|s| s.to_string()).collect();
+        assert_eq!(strip_title_tags("Explicit Content", &defaults), "Explicit Content"); // A real song name
+        assert_eq!(strip_title_tags("Song (Explicit Content)", &defaults), "Song (Explicit Content)");
+        assert_eq!(strip_title_tags("Song (Explicit) [Clean Version]", &defaults), "Song");
+        assert_eq!(strip_title_tags("Song (Explicit) Reprise", &defaults), "Song (Explicit) Reprise"); // Not at the end
+        assert_eq!(strip_title_tags("Song (Without You)", &defaults), "Song (Without You)");
+        assert_eq!(strip_title_tags("Song (feat. X) [Explicit]", &defaults), "Song");
+        // Configurable: an empty list keeps every tag, a custom one strips its own
+        assert_eq!(strip_title_tags("Song (Explicit)", &[]), "Song (Explicit)");
+        assert_eq!(strip_title_tags("Song (Sped Up)", &["sped up".to_string()]), "Song");
+        assert_eq!(format_genius_path_component("Explicit Content"), "explicit-content");
+    }
+
+    #[test]
+    fn genius_url_joins_artists_and_title() {
+        let artists = vec!["Queen".to_string()];
+        assert_eq!(build_genius_url(GENIUS_BASE_URL, &artists, "Bohemian Rhapsody", 3), "https://genius.com/queen-bohemian-rhapsody-lyrics");
+
+        let artists = vec!["Artist One".to_string(), "Artist Two".to_string()];
+        assert_eq!(build_genius_url("http://127.0.0.1:1234/", &artists, "Duet", 3), "http://127.0.0.1:1234/artist-one-and-artist-two-duet-lyrics");
+    }
+
+    #[test]
+    fn genius_url_caps_long_artist_lists() {
+        let artists: Vec<String> = ["First Artist", "Second Artist", "Third Artist", "Fourth Artist", "Fifth Artist", "Sixth Artist"]
+            .iter()
+            .map(|artist| artist.to_string())
+            .collect();
+        let url = build_genius_url(GENIUS_BASE_URL, &artists, "Posse Cut", DEFAULT_MAX_SLUG_ARTISTS);
+        assert_eq!(url, "https://genius.com/first-artist-and-second-artist-and-third-artist-posse-cut-lyrics");
+        assert!(url.len() < 100);
+        // The fallback after a miss: primary artist only
+        let attempts = lookup_attempts(Provider::Genius, &artists, "Posse Cut", &FetchOptions::default());
+        assert!(matches!(&attempts[1], LookupAttempt::Page(url) if url == "https://genius.com/first-artist-posse-cut-lyrics"));
+    }
+
+    // --- Parsing fixtures ---
+
+    #[test]
+    fn extracts_current_layout_across_containers() {
+        assert_eq!(extract_and_clean(CURRENT_LAYOUT).unwrap(), CURRENT_LAYOUT_LYRICS);
+    }
+
+    #[test]
+    fn falls_back_to_class_selector() {
+        assert_eq!(extract_and_clean(CLASS_ONLY_LAYOUT).unwrap(), "Only the class name is left\nStill parsed");
+    }
+
+    #[test]
+    fn extracts_legacy_paragraph_layout() {
+        assert_eq!(
+            extract_and_clean(LEGACY_LAYOUT).unwrap(),
+            "Old layout line one\nOld annotated line\nOld layout line three\n\nOld chorus line"
+        );
+    }
+
+    #[test]
+    fn reports_missing_container() {
+        let error = parse_and_extract_genius_lyrics(NO_LYRICS, &default_selectors(), false).unwrap_err();
+        assert!(error.contains("Could not find a lyrics container"), "{}", error);
+    }
+
+    #[test]
+    fn skips_invalid_selectors() {
+        let selectors = vec!["div[".to_string(), "div.lyrics".to_string()];
+        assert!(parse_and_extract_genius_lyrics(LEGACY_LAYOUT, &selectors, false).is_ok());
+    }
+
+    // --- Cleaning ---
+
+    #[test]
+    fn cleaning_keeps_one_blank_line_between_stanzas() {
+        assert_eq!(clean_genius_lyrics("[Verse 1]\nA\nB\n\n\n\n[Chorus]\nC\n \n\nD", &[]), "A\nB\n\nC\n\nD");
+        // A header without a blank line before it still starts a new stanza
+        assert_eq!(clean_genius_lyrics("A\n[Chorus]\nB", &[]), "A\n\nB");
+    }
+
+    #[test]
+    fn selector_updates_are_validated() {
+        let update = parse_selector_update(r#"["div.lyrics-v2", "div[data-lyrics-container='true']", "div[", " div.lyrics-v2 "]"#).unwrap();
+        assert_eq!(update, ["div.lyrics-v2", "div[data-lyrics-container='true']"]);
+        assert!(parse_selector_update("div.new-lyrics\n").is_err()); // Plain text isn't accepted
+        assert!(parse_selector_update("Not found").is_err());
+        assert!(parse_selector_update("<html>Not found</html>").is_err());
+        assert!(parse_selector_update(r#"{"selectors": []}"#).is_err());
+        let merged = merge_selectors(&update, &default_selectors());
+        assert_eq!(merged[0], "div.lyrics-v2");
+        assert_eq!(merged.len(), 1 + DEFAULT_LYRICS_SELECTORS.len());
+    }
+
+    #[test]
+    fn cleaning_drops_inline_annotations() {
+        assert_eq!(clean_genius_lyrics("Some words [?] here", &[]), "Some words here");
+    }
+
+    #[test]
+    fn marks_annotated_lines_when_kept() {
+        let raw = parse_and_extract_genius_lyrics(CURRENT_LAYOUT, &default_selectors(), true).unwrap();
+        let lines = parse_lyric_lines(&clean_genius_lyrics(&raw, &[]));
+        let annotated: Vec<&str> = lines.iter().filter(|line| line.annotated).map(|line| line.text.as_str()).collect();
+        assert_eq!(annotated, ["An annotated line"]);
+        assert_eq!(lines.iter().map(|line| line.text.as_str()).collect::<Vec<_>>().join("\n"), CURRENT_LAYOUT_LYRICS);
+        // Off by default: the text is exactly as before and nothing is flagged
+        assert!(!extract_and_clean(CURRENT_LAYOUT).unwrap().contains(ANNOTATION_START));
+    }
+
+    #[test]
+    fn cleaning_strips_leaked_promo_text() {
+        assert_eq!(
+            extract_and_clean(PROMO_LAYOUT).unwrap(),
+            "First line of the verse\nLast line of the verse\n\nChorus line one\nChorus line two"
+        );
+    }
+
+    #[test]
+    fn cleaning_keeps_lyrics_that_contain_promo_words() {
+        let phrases = FetchOptions::default().promo_phrases;
+        let lyrics = "I think You might also like\nyou might also like me\nSee you live, get tickets while you can";
+        assert_eq!(clean_genius_lyrics(lyrics, &phrases), lyrics);
+    }
+
+    #[test]
+    fn compact_removes_stanza_breaks() {
+        assert_eq!(compact_stanzas("A\nB\n\nC"), "A\nB\nC");
+    }
+
+    // --- Match confidence ---
+
+    #[test]
+    fn close_match_is_accepted() {
+        let artists = vec!["Queen".to_string()];
+        let confidence = match_confidence(&artists, "Bohemian Rhapsody - Remastered 2011", "Queen", "Bohemian Rhapsody");
+        assert!(confidence >= DEFAULT_MIN_MATCH_CONFIDENCE, "{}", confidence);
+        // Extra credited artists on the result don't count against it
+        let confidence = match_confidence(&artists, "Under Pressure", "Queen & David Bowie", "Under Pressure");
+        assert!(confidence > 0.9, "{}", confidence);
+    }
+
+    #[test]
+    fn loose_match_is_rejected() {
+        let artists = vec!["Queen".to_string()];
+        let confidence = match_confidence(&artists, "Bohemian Rhapsody", "Panic! at the Disco", "Bohemian Rhapsody (Cover)");
+        assert!(confidence < 0.9, "{}", confidence);
+        let confidence = match_confidence(&artists, "Bohemian Rhapsody", "Some Band", "Rhapsody in Blue");
+        assert!(confidence < DEFAULT_MIN_MATCH_CONFIDENCE, "{}", confidence);
+    }
+
+    #[test]
+    fn picks_the_best_confident_hit() {
+        let hit = |title: &str, artist: &str, url: &str| GeniusSearchHit {
+            hit_type: "song".to_string(),
+            result: GeniusSong { title: title.to_string(), url: url.to_string(), artist_names: artist.to_string(), language: None },
+        };
+        let artists = vec!["Artist".to_string()];
+        let hits = vec![hit("Unrelated Tune", "Other", "a"), hit("Song", "Artist", "b")];
+        assert_eq!(pick_song_url(hits, &artists, "Song", &FetchOptions::default()), Some("b".to_string()));
+        let hits = vec![hit("Unrelated Tune", "Other", "a")];
+        assert_eq!(pick_song_url(hits, &artists, "Song", &FetchOptions::default()), None);
+    }
+
+    #[test]
+    fn prefers_hits_in_the_preferred_language() {
+        let hit = |url: &str, language: Option<&str>| GeniusSearchHit {
+            hit_type: "song".to_string(),
+            result: GeniusSong { title: "Song".to_string(), url: url.to_string(), artist_names: "Artist".to_string(), language: language.map(String::from) },
+        };
+        let artists = vec!["Artist".to_string()];
+        let options = FetchOptions { preferred_language: Some("ko".to_string()), ..FetchOptions::default() };
+        let hits = vec![hit("romanized", Some("romanization")), hit("original", Some("ko-KR"))];
+        assert_eq!(pick_song_url(hits, &artists, "Song", &options), Some("original".to_string()));
+        // Not offered in that language: fall back to the best available
+        let hits = vec![hit("english", Some("en")), hit("unknown", None)];
+        assert_eq!(pick_song_url(hits, &artists, "Song", &options), Some("english".to_string()));
+        assert!(language_matches("EN", "en-US") && !language_matches("en", "es"));
+    }
+
+    // --- Display formatting ---
+
+    #[test]
+    fn formatting_is_off_by_default() {
+        let lyrics = "  odd\u{00A0}\u{00A0}spacing \nlowercase line";
+        assert_eq!(format_lyrics(lyrics, &LyricsFormat::default()), lyrics);
+    }
+
+    #[test]
+    fn normalizes_spaces_within_lines() {
+        let format = LyricsFormat { normalize_spaces: true, ..LyricsFormat::default() };
+        assert_eq!(format_lyrics("odd\u{00A0}\u{2005}spacing\there\n\nnext", &format), "odd spacing here\n\nnext");
+    }
+
+    #[test]
+    fn trims_trailing_whitespace_only() {
+        let format = LyricsFormat { trim_lines: true, ..LyricsFormat::default() };
+        assert_eq!(format_lyrics("  indented \t\nplain\n   ", &format), "  indented\nplain\n");
+    }
+
+    #[test]
+    fn capitalizes_line_starts_but_keeps_styling() {
+        let format = LyricsFormat { capitalize_lines: true, ..LyricsFormat::default() };
+        assert_eq!(format_lyrics("hello there\n(oh) yeah\n[00:12.34]synced line", &format), "Hello there\n(Oh) yeah\n[00:12.34]Synced line");
+        // Intentional mixed case, numbers and already capitalized lines stay as they are
+        assert_eq!(format_lyrics("iPhone in my hand\n99 problems\nALL CAPS\némile", &format), "iPhone in my hand\n99 problems\nALL CAPS\nÉmile");
+    }
+
+    #[test]
+    fn collapses_consecutive_duplicates_only() {
+        let format = LyricsFormat { collapse_repeats: true, ..LyricsFormat::default() };
+        let lyrics = "Na na na\nNa na na\nNa na na\nHey\nNa na na\n\n\nna na na\nNa na na";
+        assert_eq!(format_lyrics(lyrics, &format), "Na na na ×3\nHey\nNa na na\n\n\nna na na\nNa na na");
+        // Synced repeats keep the first timestamp
+        assert_eq!(format_lyrics("[00:10.00]Again\n[00:12.00]Again\n[00:14.00]Done", &format), "[00:10.00]Again ×2\n[00:14.00]Done");
+    }
+
+    // --- Text direction ---
+
+    #[test]
+    fn detects_rtl_lines() {
+        // Each fixture line starts with the expected direction
+        for line in include_str!("../tests/fixtures/rtl_lyrics.txt").lines().filter(|line| !line.is_empty()) {
+            let (expected, text) = line.split_once(' ').unwrap();
+            assert_eq!(is_predominantly_rtl(text), expected == "rtl", "{}", text);
+        }
+    }
+
+    // --- Synced lines ---
+
+    #[test]
+    fn line_position_skips_stanza_breaks() {
+        let lines = parse_lyric_lines("One\nTwo\n\nThree");
+        assert_eq!(line_position(&lines, 0), (1, 3));
+        assert_eq!(line_position(&lines, 2), (2, 3)); // The break belongs to the line before it
+        assert_eq!(line_position(&lines, 3), (3, 3));
+        assert_eq!(line_position(&parse_lyric_lines("\nOne"), 0), (1, 1));
+    }
+
+    #[test]
+    fn matching_line_follows_text_across_a_refresh() {
+        let old = parse_lyric_lines("Intro\nVerse one\nChorus\nVerse two\nChorus");
+        // A line was added at the top and "Verse one" was corrected
+        let new = parse_lyric_lines("[Intro]\nIntro\nVerse 1\nChorus\nVerse two\nChorus");
+        assert_eq!(matching_line(&old, 0, &new), 1);
+        assert_eq!(matching_line(&old, 1, &new), 2); // Changed line: located through the chorus after it
+        assert_eq!(matching_line(&old, 4, &new), 5); // Repeated line: the nearest copy
+        assert_eq!(matching_line(&old, 3, &parse_lyric_lines("Something else\nentirely")), 1);
+    }
+
+    #[test]
+    fn line_progress_runs_until_the_next_timestamp() {
+        let lines = parse_lyric_lines("[00:10.00]One\n[00:14.00]Two\nPlain");
+        assert_eq!(line_progress(&lines, 0, 10_000), Some(0.0));
+        assert_eq!(line_progress(&lines, 0, 12_000), Some(0.5));
+        assert_eq!(line_progress(&lines, 0, 20_000), Some(1.0));
+        assert_eq!(line_progress(&lines, 1, 15_000), None); // Last timed line: nothing marks its end
+        assert_eq!(line_progress(&lines, 2, 15_000), None);
+    }
+
+    #[test]
+    fn tapped_times_become_lrc_tags() {
+        let lrc = tapped_lrc("First\nSecond\n\nThird\nFourth", &[4_210, 9_000, 61_555]);
+        assert_eq!(lrc, "[00:04.21]First\n[00:09.00]Second\n\n[01:01.55]Third\nFourth");
+        // Round-trips through the LRC parser
+        let lines = parse_lyric_lines(&lrc);
+        assert_eq!(lines[3].time_ms, Some(61_550));
+        assert_eq!(lines[3].text, "Third");
+        assert_eq!(lines[4].time_ms, None);
+    }
+
+    #[test]
+    fn parses_lrc_timestamps() {
+        let lines = parse_lyric_lines("[00:01.00]One\n[01:02.345]Two\nPlain");
+        assert_eq!(lines[0], LyricLine { time_ms: Some(1000), text: "One".to_string(), annotated: false });
+        assert_eq!(lines[1], LyricLine { time_ms: Some(62_345), text: "Two".to_string(), annotated: false });
+        assert_eq!(lines[2], LyricLine { time_ms: None, text: "Plain".to_string(), annotated: false });
+
+        assert_eq!(current_line_index(&lines, 500), None);
+        assert_eq!(current_line_index(&lines, 1500), Some(0));
+        assert_eq!(current_line_index(&lines, 70_000), Some(1));
+    }
+
+    #[test]
+    fn formats_timestamps_as_minutes_and_seconds() {
+        assert_eq!(format_timestamp(0), "[00:00]");
+        assert_eq!(format_timestamp(62_345), "[01:02]");
+        assert_eq!(format_timestamp(600_999), "[10:00]");
+    }
+
+    // --- Fetching against a mock Genius ---
+
+    fn mock_options(server: &MockServer) -> FetchOptions {
+        FetchOptions { base_url: server.uri(), ..FetchOptions::default() }
+    }
+
+    #[tokio::test]
+    async fn fetches_and_cleans_lyrics_from_server() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/queen-bohemian-rhapsody-lyrics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(CURRENT_LAYOUT))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let fetched = fetch_and_parse_lyrics(&["Queen".to_string()], "Bohemian Rhapsody", &mock_options(&server)).await.unwrap();
+        assert_eq!(fetched.lyrics, CURRENT_LAYOUT_LYRICS);
+        assert_eq!(fetched.url, format!("{}/queen-bohemian-rhapsody-lyrics", server.uri()));
+    }
+
+    #[tokio::test]
+    async fn retries_with_the_featured_artist_from_the_title() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/artist-song-lyrics"))
+            .respond_with(ResponseTemplate::new(404))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/artist-and-guest-song-lyrics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(CURRENT_LAYOUT))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let fetched = fetch_and_parse_lyrics(&["Artist".to_string()], "Song (feat. Guest)", &mock_options(&server)).await.unwrap();
+        assert_eq!(fetched.url, format!("{}/artist-and-guest-song-lyrics", server.uri()));
+    }
+
+    #[tokio::test]
+    async fn best_match_prefers_the_more_complete_result() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/artist-song-lyrics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(LEGACY_LAYOUT))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/artist-and-guest-song-lyrics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(CURRENT_LAYOUT))
+            .mount(&server)
+            .await;
+
+        let artists = ["Artist".to_string()];
+        let first = fetch_and_parse_lyrics(&artists, "Song (feat. Guest)", &mock_options(&server)).await.unwrap();
+        assert_eq!(first.url, format!("{}/artist-song-lyrics", server.uri()));
+
+        let options = FetchOptions { best_match: true, ..mock_options(&server) };
+        let best = fetch_and_parse_lyrics(&artists, "Song (feat. Guest)", &options).await.unwrap();
+        assert_eq!(best.lyrics, CURRENT_LAYOUT_LYRICS);
+    }
+
+    #[tokio::test]
+    async fn follows_redirects_and_reports_final_url() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/artist-song-lyrics"))
+            .respond_with(ResponseTemplate::new(301).insert_header("Location", "/artist-the-song-lyrics"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/artist-the-song-lyrics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(LEGACY_LAYOUT))
+            .mount(&server)
+            .await;
+
+        let fetched = fetch_and_parse_lyrics(&["Artist".to_string()], "Song", &mock_options(&server)).await.unwrap();
+        assert_eq!(fetched.url, format!("{}/artist-the-song-lyrics", server.uri()));
+    }
+
+    #[tokio::test]
+    async fn searches_genius_when_slug_is_not_found() {
+        let server = MockServer::start().await;
+        let found_url = format!("{}/artist-the-song-lyrics", server.uri());
+        Mock::given(method("GET"))
+            .and(path("/artist-song-lyrics"))
+            .respond_with(ResponseTemplate::new(404))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/search/song"))
+            .and(query_param("q", "Artist Song"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "response": { "sections": [{ "hits": [
+                    { "type": "song", "result": { "title": "The Song", "url": found_url } }
+                ] }] }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/artist-the-song-lyrics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(CURRENT_LAYOUT))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let fetched = fetch_and_parse_lyrics(&["Artist".to_string()], "Song", &mock_options(&server)).await.unwrap();
+        assert_eq!(fetched.lyrics, CURRENT_LAYOUT_LYRICS);
+        assert_eq!(fetched.url, found_url);
+    }
+
+    #[tokio::test]
+    async fn tries_alternate_host_when_primary_404s() {
+        let primary = MockServer::start().await;
+        let alternate = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/artist-song-lyrics"))
+            .respond_with(ResponseTemplate::new(404))
+            .expect(1)
+            .mount(&primary)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/artist-song-lyrics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(CURRENT_LAYOUT))
+            .expect(1)
+            .mount(&alternate)
+            .await;
+
+        let options = FetchOptions { alternate_hosts: vec![alternate.uri()], ..mock_options(&primary) };
+        let fetched = fetch_and_parse_lyrics(&["Artist".to_string()], "Song", &options).await.unwrap();
+        assert_eq!(fetched.lyrics, CURRENT_LAYOUT_LYRICS);
+        assert_eq!(fetched.url, format!("{}/artist-song-lyrics", alternate.uri()));
+    }
+
+    #[tokio::test]
+    async fn reports_missing_page() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET")).respond_with(ResponseTemplate::new(404)).mount(&server).await;
+
+        let error = fetch_and_parse_lyrics(&["Nobody".to_string()], "Nothing", &mock_options(&server)).await.unwrap_err();
+        assert!(error.contains("404"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn retries_once_after_rate_limit() {
+        let server = MockServer::start().await;
+        // First request is throttled (mounted first, so it wins while it still matches)
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(LEGACY_LAYOUT))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let fetched = fetch_and_parse_lyrics(&["Artist".to_string()], "Song", &mock_options(&server)).await.unwrap();
+        assert!(fetched.lyrics.starts_with("Old layout line one"), "{}", fetched.lyrics);
+    }
+
+    #[tokio::test]
+    async fn reports_rate_limit_when_the_retry_would_miss_the_deadline() {
+        // Not from the shared pool: the 30s cooldown on its address must not reach the other tests
+        let server = MockServer::builder().start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "30"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let options = FetchOptions { deadline: Duration::from_secs(2), max_attempts: 1, ..mock_options(&server) };
+        let started = Instant::now();
+        let error = fetch_and_parse_lyrics(&["Artist".to_string()], "Song", &options).await.unwrap_err();
+        assert!(error.starts_with("Rate limited by Genius"), "{}", error);
+        assert!(started.elapsed() < options.deadline);
+    }
+}
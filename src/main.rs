@@ -8,6 +8,14 @@ mod app;
 mod lyrics;
 mod spotify;
 mod cache; // Declare cache module
+mod server;
+mod config;
+mod artwork;
+mod secrets;
+mod nowplaying;
+mod playlog;
+
+const DEFAULT_WINDOW_SIZE: [f32; 2] = [400.0, 600.0];
 
 #[tokio::main] // Make main async
 async fn main() -> Result<(), Box<dyn std::error::Error>> { // Return Box<dyn Error>
@@ -16,12 +24,37 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> { // Return Box<dyn Er
 
     println!("Starting Spotify Lyrics Overlay...");
 
-    // Initialize the Spotify client (await the async function)
-    spotify::init_client().await?; // Use .await and ?
+    let daemon = std::env::args().skip(1).any(|arg| arg == "--daemon"); // Headless: poll loop and local server only
+
+    // Settings are needed before auth: they pick the Spotify endpoint (and therefore the scope)
+    let settings = config::load_settings();
+
+    // Initialize the Spotify client (await the async function); not needed when no source uses Spotify
+    if nowplaying::uses_spotify(&settings) {
+        spotify::init_client(settings.playback_endpoint, settings.show_up_next, settings.volume_control, settings.use_keyring).await?; // Use .await and ?
+    }
+
+    // One poll before the window opens, so it starts out showing the current song instead of a blank overlay
+    let startup_info = if settings.startup_poll {
+        nowplaying::current(&settings).await.unwrap_or_else(|e| {
+            eprintln!("Startup poll failed: {}", e);
+            None
+        })
+    } else {
+        None
+    };
+
+    if daemon {
+        return app::run_daemon(settings, startup_info).await.map_err(Into::into);
+    }
 
     // Configure viewport settings (size, always_on_top, transparency)
+    let window_size = match settings.window_sizing {
+        config::WindowSizing::Manual => settings.window_size.unwrap_or(DEFAULT_WINDOW_SIZE),
+        config::WindowSizing::FitContent => DEFAULT_WINDOW_SIZE, // Adjusted to the content on the first frames
+    };
     let viewport = egui::ViewportBuilder::default()
-        .with_inner_size([400.0, 600.0])
+        .with_inner_size(window_size)
         .with_always_on_top() // Keep always on top
         .with_transparent(true); // Use egui's transparency setting
 
@@ -35,7 +68,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> { // Return Box<dyn Er
     eframe::run_native(
         "Spotify Lyrics Overlay", // Window title
         options,
-        Box::new(|cc| Box::new(app::LyricsApp::new(cc))),
+        Box::new(|cc| Box::new(app::LyricsApp::new(cc, settings, startup_info))),
     )?; // Use ? to propagate eframe errors
 
     Ok(()) // Return Ok if everything ran successfully
@@ -0,0 +1,467 @@
+// Where "what's playing" comes from. Spotify is the default; last.fm covers other players that scrobble,
+// MPRIS (Linux, feature `mpris`) any local player that exposes the D-Bus media interface, the Spotify app
+// source the desktop client on macOS (through AppleScript), and the remote source whatever track another
+// instance or a companion app POSTs to the local server (see server.rs).
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Settings;
+use crate::spotify::{self, SpotifyInfo};
+
+const LASTFM_API_URL: &str = "https://ws.audioscrobbler.com/2.0/";
+
+/// Which now-playing source the poll loop uses (applies after restart).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SourceKind {
+    #[default]
+    Spotify,
+    LastFm, // The user's last.fm "now playing" scrobble (needs a user name and API key)
+    Mpris, // The active MPRIS player (Linux only, needs the `mpris` feature)
+    SpotifyApp, // The Spotify desktop app, asked directly through AppleScript (macOS only)
+    Remote, // Track pushed to the local server's POST /track, e.g. a friend's session relayed by a companion
+}
+
+/// A now-playing provider, mirroring `spotify::get_current_info`.
+/// Sources without progress/duration leave those fields empty (no sync highlighting then).
+pub trait NowPlayingSource {
+    async fn current(&self) -> Result<Option<SpotifyInfo>, String>;
+}
+
+pub struct SpotifySource {
+    pub preferred_device: Option<String>,
+}
+
+impl NowPlayingSource for SpotifySource {
+    async fn current(&self) -> Result<Option<SpotifyInfo>, String> {
+        spotify::get_current_info(self.preferred_device.as_deref()).await
+    }
+}
+
+pub struct LastFmSource {
+    pub user: String,
+    pub api_key: String,
+    pub api_url: String, // Overridable for tests
+}
+
+/// The active MPRIS player (the one playing, or the most recently active one).
+pub struct MprisSource;
+
+#[cfg(all(feature = "mpris", target_os = "linux"))]
+impl NowPlayingSource for MprisSource {
+    async fn current(&self) -> Result<Option<SpotifyInfo>, String> {
+        // The mpris crate talks to D-Bus synchronously
+        tokio::task::spawn_blocking(read_mpris_player).await.map_err(|e| format!("MPRIS lookup failed: {}", e))?
+    }
+}
+
+#[cfg(all(feature = "mpris", target_os = "linux"))]
+fn read_mpris_player() -> Result<Option<SpotifyInfo>, String> {
+    let finder = mpris::PlayerFinder::new().map_err(|e| format!("Failed to connect to D-Bus: {}", e))?;
+    let player = match finder.find_active() {
+        Ok(player) => player,
+        Err(mpris::FindingError::NoPlayerFound) => return Ok(None),
+        Err(e) => return Err(format!("Failed to find an MPRIS player: {}", e)),
+    };
+    let status = player.get_playback_status().map_err(|e| format!("Failed to read {} status: {}", player.identity(), e))?;
+    if status == mpris::PlaybackStatus::Stopped {
+        return Ok(None);
+    }
+    let metadata = player.get_metadata().map_err(|e| format!("Failed to read {} metadata: {}", player.identity(), e))?;
+    let Some(title) = metadata.title().filter(|title| !title.is_empty()) else {
+        return Ok(None);
+    };
+    let duration_ms = metadata.length().and_then(|length| length.as_millis().try_into().ok());
+    Ok(Some(SpotifyInfo {
+        artists: metadata.artists().unwrap_or_default().into_iter().map(String::from).collect(),
+        title: title.to_string(),
+        progress_ms: player.get_position().ok().map(|position| spotify::clamp_progress(position.as_millis().try_into().unwrap_or(i64::MAX), duration_ms)),
+        duration_ms,
+        is_playing: status == mpris::PlaybackStatus::Playing,
+        // Local players often point at file:// covers, which can't be downloaded
+        album_art_url: metadata.art_url().filter(|url| url.starts_with("http")).map(String::from),
+        volume_percent: None,
+    }))
+}
+
+#[cfg(not(all(feature = "mpris", target_os = "linux")))]
+impl NowPlayingSource for MprisSource {
+    async fn current(&self) -> Result<Option<SpotifyInfo>, String> {
+        Err("MPRIS needs Linux and a build with --features mpris".to_string())
+    }
+}
+
+/// The Spotify desktop app on this Mac, read locally, so it keeps working while the Web API is rate limited.
+pub struct SpotifyAppSource;
+
+// Prints one tab-separated line (see parse_spotify_app_state), or nothing when Spotify isn't running or is
+// stopped. Checking `is running` first keeps the script from launching Spotify.
+#[cfg(target_os = "macos")]
+const SPOTIFY_APP_SCRIPT: &str = r#"
+if application "Spotify" is running then
+    tell application "Spotify"
+        if player state is stopped then return ""
+        set art to ""
+        try
+            set art to artwork url of current track
+        end try
+        return (name of current track) & tab & (artist of current track) & tab & (duration of current track) & tab & (player position) & tab & (player state as text) & tab & art
+    end tell
+end if
+return ""
+"#;
+
+#[cfg(target_os = "macos")]
+impl NowPlayingSource for SpotifyAppSource {
+    async fn current(&self) -> Result<Option<SpotifyInfo>, String> {
+        // osascript blocks until Spotify answers
+        let output = tokio::task::spawn_blocking(|| std::process::Command::new("osascript").args(["-e", SPOTIFY_APP_SCRIPT]).output())
+            .await
+            .map_err(|e| format!("Spotify app lookup failed: {}", e))?
+            .map_err(|e| format!("Failed to run osascript: {}", e))?;
+        if !output.status.success() {
+            return Err(format!("Failed to read the Spotify app: {}", String::from_utf8_lossy(&output.stderr).trim()));
+        }
+        parse_spotify_app_state(&String::from_utf8_lossy(&output.stdout))
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+impl NowPlayingSource for SpotifyAppSource {
+    async fn current(&self) -> Result<Option<SpotifyInfo>, String> {
+        Err("The Spotify app source needs macOS".to_string())
+    }
+}
+
+// Title, artist, duration (ms), position (s), player state and artwork URL, as printed by SPOTIFY_APP_SCRIPT
+#[cfg(any(target_os = "macos", test))]
+fn parse_spotify_app_state(output: &str) -> Result<Option<SpotifyInfo>, String> {
+    let output = output.trim_end_matches(['\r', '\n']);
+    if output.trim().is_empty() {
+        return Ok(None);
+    }
+    let fields: Vec<&str> = output.split('\t').collect();
+    let [title, artist, duration, position, state, artwork] = fields[..] else {
+        return Err(format!("Unexpected Spotify app output: {:?}", output));
+    };
+    if title.trim().is_empty() {
+        return Ok(None); // Between tracks or an ad
+    }
+    // AppleScript writes numbers with the user's decimal separator
+    let number = |value: &str| value.trim().replace(',', ".").parse::<f64>().ok();
+    let duration_ms = number(duration).map(|ms| ms as u32).filter(|&ms| ms > 0);
+    Ok(Some(SpotifyInfo {
+        artists: Some(artist.trim()).filter(|artist| !artist.is_empty()).map(String::from).into_iter().collect(),
+        title: title.trim().to_string(),
+        progress_ms: number(position).map(|secs| spotify::clamp_progress((secs * 1000.0) as i64, duration_ms)),
+        duration_ms,
+        is_playing: state.trim() == "playing",
+        album_art_url: Some(artwork.trim()).filter(|url| url.starts_with("http")).map(String::from),
+        volume_percent: None,
+    }))
+}
+
+/// Body of `POST /track` on the local server.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct RemoteTrack {
+    pub uri: String, // Spotify track URI, open.spotify.com link or bare ID
+    #[serde(default)]
+    pub progress_ms: Option<u32>,
+    #[serde(default = "default_remote_playing")]
+    pub is_playing: bool,
+}
+
+fn default_remote_playing() -> bool {
+    true
+}
+
+static LAST_SOURCE: Mutex<Option<SourceKind>> = Mutex::new(None); // Source that answered the last successful poll
+
+// Last pushed track, when it arrived, and its metadata once resolved through the Web API
+static REMOTE_TRACK: Mutex<Option<(RemoteTrack, Instant, Option<SpotifyInfo>)>> = Mutex::new(None);
+
+/// Stores a track pushed to the local server; the remote source picks it up on the next poll.
+pub fn push_remote_track(track: RemoteTrack) -> Result<(), String> {
+    spotify::parse_track_id(&track.uri)?;
+    let mut remote = REMOTE_TRACK.lock().unwrap();
+    // Same track again (e.g. a progress update): keep the resolved metadata
+    let info = remote.take().filter(|(previous, _, _)| previous.uri.trim() == track.uri.trim()).and_then(|(_, _, info)| info);
+    println!("Remote track pushed: {}", track.uri.trim());
+    *remote = Some((track, Instant::now(), info));
+    Ok(())
+}
+
+// The pushed position, advanced by the time since the push while playing
+fn remote_progress(track: &RemoteTrack, duration_ms: Option<u32>, elapsed: Duration) -> Option<u32> {
+    track.progress_ms.map(|progress| {
+        let elapsed_ms = u32::try_from(elapsed.as_millis()).unwrap_or(u32::MAX);
+        let advanced = if track.is_playing { progress.saturating_add(elapsed_ms) } else { progress };
+        duration_ms.map_or(advanced, |duration| advanced.min(duration))
+    })
+}
+
+/// Whatever was last pushed to the local server. Nothing is playing until the first push.
+pub struct RemoteSource;
+
+impl NowPlayingSource for RemoteSource {
+    async fn current(&self) -> Result<Option<SpotifyInfo>, String> {
+        let Some((track, received_at, info)) = REMOTE_TRACK.lock().unwrap().clone() else {
+            return Ok(None);
+        };
+        let info = match info {
+            Some(info) => info,
+            None => {
+                let info = spotify::track_info_from_uri(&track.uri).await?;
+                // Only keep it if no other track was pushed during the lookup
+                if let Some((current, _, resolved)) = REMOTE_TRACK.lock().unwrap().as_mut()
+                    && current.uri == track.uri
+                {
+                    *resolved = Some(info.clone());
+                }
+                info
+            }
+        };
+        Ok(Some(SpotifyInfo {
+            progress_ms: remote_progress(&track, info.duration_ms, received_at.elapsed()),
+            is_playing: track.is_playing,
+            ..info
+        }))
+    }
+}
+
+// --- last.fm recent tracks response ---
+
+#[derive(Deserialize)]
+struct RecentTracksResponse {
+    recenttracks: RecentTracks,
+}
+
+#[derive(Deserialize)]
+struct RecentTracks {
+    #[serde(default)]
+    track: OneOrMany<LastFmTrack>,
+}
+
+// last.fm returns a bare object instead of a one-element array
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> Default for OneOrMany<T> {
+    fn default() -> Self {
+        OneOrMany::Many(Vec::new())
+    }
+}
+
+#[derive(Deserialize)]
+struct LastFmTrack {
+    name: String,
+    artist: LastFmText,
+    #[serde(default)]
+    image: Vec<LastFmImage>,
+    #[serde(rename = "@attr")]
+    attr: Option<LastFmAttr>,
+}
+
+#[derive(Deserialize)]
+struct LastFmText {
+    #[serde(rename = "#text")]
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct LastFmImage {
+    #[serde(rename = "#text")]
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct LastFmAttr {
+    #[serde(default)]
+    nowplaying: String,
+}
+
+// The track marked "now playing", if any (the rest are past scrobbles)
+fn parse_recent_tracks(json: &str) -> Result<Option<SpotifyInfo>, String> {
+    let response: RecentTracksResponse = serde_json::from_str(json).map_err(|e| format!("Unexpected last.fm response: {}", e))?;
+    let tracks = match response.recenttracks.track {
+        OneOrMany::One(track) => vec![track],
+        OneOrMany::Many(tracks) => tracks,
+    };
+    Ok(tracks
+        .into_iter()
+        .find(|track| track.attr.as_ref().is_some_and(|attr| attr.nowplaying == "true"))
+        .map(|track| SpotifyInfo {
+            artists: vec![track.artist.text],
+            title: track.name,
+            progress_ms: None,
+            duration_ms: None,
+            is_playing: true,
+            // Images are listed smallest first; empty URLs mean "no cover"
+            album_art_url: track.image.into_iter().rev().map(|image| image.url).find(|url| !url.is_empty()),
+            volume_percent: None,
+        }))
+}
+
+impl NowPlayingSource for LastFmSource {
+    async fn current(&self) -> Result<Option<SpotifyInfo>, String> {
+        if self.user.trim().is_empty() || self.api_key.trim().is_empty() {
+            return Err("last.fm needs a user name and an API key (settings or LASTFM_API_KEY in .env)".to_string());
+        }
+        let body = reqwest::Client::new()
+            .get(&self.api_url)
+            .query(&[
+                ("method", "user.getrecenttracks"),
+                ("user", self.user.trim()),
+                ("api_key", self.api_key.trim()),
+                ("format", "json"),
+                ("limit", "1"),
+            ])
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| format!("Failed to get last.fm now playing: {}", e))?
+            .text()
+            .await
+            .map_err(|e| format!("Failed to read last.fm response: {}", e))?;
+        parse_recent_tracks(&body)
+    }
+}
+
+/// Polls the source selected in the settings. If it fails (rate limit, network, offline device),
+/// the configured fallbacks are tried in order, e.g. the local media session for the Spotify desktop app.
+pub async fn current(settings: &Settings) -> Result<Option<SpotifyInfo>, String> {
+    let primary_error = match poll_source(settings.now_playing_source, settings).await {
+        Ok(info) => {
+            *LAST_SOURCE.lock().unwrap() = Some(settings.now_playing_source);
+            return Ok(info);
+        }
+        Err(e) => e,
+    };
+    let mut fallback_result = None;
+    for &kind in settings.fallback_sources.iter().filter(|&&kind| kind != settings.now_playing_source) {
+        match poll_source(kind, settings).await {
+            Ok(Some(info)) => {
+                println!("{} (using {:?} instead)", primary_error, kind);
+                *LAST_SOURCE.lock().unwrap() = Some(kind);
+                return Ok(Some(info));
+            }
+            Ok(None) => fallback_result = Some(None), // Reachable, but nothing playing there; try the next one
+            Err(e) => eprintln!("Fallback source {:?} failed: {}", kind, e),
+        }
+    }
+    fallback_result.ok_or(primary_error)
+}
+
+/// The source that answered the last successful poll (the primary one, or the fallback that stood in).
+pub fn last_source() -> Option<SourceKind> {
+    *LAST_SOURCE.lock().unwrap()
+}
+
+/// Whether the primary source or any fallback needs the Spotify login (remote tracks are looked up through it too).
+pub fn uses_spotify(settings: &Settings) -> bool {
+    [SourceKind::Spotify, SourceKind::Remote]
+        .iter()
+        .any(|kind| settings.now_playing_source == *kind || settings.fallback_sources.contains(kind))
+}
+
+/// Whether the primary source or any fallback listens on the local server.
+pub fn uses_remote(settings: &Settings) -> bool {
+    settings.now_playing_source == SourceKind::Remote || settings.fallback_sources.contains(&SourceKind::Remote)
+}
+
+async fn poll_source(kind: SourceKind, settings: &Settings) -> Result<Option<SpotifyInfo>, String> {
+    match kind {
+        SourceKind::Spotify => {
+            let preferred_device = settings.preferred_device.as_ref().map(|(id, _)| id.clone());
+            SpotifySource { preferred_device }.current().await
+        }
+        SourceKind::LastFm => {
+            let api_key = if settings.lastfm_api_key.trim().is_empty() {
+                std::env::var("LASTFM_API_KEY").unwrap_or_default()
+            } else {
+                settings.lastfm_api_key.clone()
+            };
+            LastFmSource { user: settings.lastfm_user.clone(), api_key, api_url: LASTFM_API_URL.to_string() }.current().await
+        }
+        SourceKind::Mpris => MprisSource.current().await,
+        SourceKind::SpotifyApp => SpotifyAppSource.current().await,
+        SourceKind::Remote => RemoteSource.current().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn picks_the_now_playing_scrobble() {
+        let json = r##"{"recenttracks":{"track":[
+            {"name":"Halo","artist":{"#text":"Beyoncé"},"image":[{"#text":"small.png","size":"small"},{"#text":"large.png","size":"extralarge"}],"@attr":{"nowplaying":"true"}},
+            {"name":"Older","artist":{"#text":"Someone"},"image":[]}
+        ]}}"##;
+        let info = parse_recent_tracks(json).unwrap().unwrap();
+        assert_eq!(info.artists, vec!["Beyoncé".to_string()]);
+        assert_eq!(info.title, "Halo");
+        assert_eq!(info.album_art_url.as_deref(), Some("large.png"));
+        assert_eq!(info.progress_ms, None);
+    }
+
+    #[test]
+    fn past_scrobbles_are_not_playing() {
+        let json = r##"{"recenttracks":{"track":{"name":"Older","artist":{"#text":"Someone"}}}}"##;
+        assert_eq!(parse_recent_tracks(json).unwrap(), None);
+    }
+
+    #[test]
+    fn remote_progress_advances_only_while_playing() {
+        let mut track = RemoteTrack { uri: "spotify:track:4uLU6hMCjMI75M1A2tKUQC".to_string(), progress_ms: Some(10_000), is_playing: true };
+        assert_eq!(remote_progress(&track, Some(200_000), Duration::from_secs(5)), Some(15_000));
+        assert_eq!(remote_progress(&track, Some(12_000), Duration::from_secs(5)), Some(12_000)); // Capped at the track's end
+        track.is_playing = false;
+        assert_eq!(remote_progress(&track, Some(200_000), Duration::from_secs(5)), Some(10_000));
+        track.progress_ms = None;
+        assert_eq!(remote_progress(&track, Some(200_000), Duration::from_secs(5)), None);
+    }
+
+    #[test]
+    fn reads_the_spotify_app_state() {
+        let output = "Halo\tBeyoncé\t261640\t12,5\tplaying\thttps://i.scdn.co/image/cover\n";
+        let info = parse_spotify_app_state(output).unwrap().unwrap();
+        assert_eq!(info.artists, vec!["Beyoncé".to_string()]);
+        assert_eq!(info.title, "Halo");
+        assert_eq!(info.duration_ms, Some(261_640));
+        assert_eq!(info.progress_ms, Some(12_500)); // Comma as the decimal separator
+        assert!(info.is_playing);
+        assert_eq!(info.album_art_url.as_deref(), Some("https://i.scdn.co/image/cover"));
+
+        assert_eq!(parse_spotify_app_state("\n").unwrap(), None); // Not running or stopped
+        assert!(parse_spotify_app_state("Halo\tBeyoncé").is_err());
+    }
+
+    #[tokio::test]
+    async fn queries_recent_tracks_for_the_user() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(query_param("method", "user.getrecenttracks"))
+            .and(query_param("user", "listener"))
+            .and(query_param("api_key", "key"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r##"{"recenttracks":{"track":[{"name":"Halo","artist":{"#text":"Beyoncé"},"@attr":{"nowplaying":"true"}}]}}"##,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let source = LastFmSource { user: "listener".to_string(), api_key: "key".to_string(), api_url: server.uri() };
+        let info = source.current().await.unwrap().unwrap();
+        assert_eq!(info.title, "Halo");
+    }
+}
@@ -0,0 +1,184 @@
+// Listening log: every detected song (time, artists, title, lyrics outcome, now-playing source) is
+// buffered in memory and, when the user opts in, appended to a JSONL or CSV file in the config dir.
+// Without file writing the buffer is only a bounded trail of the most recent songs, and it's discarded when
+// writing is turned on, so the file only gets songs played after the user opted in.
+use std::{
+    collections::VecDeque,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::nowplaying::SourceKind;
+
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(60); // How often the poll loop appends new entries
+const MAX_BUFFERED_ENTRIES: usize = 500; // Oldest entries are dropped beyond this (e.g. while writing is off)
+const CSV_HEADER: &str = "time,artists,title,lyrics_found,source";
+
+/// File format of the listening log.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlayLogFormat {
+    #[default]
+    Jsonl, // One JSON object per line
+    Csv, // Header row, then one row per song
+}
+
+impl PlayLogFormat {
+    fn file_name(self) -> &'static str {
+        match self {
+            PlayLogFormat::Jsonl => "listening_log.jsonl",
+            PlayLogFormat::Csv => "listening_log.csv",
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct PlayLogEntry {
+    pub time: String, // Local time the song was detected (RFC 3339)
+    pub artists: Vec<String>,
+    pub title: String,
+    pub lyrics_found: Option<bool>, // None while the lookup is still running (or fetching is paused)
+    pub source: Option<SourceKind>, // Now-playing source that reported the song
+}
+
+impl PlayLogEntry {
+    fn to_csv_row(&self) -> String {
+        let lyrics_found = self.lyrics_found.map_or(String::new(), |found| found.to_string());
+        let source = self.source.map_or(String::new(), |kind| format!("{:?}", kind));
+        [self.time.as_str(), &self.artists.join("; "), &self.title, &lyrics_found, &source]
+            .iter()
+            .map(|field| csv_field(field))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// Quotes a CSV field if it contains a separator, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+static PENDING: Mutex<VecDeque<PlayLogEntry>> = Mutex::new(VecDeque::new());
+
+/// Buffers a newly detected song.
+pub fn record(artists: &[String], title: &str, source: Option<SourceKind>) {
+    let mut pending = PENDING.lock().unwrap();
+    pending.push_back(PlayLogEntry {
+        time: chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+        artists: artists.to_vec(),
+        title: title.to_string(),
+        lyrics_found: None,
+        source,
+    });
+    while pending.len() > MAX_BUFFERED_ENTRIES {
+        pending.pop_front();
+    }
+}
+
+/// Recovers the buffer after a panic while it was locked, so the songs in it are still written.
+pub fn clear_poison() {
+    PENDING.clear_poison();
+}
+
+/// Forgets all buffered songs, so those played before the log was turned on are never written.
+pub fn discard_pending() {
+    PENDING.lock().unwrap().clear();
+}
+
+/// Sets the lyrics outcome of the newest buffered entry, if it's this song.
+pub fn set_lyrics_found(artists: &[String], title: &str, found: bool) {
+    if let Some(entry) = PENDING.lock().unwrap().back_mut()
+        && entry.artists == artists
+        && entry.title == title
+    {
+        entry.lyrics_found = Some(found);
+    }
+}
+
+/// Where the log is written (in the settings directory).
+pub fn log_path(format: PlayLogFormat) -> Option<PathBuf> {
+    crate::config::config_dir().ok().map(|dir| dir.join(format.file_name()))
+}
+
+/// Appends buffered entries to the log file and drops them from the buffer. The newest entry is held back
+/// while its lookup is still running, unless `include_unresolved` (on exit). Returns the number written.
+pub fn flush(format: PlayLogFormat, include_unresolved: bool) -> Result<usize, String> {
+    let path = log_path(format).ok_or("No config directory for the listening log")?;
+    let mut pending = PENDING.lock().unwrap();
+    let held_back = !include_unresolved && pending.back().is_some_and(|entry| entry.lyrics_found.is_none());
+    let count = pending.len() - usize::from(held_back);
+    if count == 0 {
+        return Ok(0);
+    }
+    append_entries(&path, format, pending.range(..count))?;
+    pending.drain(..count);
+    Ok(count)
+}
+
+fn append_entries<'a>(path: &Path, format: PlayLogFormat, entries: impl Iterator<Item = &'a PlayLogEntry>) -> Result<(), String> {
+    let is_new = fs::metadata(path).map_or(true, |meta| meta.len() == 0);
+    let mut out = String::new();
+    if is_new && format == PlayLogFormat::Csv {
+        out.push_str(CSV_HEADER);
+        out.push('\n');
+    }
+    for entry in entries {
+        let line = match format {
+            PlayLogFormat::Jsonl => serde_json::to_string(entry).map_err(|e| format!("Failed to serialize log entry: {}", e))?,
+            PlayLogFormat::Csv => entry.to_csv_row(),
+        };
+        out.push_str(&line);
+        out.push('\n');
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Failed to open listening log {}: {}", path.display(), e))?;
+    file.write_all(out.as_bytes())
+        .map_err(|e| format!("Failed to write listening log {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(title: &str) -> PlayLogEntry {
+        PlayLogEntry {
+            time: "2024-05-01T12:00:00+02:00".to_string(),
+            artists: vec!["Simon, Garfunkel".to_string(), "Guest".to_string()],
+            title: title.to_string(),
+            lyrics_found: Some(true),
+            source: Some(SourceKind::Spotify),
+        }
+    }
+
+    #[test]
+    fn csv_rows_quote_separators() {
+        assert_eq!(
+            entry("The \"Boxer\"").to_csv_row(),
+            "2024-05-01T12:00:00+02:00,\"Simon, Garfunkel; Guest\",\"The \"\"Boxer\"\"\",true,Spotify"
+        );
+    }
+
+    #[test]
+    fn csv_header_is_written_once() {
+        let path = std::env::temp_dir().join(format!("lyricrs-playlog-test-{}.csv", std::process::id()));
+        let _ = fs::remove_file(&path);
+        append_entries(&path, PlayLogFormat::Csv, [entry("One")].iter()).unwrap();
+        append_entries(&path, PlayLogFormat::Csv, [entry("Two")].iter()).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], CSV_HEADER);
+        assert!(lines[2].contains(",Two,"));
+    }
+}
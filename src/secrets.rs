@@ -0,0 +1,50 @@
+// Optional OS keyring storage (feature `keyring`) for Spotify credentials and the OAuth token,
+// so neither has to live in plain-text files. Without the feature, or when the platform has no
+// usable keyring, lookups simply come back empty and callers fall back to .env / the token cache file.
+
+#[cfg(feature = "keyring")]
+const KEYRING_SERVICE: &str = "lyricrs";
+
+pub const CREDENTIALS_ENTRY: &str = "spotify-credentials"; // JSON: client id, secret, redirect URI
+pub const TOKEN_ENTRY: &str = "spotify-token"; // JSON: rspotify Token
+
+/// Whether this build can talk to the OS keyring at all.
+pub fn is_supported() -> bool {
+    cfg!(feature = "keyring")
+}
+
+#[cfg(feature = "keyring")]
+pub fn load_secret(name: &str) -> Option<String> {
+    let entry = match keyring::Entry::new(KEYRING_SERVICE, name) {
+        Ok(entry) => entry,
+        Err(e) => {
+            eprintln!("Keyring unavailable: {}", e);
+            return None;
+        }
+    };
+    match entry.get_password() {
+        Ok(value) => Some(value),
+        Err(keyring::Error::NoEntry) => None,
+        Err(e) => {
+            eprintln!("Failed to read '{}' from the keyring: {}", name, e);
+            None
+        }
+    }
+}
+
+#[cfg(feature = "keyring")]
+pub fn store_secret(name: &str, value: &str) -> Result<(), String> {
+    keyring::Entry::new(KEYRING_SERVICE, name)
+        .and_then(|entry| entry.set_password(value))
+        .map_err(|e| format!("Failed to store '{}' in the keyring: {}", name, e))
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn load_secret(_name: &str) -> Option<String> {
+    None
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn store_secret(name: &str, _value: &str) -> Result<(), String> {
+    Err(format!("Cannot store '{}': built without keyring support", name))
+}
@@ -0,0 +1,334 @@
+// Local now-playing server for OBS / stream widgets.
+//
+// Routes (bound to 127.0.0.1):
+//   /now-playing  -> JSON: { "song": SpotifyInfo | null, "progress_ms": estimated position,
+//                            "lyrics": full lyrics text, "line": current synced line | null,
+//                            "status": status text }
+//   /line         -> text/plain: the current synced line (empty when lyrics aren't synced)
+//   /ws           -> WebSocket: pushes the current synced line as a text message whenever it changes
+//   POST /track   -> JSON body { "uri": Spotify track URI/link, "progress_ms": optional, "is_playing": optional }:
+//                    the track shown by the "Remote" now-playing source. Refused (403) for non-loopback peers and
+//                    for browser requests from pages that aren't served from localhost.
+// Anything else returns 404. Browser pages may only read the routes (CORS) or open /ws if they're served from
+// localhost or are an OBS local file; other sites get no CORS header and a refused WebSocket handshake.
+//
+// Driving another instance, e.g. from a script following a friend's session:
+//   curl -X POST http://127.0.0.1:7878/track -d '{"uri": "spotify:track:4uLU6hMCjMI75M1A2tKUQC", "progress_ms": 30000}'
+//
+// Minimal OBS browser source for /ws:
+//   <html><body style="color: white; font: bold 32px sans-serif;">
+//     <div id="line"></div>
+//     <script>
+//       const ws = new WebSocket("ws://127.0.0.1:7878/ws");
+//       ws.onmessage = (event) => { document.getElementById("line").textContent = event.data; };
+//     </script>
+//   </body></html>
+//
+// Without the server, the current line can also be written to a file or named pipe ("line output") for
+// Stream Deck plugins and similar tools: a plain file is replaced with the line on every change, a pipe
+// gets one line of text per change.
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+use tungstenite::{
+    handshake::server::{ErrorResponse, Request, Response},
+    http::StatusCode,
+    Message,
+};
+
+use crate::app::AppState;
+use crate::nowplaying;
+
+pub const SERVER_ADDR: &str = "127.0.0.1:7878"; // Localhost only, never exposed on the network
+const WS_POLL_INTERVAL: Duration = Duration::from_millis(100); // How often /ws checks for a new line
+const MAX_BODY_BYTES: usize = 4096; // A track push is a few dozen bytes
+
+/// Keeps the server running; dropping it stops the accept loop.
+pub struct ServerHandle {
+    stop: Arc<AtomicBool>,
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Binds the listener and serves requests on a background thread.
+pub fn start(state: Arc<Mutex<AppState>>) -> Result<ServerHandle, io::Error> {
+    let listener = TcpListener::bind(SERVER_ADDR)?;
+    // Non-blocking accept so the loop can notice the stop flag
+    listener.set_nonblocking(true)?;
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_clone = Arc::clone(&stop);
+
+    std::thread::spawn(move || {
+        println!("Local server listening on http://{}", SERVER_ADDR);
+        while !stop_clone.load(Ordering::Relaxed) {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    let state = Arc::clone(&state);
+                    let stop = Arc::clone(&stop_clone);
+                    std::thread::spawn(move || {
+                        if let Err(e) = handle_connection(stream, &state, &stop) {
+                            eprintln!("Local server connection error: {}", e);
+                        }
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+                Err(e) => {
+                    eprintln!("Local server accept error: {}", e);
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+            }
+        }
+        println!("Local server stopped.");
+    });
+
+    Ok(ServerHandle { stop })
+}
+
+fn handle_connection(stream: TcpStream, state: &Mutex<AppState>, stop: &AtomicBool) -> Result<(), io::Error> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    // Peek at the request line so a WebSocket upgrade can still read the full handshake
+    let mut peek_buf = [0u8; 512];
+    let peeked = stream.peek(&mut peek_buf)?;
+    let request_start = String::from_utf8_lossy(&peek_buf[..peeked]);
+    if request_start.starts_with("GET /ws ") {
+        return handle_websocket(stream, state, stop);
+    }
+
+    // Request line, e.g. "GET /now-playing HTTP/1.1"
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("");
+    let (origin, content_length) = read_headers(&mut reader)?;
+
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/now-playing") => ("200 OK", "application/json", now_playing_json(state)),
+        ("GET", "/line") => ("200 OK", "text/plain; charset=utf-8", current_line_text(state)),
+        ("POST", "/track") => {
+            let local_peer = stream.peer_addr().is_ok_and(|addr| addr.ip().is_loopback());
+            if !local_peer || !origin.as_deref().is_none_or(is_local_origin) {
+                ("403 Forbidden", "text/plain; charset=utf-8", "Only local clients may push tracks".to_string())
+            } else if content_length > MAX_BODY_BYTES {
+                ("413 Payload Too Large", "text/plain; charset=utf-8", "Body too large".to_string())
+            } else {
+                let mut body = vec![0u8; content_length];
+                reader.read_exact(&mut body)?;
+                match serde_json::from_slice(&body).map_err(|e| format!("Invalid track JSON: {}", e)).and_then(nowplaying::push_remote_track) {
+                    Ok(()) => ("200 OK", "text/plain; charset=utf-8", "OK".to_string()),
+                    Err(e) => ("400 Bad Request", "text/plain; charset=utf-8", e),
+                }
+            }
+        }
+        _ => ("404 Not Found", "text/plain; charset=utf-8", "Not found".to_string()),
+    };
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        cors_headers(origin.as_deref()),
+        body
+    )?;
+    stream.flush()
+}
+
+// Reads the remaining headers, returning the Origin (sent by browsers) and the body length
+fn read_headers(reader: &mut impl BufRead) -> Result<(Option<String>, usize), io::Error> {
+    let mut origin = None;
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("origin") {
+                origin = Some(value.trim().to_string());
+            } else if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    Ok((origin, content_length))
+}
+
+// Blocks web pages from reading lyrics or pushing tracks through the user's browser; local widgets and tools may.
+// OBS serves browser sources from local files as http://absolute/, a host no website can have.
+fn is_local_origin(origin: &str) -> bool {
+    let host = origin.split("://").nth(1).unwrap_or("");
+    let host = host.rsplit_once(':').filter(|(_, port)| port.chars().all(|c| c.is_ascii_digit())).map_or(host, |(host, _)| host);
+    matches!(host, "localhost" | "127.0.0.1" | "[::1]" | "absolute")
+}
+
+// Lets a local page read the response; requests without an Origin (curl, OBS URL sources) don't need it
+fn cors_headers(origin: Option<&str>) -> String {
+    match origin {
+        Some(origin) if is_local_origin(origin) => format!("Access-Control-Allow-Origin: {}\r\nVary: Origin\r\n", origin),
+        _ => "Vary: Origin\r\n".to_string(),
+    }
+}
+
+fn handle_websocket(stream: TcpStream, state: &Mutex<AppState>, stop: &AtomicBool) -> Result<(), io::Error> {
+    // Browsers don't apply CORS to WebSockets, so the handshake checks the Origin itself
+    #[allow(clippy::result_large_err)] // The error type is tungstenite's
+    let check_origin = |request: &Request, response: Response| {
+        let origin = request.headers().get("origin").map(|value| value.to_str().unwrap_or_default());
+        if origin.is_none_or(is_local_origin) {
+            Ok(response)
+        } else {
+            let mut refusal = ErrorResponse::new(Some("Only local pages may connect".to_string()));
+            *refusal.status_mut() = StatusCode::FORBIDDEN;
+            Err(refusal)
+        }
+    };
+    let mut socket = tungstenite::accept_hdr(stream, check_origin)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+    // Push only on change; the first message is always sent so new clients get the current line
+    let mut last_sent: Option<String> = None;
+    while !stop.load(Ordering::Relaxed) {
+        let line = current_line_text(state);
+        if last_sent.as_ref() != Some(&line) {
+            if let Err(e) = socket.send(Message::text(line.clone())) {
+                // Client went away (closed tab / OBS scene switch)
+                println!("WebSocket client disconnected: {}", e);
+                return Ok(());
+            }
+            last_sent = Some(line);
+        }
+        std::thread::sleep(WS_POLL_INTERVAL);
+    }
+    socket.close(None).ok(); // Server was switched off
+    Ok(())
+}
+
+/// Writes the current line to `path` whenever it changes, on a background thread; dropping the handle stops it.
+/// A named pipe blocks until something reads from it, which only holds up this thread.
+pub fn start_line_output(state: Arc<Mutex<AppState>>, path: PathBuf) -> ServerHandle {
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_clone = Arc::clone(&stop);
+
+    std::thread::spawn(move || {
+        println!("Writing the current lyric line to {}", path.display());
+        let is_pipe = is_named_pipe(&path);
+        let mut last_written: Option<String> = None;
+        while !stop_clone.load(Ordering::Relaxed) {
+            let line = current_line_text(&state);
+            if last_written.as_ref() != Some(&line) {
+                match write_line_output(&path, &line, is_pipe) {
+                    Ok(()) => last_written = Some(line),
+                    Err(e) => {
+                        eprintln!("Failed to write the current line to {}: {}", path.display(), e);
+                        std::thread::sleep(Duration::from_secs(1)); // Don't spin on a missing directory
+                    }
+                }
+            }
+            std::thread::sleep(WS_POLL_INTERVAL);
+        }
+        println!("Line output stopped.");
+    });
+
+    ServerHandle { stop }
+}
+
+#[cfg(unix)]
+fn is_named_pipe(path: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    fs::metadata(path).is_ok_and(|meta| meta.file_type().is_fifo())
+}
+
+#[cfg(not(unix))]
+fn is_named_pipe(path: &Path) -> bool {
+    path.to_string_lossy().starts_with(r"\\.\pipe\")
+}
+
+// Pipes get the line appended; files are replaced through a temporary file so readers never see half a line
+fn write_line_output(path: &Path, line: &str, is_pipe: bool) -> Result<(), io::Error> {
+    if is_pipe {
+        let mut pipe = fs::OpenOptions::new().write(true).open(path)?;
+        return writeln!(pipe, "{}", line);
+    }
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, line)?;
+    fs::rename(&temp_path, path)
+}
+
+fn now_playing_json(state: &Mutex<AppState>) -> String {
+    let state = state.lock().unwrap_or_else(PoisonError::into_inner);
+    serde_json::json!({
+        "song": state.current_info,
+        "progress_ms": state.interpolated_progress_ms(),
+        "lyrics": state.lyrics,
+        "line": state.current_line().map(|line| &line.text),
+        "status": state.status,
+    })
+    .to_string()
+}
+
+fn current_line_text(state: &Mutex<AppState>) -> String {
+    let state = state.lock().unwrap_or_else(PoisonError::into_inner);
+    state.current_line().map(|line| line.text.clone()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_localhost_origins_may_push() {
+        assert!(is_local_origin("http://localhost:3000"));
+        assert!(is_local_origin("http://127.0.0.1"));
+        assert!(is_local_origin("http://[::1]:7878"));
+        assert!(!is_local_origin("https://example.com"));
+        assert!(!is_local_origin("http://localhost.example.com"));
+        assert!(!is_local_origin("null")); // Sandboxed iframes and file:// pages
+        assert!(is_local_origin("http://absolute")); // OBS local file
+    }
+
+    #[test]
+    fn only_local_origins_may_read_cross_origin() {
+        assert_eq!(cors_headers(Some("http://localhost:3000")), "Access-Control-Allow-Origin: http://localhost:3000\r\nVary: Origin\r\n");
+        assert_eq!(cors_headers(Some("https://example.com")), "Vary: Origin\r\n");
+        assert_eq!(cors_headers(None), "Vary: Origin\r\n");
+    }
+
+    #[test]
+    fn line_output_replaces_the_file() {
+        let path = std::env::temp_dir().join(format!("lyricrs-line-test-{}.txt", std::process::id()));
+        write_line_output(&path, "First line", false).unwrap();
+        write_line_output(&path, "Second", false).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "Second");
+        assert!(!path.with_extension("tmp").exists());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn reads_origin_and_content_length() {
+        let mut headers = "Host: 127.0.0.1:7878\r\nOrigin: http://localhost:3000\r\nContent-Length: 42\r\n\r\n{}".as_bytes();
+        let (origin, content_length) = read_headers(&mut headers).unwrap();
+        assert_eq!(origin.as_deref(), Some("http://localhost:3000"));
+        assert_eq!(content_length, 42);
+    }
+}
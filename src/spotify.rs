@@ -2,114 +2,745 @@ use rspotify::{
     prelude::*,
     scopes, // Needed for defining authorization scopes
     AuthCodePkceSpotify, // Use the PKCE client
+    ClientError,
     Credentials,
+    http::HttpError,
     OAuth, // Needed for defining scopes and cache path
-    model::{PlayableItem},
+    Token,
+    model::{AlbumId, Context, CurrentlyPlayingType, PlayableItem, TrackId, Type},
     // Removed unused Token import
     Config, // Re-add Config
 };
+use serde::{Deserialize, Serialize};
 use std::sync::Mutex;
 use std::path::PathBuf; // Re-add PathBuf
+use std::time::{Duration, Instant};
+
+use crate::secrets;
 // Removed tokio::runtime::Handle import
 
+const EXAMPLE_REDIRECT_URI: &str = "http://127.0.0.1:8888/callback";
+const TOKEN_CACHE_PATH: &str = ".spotify_token_cache.json"; // Plain-text token cache, used unless the keyring holds the token
+
 // Structure to hold Spotify info (remains the same)
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct SpotifyInfo {
     pub artists: Vec<String>,
     pub title: String,
     pub progress_ms: Option<u32>,
     pub duration_ms: Option<u32>,
     pub is_playing: bool,
+    pub album_art_url: Option<String>, // Largest cover image (Spotify lists them largest first)
+    pub volume_percent: Option<u32>, // Playing device's volume (PlaybackState endpoint only)
+}
+
+/// Which Web API endpoint is polled for now-playing info.
+/// - `PlaybackState`: `/me/player` (scope `user-read-playback-state`). Also returns device and
+///   volume info, which later features can use.
+/// - `CurrentlyPlaying`: `/me/player/currently-playing` (scope `user-read-currently-playing`).
+///   Minimal scope for privacy-minded users, but no device/volume info.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlaybackEndpoint {
+    #[default]
+    PlaybackState,
+    CurrentlyPlaying,
+}
+
+/// A Spotify Connect device the user can choose to follow.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceInfo {
+    pub id: String,
+    pub name: String,
+    pub is_active: bool,
+}
+
+/// Why a playback-control call (e.g. setting the volume) failed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ControlError {
+    MissingScope, // The token lacks user-modify-playback-state; re-authorizing fixes it
+    Failed(String),
 }
 
+const MODIFY_PLAYBACK_SCOPE: &str = "user-modify-playback-state";
+const MISSING_ITEM_GRACE: Duration = Duration::from_secs(10); // Device handoffs briefly report playback without a track
+const AUTHORIZE_TIMEOUT: Duration = Duration::from_secs(300); // How long re-authorizing waits for the browser
+
 // Removed static TOKIO_RUNTIME definition
 
 // Store the PKCE client
 static SPOTIFY_CLIENT: Mutex<Option<AuthCodePkceSpotify>> = Mutex::new(None);
+// Endpoint chosen at init; must match the scope that was authorized
+static PLAYBACK_ENDPOINT: Mutex<PlaybackEndpoint> = Mutex::new(PlaybackEndpoint::PlaybackState);
+// Keep credentials and the token in the OS keyring instead of .env / the token cache file
+static USE_KEYRING: Mutex<bool> = Mutex::new(false);
+// Token JSON last written to the keyring, to only write again when rspotify refreshed it
+static SAVED_TOKEN: Mutex<Option<String>> = Mutex::new(None);
+// Last track reported and when, to bridge "playing, but no item" gaps
+static LAST_TRACK: Mutex<Option<(SpotifyInfo, Instant)>> = Mutex::new(None);
+// Set while Spotify plays an ad (free tier)
+static AD_PLAYING: Mutex<bool> = Mutex::new(false);
+// URI of the album being played through (playback context is an album), for prefetching its next tracks
+static PLAYING_ALBUM: Mutex<Option<String>> = Mutex::new(None);
+const ALBUM_TRACKS_PAGE: u32 = 50; // The API's maximum page size
+const MAX_ALBUM_TRACKS: usize = 200; // Box sets beyond this aren't read further
+
+// Spotify app settings as stored in the keyring (the same values the .env file provides)
+#[derive(Serialize, Deserialize)]
+struct StoredCredentials {
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_uri: String,
+}
 
 // Initialize the Spotify client using PKCE flow (now async)
-pub async fn init_client() -> Result<(), String> {
-    let mut client_guard = SPOTIFY_CLIENT.lock().unwrap();
-    if client_guard.is_some() {
+// `queue_access` additionally requests the scopes needed to read the playback queue ("Up next"),
+// `volume_control` the scope needed to change the device volume.
+// With `use_keyring`, credentials missing from .env and the token come from the OS keyring.
+pub async fn init_client(endpoint: PlaybackEndpoint, queue_access: bool, volume_control: bool, use_keyring: bool) -> Result<(), String> {
+    if SPOTIFY_CLIENT.lock().unwrap().is_some() {
         println!("Spotify client already initialized.");
         return Ok(());
     }
 
     println!("Initializing Spotify client (PKCE)...");
+    let (mut spotify, authenticated) = new_client(endpoint, queue_access, volume_control, use_keyring).await?;
+    if !authenticated {
+        // --- Simplest Token Handling ---
+        // Rely entirely on prompt_for_token to check cache, prompt if needed, and manage internal state/cache.
 
-    // Load credentials from .env file
-    let creds = Credentials::from_env().ok_or_else(|| {
-        "Failed to load RSPOTIFY_CLIENT_ID and RSPOTIFY_CLIENT_SECRET from .env".to_string()
-    })?;
+        // Generate the authorization URL (needed for prompt_for_token)
+        let auth_url = spotify.get_authorize_url(None)
+            .map_err(|e| format!("Failed to get authorize URL: {}", e))?;
 
-    // Define required scopes
-    let scopes = scopes!("user-read-playback-state");
+        // Call prompt_for_token.
+        spotify.prompt_for_token(&auth_url).await.map_err(|e| format!("Failed to authenticate Spotify client (PKCE): {}", e))?;
+        println!("Spotify client authentication check/prompt successful.");
+        if use_keyring {
+            save_token_to_keyring(&spotify).await;
+        }
+    }
+    // Store the client instance. Assume prompt_for_token handled caching and internal state.
+    install_client(spotify, endpoint, use_keyring);
+    Ok(())
+}
+
+// Makes `spotify` the client every call uses, along with the endpoint its scopes were requested for
+fn install_client(spotify: AuthCodePkceSpotify, endpoint: PlaybackEndpoint, use_keyring: bool) {
+    *PLAYBACK_ENDPOINT.lock().unwrap() = endpoint;
+    *USE_KEYRING.lock().unwrap() = use_keyring;
+    *SPOTIFY_CLIENT.lock().unwrap() = Some(spotify);
+}
 
-    // Configure OAuth settings (scopes, redirect URI, cache path)
-    let oauth = OAuth::from_env(scopes).ok_or_else(|| {
-        "Failed to load RSPOTIFY_REDIRECT_URI from .env".to_string()
+// Builds a client for the requested scopes without touching the current one.
+// Returns it with `true` if a keyring token already authenticates it, otherwise it still needs authorizing.
+async fn new_client(endpoint: PlaybackEndpoint, queue_access: bool, volume_control: bool, use_keyring: bool) -> Result<(AuthCodePkceSpotify, bool), String> {
+
+    // Define required scopes (only what the chosen endpoint needs)
+    let mut scopes = match endpoint {
+        PlaybackEndpoint::PlaybackState => scopes!("user-read-playback-state"),
+        PlaybackEndpoint::CurrentlyPlaying => scopes!("user-read-currently-playing"),
+    };
+    if queue_access {
+        // The queue endpoint requires both read scopes
+        scopes.extend(scopes!("user-read-playback-state", "user-read-currently-playing"));
+    }
+    if volume_control {
+        scopes.extend(scopes!(MODIFY_PLAYBACK_SCOPE));
+    }
+
+    // Load credentials and OAuth settings (scopes, redirect URI) from .env, or the keyring if enabled
+    let (creds, mut oauth) = match (Credentials::from_env(), OAuth::from_env(scopes.clone())) {
+        (Some(creds), Some(oauth)) => {
+            if use_keyring {
+                // Copy them over so the secrets can be removed from .env afterwards
+                let stored = StoredCredentials { client_id: creds.id.clone(), client_secret: creds.secret.clone(), redirect_uri: oauth.redirect_uri.clone() };
+                if let Err(e) = serde_json::to_string(&stored).map_err(|e| e.to_string()).and_then(|json| secrets::store_secret(secrets::CREDENTIALS_ENTRY, &json)) {
+                    eprintln!("{}", e);
+                }
+            }
+            (creds, oauth)
+        }
+        _ => {
+            let stored = use_keyring
+                .then(|| secrets::load_secret(secrets::CREDENTIALS_ENTRY))
+                .flatten()
+                .and_then(|json| serde_json::from_str::<StoredCredentials>(&json).ok())
+                .ok_or_else(|| "Failed to load RSPOTIFY_CLIENT_ID, RSPOTIFY_CLIENT_SECRET and RSPOTIFY_REDIRECT_URI from .env (or the keyring)".to_string())?;
+            let creds = Credentials { id: stored.client_id, secret: stored.client_secret };
+            let oauth = OAuth { redirect_uri: stored.redirect_uri, scopes: scopes.clone(), ..Default::default() };
+            (creds, oauth)
+        }
+    };
+    // A malformed redirect URI otherwise only shows up as an opaque error from Spotify's authorize page
+    oauth.redirect_uri = normalize_redirect_uri(&oauth.redirect_uri).map_err(|e| {
+        format!(
+            "Invalid RSPOTIFY_REDIRECT_URI {:?}: {}. It must exactly match a Redirect URI in your app's settings on the Spotify Developer Dashboard, e.g. {}",
+            oauth.redirect_uri, e, EXAMPLE_REDIRECT_URI
+        )
     })?;
-    // Configure the client config, including the cache path
+    println!("Using redirect URI {} (must match the Spotify dashboard exactly)", oauth.redirect_uri);
+    // A keyring token that covers the requested scopes saves the browser round-trip
+    let keyring_token = use_keyring
+        .then(|| secrets::load_secret(secrets::TOKEN_ENTRY))
+        .flatten()
+        .and_then(|json| serde_json::from_str::<Token>(&json).ok())
+        .filter(|token| scopes.is_subset(&token.scopes));
+    // Configure the client config, including the cache path. The token file stays in use until the keyring
+    // holds a token, so it still works without keyring support or when storing the token there fails.
     let config = Config {
-        token_cached: true, // Enable caching
-        cache_path: PathBuf::from(".spotify_token_cache.json"), // Explicit path
+        token_cached: keyring_token.is_none(),
+        cache_path: PathBuf::from(TOKEN_CACHE_PATH), // Explicit path
         ..Default::default()
     };
 
     // Create the PKCE client with the config
-    let mut spotify = AuthCodePkceSpotify::with_config(creds, oauth, config); // Use with_config
-
-    // Generate the authorization URL (only needed if prompting)
-    // let _auth_url = spotify.get_authorize_url(None) // Prefix with _ if unused now
-    //     .map_err(|e| format!("Failed to get authorize URL: {}", e))?;
-
-    // --- Simplest Token Handling ---
-    // Rely entirely on prompt_for_token to check cache, prompt if needed, and manage internal state/cache.
-
-    // Generate the authorization URL (needed for prompt_for_token)
-    let auth_url = spotify.get_authorize_url(None)
-        .map_err(|e| format!("Failed to get authorize URL: {}", e))?;
-
-    // Call prompt_for_token.
-    match spotify.prompt_for_token(&auth_url).await {
-        Ok(_) => {
-            println!("Spotify client authentication check/prompt successful.");
-            // Store the client instance. Assume prompt_for_token handled caching and internal state.
-            *client_guard = Some(spotify);
-            Ok(())
+    let spotify = AuthCodePkceSpotify::with_config(creds, oauth, config); // Use with_config
+
+    if let Some(token) = keyring_token {
+        let expired = token.is_expired();
+        if let Ok(mut current) = spotify.get_token().lock().await {
+            *current = Some(token);
+        }
+        if !expired || spotify.refresh_token().await.is_ok() {
+            println!("Spotify client authenticated from the keyring.");
+            save_token_to_keyring(&spotify).await;
+            return Ok((spotify, true));
+        }
+        println!("Keyring token could not be refreshed, authorizing again.");
+    }
+    Ok((spotify, false))
+}
+
+/// Clamps a reported position to `[0, duration]`. At track boundaries Spotify sometimes reports a
+/// progress slightly past the duration; that's treated as the end of the track.
+pub fn clamp_progress(progress_ms: i64, duration_ms: Option<u32>) -> u32 {
+    let progress = u32::try_from(progress_ms.max(0)).unwrap_or(u32::MAX);
+    duration_ms.map_or(progress, |duration| progress.min(duration))
+}
+
+// Maps the playing item to SpotifyInfo (episodes and empty items count as nothing playing)
+fn info_from_item(item: Option<PlayableItem>, progress: Option<chrono::TimeDelta>, is_playing: bool) -> Option<SpotifyInfo> {
+    if let Some(PlayableItem::Track(track)) = item {
+        // track object in v0.13 likely has duration directly
+        let artists = track.artists.iter().map(|a| a.name.clone()).collect();
+        let duration_ms = track.duration.num_milliseconds().try_into().ok();
+
+        Some(SpotifyInfo {
+            artists,
+            title: track.name,
+            // Convert progress from Option<TimeDelta> to Option<u32> milliseconds, within the track
+            progress_ms: progress.map(|p| clamp_progress(p.num_milliseconds(), duration_ms)),
+            duration_ms,
+            is_playing,
+            album_art_url: track.album.images.first().map(|image| image.url.clone()),
+            volume_percent: None, // Filled in by the caller when the endpoint reports the device
+        })
+    } else {
+        None // Not a track
+    }
+}
+
+// Free-tier ads come as "ad" items, or as tracks without an ID credited to "Spotify"/"Advertisement"
+fn is_likely_ad(item: Option<&PlayableItem>, playing_type: CurrentlyPlayingType) -> bool {
+    if playing_type == CurrentlyPlayingType::Advertisement {
+        return true;
+    }
+    match item {
+        Some(PlayableItem::Track(track)) => {
+            let artists: Vec<&str> = track.artists.iter().map(|artist| artist.name.as_str()).collect();
+            is_ad_track(&track.name, &artists, track.id.is_some() || track.is_local)
+        }
+        _ => false,
+    }
+}
+
+// Local files have no ID either, so `has_id` should be true for them
+fn is_ad_track(title: &str, artists: &[&str], has_id: bool) -> bool {
+    let is_ad_name = |name: &str| matches!(name.trim().to_lowercase().as_str(), "" | "advertisement" | "spotify" | "ad");
+    !has_id || title.trim().eq_ignore_ascii_case("advertisement") || (is_ad_name(title) && artists.iter().all(|artist| is_ad_name(artist)))
+}
+
+/// Whether the last poll found an ad playing (reported as nothing playing, so no lyrics are fetched).
+pub fn ad_playing() -> bool {
+    *AD_PLAYING.lock().unwrap()
+}
+
+fn album_context(context: Option<&Context>) -> Option<String> {
+    context.filter(|context| context._type == Type::Album).map(|context| context.uri.clone())
+}
+
+/// The album URI when the last poll found playback running through an album (not a playlist, shuffle of liked songs, ...).
+pub fn playing_album() -> Option<String> {
+    PLAYING_ALBUM.lock().unwrap().clone()
+}
+
+/// (artists, title) of an album's tracks in order, read a page at a time up to MAX_ALBUM_TRACKS.
+pub async fn album_tracks(album_uri: &str) -> Result<Vec<(Vec<String>, String)>, String> {
+    let album_id = AlbumId::from_uri(album_uri).map_err(|e| format!("Invalid album URI '{}': {}", album_uri, e))?;
+    // A clone shares the token, and keeps the lock from being held across awaits in a spawned task
+    let spotify = SPOTIFY_CLIENT.lock().unwrap().clone().ok_or("Spotify client not initialized")?;
+
+    let mut tracks = Vec::new();
+    loop {
+        let page = spotify
+            .album_track_manual(album_id.clone(), None, Some(ALBUM_TRACKS_PAGE), Some(tracks.len() as u32))
+            .await
+            .map_err(|e| format!("Failed to get album tracks: {}", e))?;
+        let page_len = page.items.len();
+        tracks.extend(page.items.into_iter().map(|track| (track.artists.into_iter().map(|artist| artist.name).collect(), track.name)));
+        if page.next.is_none() || page_len == 0 || tracks.len() >= MAX_ALBUM_TRACKS {
+            break;
+        }
+    }
+    tracks.truncate(MAX_ALBUM_TRACKS);
+    Ok(tracks)
+}
+
+/// Up to `limit` tracks following the given one in `tracks`, or none if it isn't in the list.
+pub fn tracks_after(tracks: &[(Vec<String>, String)], artists: &[String], title: &str, limit: usize) -> Vec<(Vec<String>, String)> {
+    tracks
+        .iter()
+        .position(|(track_artists, track_title)| track_title == title && track_artists == artists)
+        .map_or_else(Vec::new, |current| tracks.iter().skip(current + 1).take(limit).cloned().collect())
+}
+
+// Spotify can report `is_playing` without an item for a moment during a device handoff. Within
+// MISSING_ITEM_GRACE of the last real track that track is reported again (its progress moved on),
+// instead of "nothing playing", so the overlay doesn't flicker. Anything else passes through.
+fn bridge_missing_item(info: Option<SpotifyInfo>, item_missing: bool, is_playing: bool, last: &mut Option<(SpotifyInfo, Instant)>, now: Instant) -> Option<SpotifyInfo> {
+    if let Some(info) = &info {
+        *last = Some((info.clone(), now));
+        return Some(info.clone());
+    }
+    if item_missing
+        && is_playing
+        && let Some((previous, seen_at)) = last.as_ref()
+        && now.duration_since(*seen_at) < MISSING_ITEM_GRACE
+    {
+        let elapsed_ms = u32::try_from(now.duration_since(*seen_at).as_millis()).unwrap_or(u32::MAX);
+        let progress_ms = previous.progress_ms.map(|progress| {
+            let advanced = if previous.is_playing { progress.saturating_add(elapsed_ms) } else { progress };
+            previous.duration_ms.map_or(advanced, |duration| advanced.min(duration))
+        });
+        println!("Spotify reported playback without a track, keeping {} for now", previous.title);
+        return Some(SpotifyInfo { progress_ms, ..previous.clone() });
+    }
+    *last = None;
+    None
+}
+
+/// Cleans up a redirect URI from .env: surrounding whitespace and quotes, and scheme/host case. The path is
+/// kept exactly as written, since Spotify compares it literally with the dashboard. Rejects what Spotify
+/// would refuse: anything but http(s), a missing host, `localhost`, and plain http for non-loopback hosts.
+pub fn normalize_redirect_uri(raw: &str) -> Result<String, String> {
+    let uri = raw.trim().trim_matches(|c| c == '"' || c == '\'');
+    let Some((scheme, rest)) = uri.split_once("://") else {
+        return Err("it has no scheme (it should start with http:// or https://)".to_string());
+    };
+    let scheme = scheme.to_ascii_lowercase();
+    if scheme != "http" && scheme != "https" {
+        return Err(format!("the scheme must be http or https, not {}", scheme));
+    }
+    let (authority, path) = rest.find(['/', '?', '#']).map_or((rest, ""), |at| rest.split_at(at));
+    let authority = authority.to_ascii_lowercase();
+    let host = authority.rsplit_once(':').filter(|(_, port)| port.chars().all(|c| c.is_ascii_digit())).map_or(authority.as_str(), |(host, _)| host);
+    if host.is_empty() {
+        return Err("it has no host".to_string());
+    }
+    if host == "localhost" {
+        return Err("Spotify no longer accepts localhost; use the loopback address http://127.0.0.1 (same port and path) here and in the dashboard".to_string());
+    }
+    if scheme == "http" && !matches!(host, "127.0.0.1" | "[::1]") {
+        return Err("Spotify only accepts http:// for loopback addresses; use https:// or 127.0.0.1".to_string());
+    }
+    Ok(format!("{}://{}{}", scheme, authority, path))
+}
+
+// Extracts the track ID from a `spotify:track:<id>` URI, an open.spotify.com/track/<id> link, or a bare ID
+pub fn parse_track_id(input: &str) -> Result<&str, String> {
+    let input = input.trim();
+    let id = if let Some(id) = input.strip_prefix("spotify:track:") {
+        id
+    } else if let Some(rest) = input.split("open.spotify.com/").nth(1) {
+        // Links may carry a locale segment ("intl-de/track/...") and a share query ("?si=...")
+        let path = rest.split(['?', '#']).next().unwrap_or("");
+        let mut segments = path.split('/');
+        segments
+            .find(|segment| *segment == "track")
+            .and_then(|_| segments.next())
+            .ok_or_else(|| format!("'{}' is not a Spotify track link", input))?
+    } else {
+        input
+    };
+    // Spotify IDs are 22 base-62 characters
+    if id.len() == 22 && id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Ok(id)
+    } else {
+        Err(format!("'{}' is not a Spotify track URI or link", input))
+    }
+}
+
+// Resolves a pasted track URI/link to its metadata, independent of what's playing
+pub async fn track_info_from_uri(uri: &str) -> Result<SpotifyInfo, String> {
+    let id = parse_track_id(uri)?;
+    let track_id = TrackId::from_id(id).map_err(|e| format!("Invalid track ID '{}': {}", id, e))?;
+
+    let client_guard = SPOTIFY_CLIENT.lock().unwrap();
+    let spotify = client_guard.as_ref().ok_or("Spotify client not initialized")?;
+    let track = spotify
+        .track(track_id, None)
+        .await
+        .map_err(|e| format!("Track {} not found or unavailable: {}", id, e))?;
+    info_from_item(Some(PlayableItem::Track(track)), None, false).ok_or_else(|| format!("Track {} has no usable metadata", id))
+}
+
+// Lists the user's available Spotify Connect devices (covered by user-read-playback-state)
+pub async fn list_devices() -> Result<Vec<DeviceInfo>, String> {
+    let client_guard = SPOTIFY_CLIENT.lock().unwrap();
+    let spotify = client_guard.as_ref().ok_or("Spotify client not initialized")?;
+
+    let devices = spotify.device().await.map_err(|e| format!("Failed to list devices: {}", e))?;
+    Ok(devices
+        .into_iter()
+        .filter_map(|device| {
+            // Restricted devices without an id can't be targeted
+            device.id.map(|id| DeviceInfo { id, name: device.name, is_active: device.is_active })
+        })
+        .collect())
+}
+
+// Refreshes the access token, e.g. when the machine wakes from sleep after it expired
+pub async fn refresh_access_token() -> Result<(), String> {
+    let client_guard = SPOTIFY_CLIENT.lock().unwrap();
+    let spotify = client_guard.as_ref().ok_or("Spotify client not initialized")?;
+
+    spotify.refresh_token().await.map_err(|e| format!("Failed to refresh Spotify token: {}", e))?;
+    if *USE_KEYRING.lock().unwrap() {
+        save_token_to_keyring(spotify).await;
+    }
+    Ok(())
+}
+
+// Runs the auth flow again, e.g. to grant scopes added since startup. The current client keeps working
+// until the new one is authorized, and stays if authorizing fails. There may be no console to paste the
+// redirect URL into, so `show_url` gets the authorization page and the redirect is caught on the loopback
+// redirect URI itself.
+pub async fn reauthorize(endpoint: PlaybackEndpoint, queue_access: bool, volume_control: bool, show_url: impl FnOnce(String)) -> Result<(), String> {
+    let use_keyring = *USE_KEYRING.lock().unwrap();
+    let (mut spotify, authenticated) = new_client(endpoint, queue_access, volume_control, use_keyring).await?;
+    if !authenticated {
+        let (address, path) = loopback_redirect(&spotify.oauth.redirect_uri)?;
+        // Bound before the page opens, so a quick redirect can't arrive first
+        let listener = tokio::net::TcpListener::bind(&address)
+            .await
+            .map_err(|e| format!("Could not listen on {} for the Spotify redirect: {}", address, e))?;
+        let auth_url = spotify.get_authorize_url(None).map_err(|e| format!("Failed to get authorize URL: {}", e))?;
+        show_url(auth_url);
+        let target = tokio::time::timeout(AUTHORIZE_TIMEOUT, wait_for_redirect(&listener, &path))
+            .await
+            .map_err(|_| "Timed out waiting for Spotify authorization".to_string())??;
+        let redirect_url = format!("http://{}{}", address, target);
+        let code = spotify.parse_response_code(&redirect_url).ok_or("Spotify authorization was denied or the redirect was invalid")?;
+        spotify.request_token(&code).await.map_err(|e| format!("Failed to authenticate Spotify client (PKCE): {}", e))?;
+        if use_keyring {
+            save_token_to_keyring(&spotify).await;
         }
-        Err(e) => Err(format!("Failed to authenticate Spotify client (PKCE): {}", e)),
     }
+    install_client(spotify, endpoint, use_keyring);
+    Ok(())
+}
+
+// Splits a loopback redirect URI (as normalized by normalize_redirect_uri) into the address to listen
+// on and the path Spotify redirects to. Errors for https or non-loopback URIs, which the app can't serve.
+fn loopback_redirect(redirect_uri: &str) -> Result<(String, String), String> {
+    let rest = redirect_uri
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("Re-authorizing from the app needs a loopback redirect URI like {}", EXAMPLE_REDIRECT_URI))?;
+    let (authority, path) = rest.find(['/', '?', '#']).map_or((rest, ""), |at| rest.split_at(at));
+    let address = if authority.ends_with(']') || !authority.contains(':') { format!("{}:80", authority) } else { authority.to_string() };
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    Ok((address, if path.is_empty() { "/".to_string() } else { path.to_string() }))
+}
+
+// Path and query of the first request for the redirect path; other requests (e.g. favicon.ico) get a 404
+async fn wait_for_redirect(listener: &tokio::net::TcpListener, path: &str) -> Result<String, String> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    loop {
+        let (mut stream, _) = listener.accept().await.map_err(|e| format!("Failed to accept the Spotify redirect: {}", e))?;
+        let mut request = vec![0; 8192];
+        let read = stream.read(&mut request).await.unwrap_or(0);
+        let target = redirect_target(&String::from_utf8_lossy(&request[..read]), path);
+        let response = match target {
+            Some(_) => "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nConnection: close\r\n\r\n<p>Done. You can close this tab and return to LyricRs.</p>",
+            None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        };
+        let _ = stream.write_all(response.as_bytes()).await;
+        if let Some(target) = target {
+            return Ok(target);
+        }
+    }
+}
+
+// Request target of a GET for `path`, e.g. "/callback?code=...&state=..."
+fn redirect_target(request: &str, path: &str) -> Option<String> {
+    let target = request.lines().next()?.strip_prefix("GET ")?.split(' ').next()?;
+    (target.split('?').next() == Some(path)).then(|| target.to_string())
+}
+
+// Recovers the client after a panic while it was locked (e.g. inside an rspotify call)
+pub fn clear_poison() {
+    SPOTIFY_CLIENT.clear_poison();
+}
+
+// Writes the client's token to the keyring if it changed since the last write.
+// rspotify refreshes tokens on its own and Spotify rotates PKCE refresh tokens, so this runs after every poll.
+async fn save_token_to_keyring(spotify: &AuthCodePkceSpotify) {
+    let json = match spotify.get_token().lock().await {
+        Ok(token) => token.as_ref().and_then(|token| serde_json::to_string(token).ok()),
+        Err(_) => None,
+    };
+    let Some(json) = json else { return };
+    let mut saved = SAVED_TOKEN.lock().unwrap();
+    if saved.as_ref() == Some(&json) {
+        return;
+    }
+    match secrets::store_secret(secrets::TOKEN_ENTRY, &json) {
+        // The keyring holds it now, so no plain-text copy is left behind
+        Ok(()) => {
+            let _ = std::fs::remove_file(TOKEN_CACHE_PATH);
+        }
+        // Fall back to the token file, so the next start doesn't need the browser again
+        Err(e) => {
+            eprintln!("{}; caching the token in {} instead", e, TOKEN_CACHE_PATH);
+            if let Err(e) = std::fs::write(TOKEN_CACHE_PATH, &json) {
+                eprintln!("Failed to write {}: {}", TOKEN_CACHE_PATH, e);
+            }
+        }
+    }
+    *saved = Some(json);
+}
+
+// Spotify answers 403 both for missing scopes and e.g. for non-Premium accounts,
+// so a 403 only counts as a missing scope if the granted token really lacks it
+async fn control_error(spotify: &AuthCodePkceSpotify, action: &str, error: ClientError) -> ControlError {
+    let forbidden = matches!(&error, ClientError::Http(http) if matches!(http.as_ref(), HttpError::StatusCode(response) if response.status().as_u16() == 403));
+    if forbidden {
+        let lacks_scope = match spotify.get_token().lock().await {
+            Ok(token) => token.as_ref().is_none_or(|token| !token.scopes.contains(MODIFY_PLAYBACK_SCOPE)),
+            Err(_) => false,
+        };
+        if lacks_scope {
+            return ControlError::MissingScope;
+        }
+    }
+    ControlError::Failed(format!("Failed to {}: {}", action, error))
+}
+
+// Sets the volume of the given device (or the active one); needs user-modify-playback-state
+pub async fn set_volume(volume_percent: u8, device_id: Option<&str>) -> Result<(), ControlError> {
+    let client_guard = SPOTIFY_CLIENT.lock().unwrap();
+    let spotify = client_guard.as_ref().ok_or(ControlError::Failed("Spotify client not initialized".to_string()))?;
+
+    match spotify.volume(volume_percent, device_id).await {
+        Ok(()) => Ok(()),
+        Err(e) => Err(control_error(spotify, "set volume", e).await),
+    }
+}
+
+// Returns "artists - title" of the next item in the user's queue (artists joined with `artist_separator`),
+// or None if the queue is empty. Fails (e.g. 401/403) when the queue scopes weren't granted.
+pub async fn get_next_in_queue(artist_separator: &str) -> Result<Option<String>, String> {
+    let client_guard = SPOTIFY_CLIENT.lock().unwrap();
+    let spotify = client_guard.as_ref().ok_or("Spotify client not initialized")?;
+
+    let queue = spotify.current_user_queue().await.map_err(|e| format!("Failed to get queue: {}", e))?;
+    Ok(queue.queue.into_iter().next().map(|item| match item {
+        PlayableItem::Track(track) => {
+            let artists: Vec<String> = track.artists.into_iter().map(|a| a.name).collect();
+            format!("{} - {}", artists.join(artist_separator), track.name)
+        }
+        PlayableItem::Episode(episode) => episode.name,
+    }))
 }
 
 // Fetches current playback info using the authenticated PKCE client (now async)
-pub async fn get_current_info() -> Result<Option<SpotifyInfo>, String> {
+// With `preferred_device`, playback on other devices is ignored unless that device is offline.
+// The currently-playing endpoint has no device info, so the preference only applies to PlaybackState.
+pub async fn get_current_info(preferred_device: Option<&str>) -> Result<Option<SpotifyInfo>, String> {
+    let endpoint = *PLAYBACK_ENDPOINT.lock().unwrap();
     let client_guard = SPOTIFY_CLIENT.lock().unwrap();
     let spotify = client_guard.as_ref().ok_or("Spotify client not initialized")?;
 
     // Fetch current playback state - await the async call directly
-    match spotify.current_playback(None, None::<&[_]>).await {
-        Ok(Some(context)) => {
-            if let Some(PlayableItem::Track(track)) = context.item {
-                 // track object in v0.13 likely has duration directly
-                let artists = track.artists.iter().map(|a| a.name.clone()).collect();
-                let duration_ms = track.duration.num_milliseconds().try_into().ok();
-
-                Ok(Some(SpotifyInfo {
-                    artists,
-                    title: track.name,
-                    // Convert progress from Option<TimeDelta> to Option<u32> milliseconds
-                    progress_ms: context.progress.and_then(|p| p.num_milliseconds().try_into().ok()),
-                    duration_ms,
-                    is_playing: context.is_playing,
-                }))
-            } else {
-                Ok(None) // Not a track
+    let result = match endpoint {
+        PlaybackEndpoint::PlaybackState => match spotify.current_playback(None, None::<&[_]>).await {
+            Ok(Some(context)) => {
+                if let Some(preferred) = preferred_device
+                    && context.device.id.as_deref() != Some(preferred)
+                {
+                    // Playing elsewhere: stay on the chosen device while it's online, else follow the active one
+                    let devices = spotify.device().await.map_err(|e| format!("Failed to list devices: {}", e))?;
+                    if devices.iter().any(|device| device.id.as_deref() == Some(preferred)) {
+                        return Ok(None);
+                    }
+                }
+                let is_ad = is_likely_ad(context.item.as_ref(), context.currently_playing_type);
+                *AD_PLAYING.lock().unwrap() = is_ad;
+                *PLAYING_ALBUM.lock().unwrap() = album_context(context.context.as_ref());
+                let item_missing = context.item.is_none() && !is_ad; // An ad isn't a handoff gap
+                let info = info_from_item(context.item, context.progress, context.is_playing)
+                    .filter(|_| !is_ad)
+                    .map(|info| SpotifyInfo { volume_percent: context.device.volume_percent, ..info });
+                Ok(bridge_missing_item(info, item_missing, context.is_playing, &mut LAST_TRACK.lock().unwrap(), Instant::now()))
+            }
+            Ok(None) => {
+                *LAST_TRACK.lock().unwrap() = None;
+                *AD_PLAYING.lock().unwrap() = false;
+                *PLAYING_ALBUM.lock().unwrap() = None;
+                Ok(None) // Nothing playing
             }
+            // Simplify error handling - catch specific auth errors if needed later
+            // Err(ClientError::InvalidToken) => { ... } // Example if needed
+            Err(e) => Err(format!("Failed to get playback state: {}", e)), // Catch other errors
+        },
+        PlaybackEndpoint::CurrentlyPlaying => match spotify.current_playing(None, None::<&[_]>).await {
+            Ok(Some(context)) => {
+                let is_ad = is_likely_ad(context.item.as_ref(), context.currently_playing_type);
+                *AD_PLAYING.lock().unwrap() = is_ad;
+                *PLAYING_ALBUM.lock().unwrap() = album_context(context.context.as_ref());
+                let item_missing = context.item.is_none() && !is_ad;
+                let info = info_from_item(context.item, context.progress, context.is_playing).filter(|_| !is_ad);
+                Ok(bridge_missing_item(info, item_missing, context.is_playing, &mut LAST_TRACK.lock().unwrap(), Instant::now()))
+            }
+            Ok(None) => {
+                *LAST_TRACK.lock().unwrap() = None;
+                *AD_PLAYING.lock().unwrap() = false;
+                *PLAYING_ALBUM.lock().unwrap() = None;
+                Ok(None) // Nothing playing
+            }
+            Err(e) => Err(format!("Failed to get currently playing track: {}", e)),
+        },
+    };
+    if *USE_KEYRING.lock().unwrap() {
+        save_token_to_keyring(spotify).await; // The request may have refreshed the token
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_track_uris_and_links() {
+        let id = "4uLU6hMCjMI75M1A2tKUQC";
+        assert_eq!(parse_track_id("spotify:track:4uLU6hMCjMI75M1A2tKUQC"), Ok(id));
+        assert_eq!(parse_track_id("https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC?si=abc123"), Ok(id));
+        assert_eq!(parse_track_id("https://open.spotify.com/intl-de/track/4uLU6hMCjMI75M1A2tKUQC"), Ok(id));
+        assert_eq!(parse_track_id(" 4uLU6hMCjMI75M1A2tKUQC "), Ok(id));
+    }
+
+    #[test]
+    fn normalizes_redirect_uris() {
+        assert_eq!(normalize_redirect_uri(" \"http://127.0.0.1:8888/callback\" ").as_deref(), Ok("http://127.0.0.1:8888/callback"));
+        assert_eq!(normalize_redirect_uri("HTTP://127.0.0.1:8888/callback/").as_deref(), Ok("http://127.0.0.1:8888/callback/")); // Path kept as is
+        assert_eq!(normalize_redirect_uri("https://example.com/").as_deref(), Ok("https://example.com/"));
+        assert_eq!(normalize_redirect_uri("http://[::1]:8888/cb").as_deref(), Ok("http://[::1]:8888/cb"));
+    }
+
+    #[test]
+    fn rejects_malformed_redirect_uris() {
+        assert!(normalize_redirect_uri("127.0.0.1:8888/callback").is_err()); // No scheme
+        assert!(normalize_redirect_uri("ftp://127.0.0.1/callback").is_err());
+        assert!(normalize_redirect_uri("http:///callback").is_err()); // No host
+        assert!(normalize_redirect_uri("http://example.com/callback").is_err()); // http only for loopback
+        assert!(normalize_redirect_uri("http://LocalHost:8888/callback").unwrap_err().contains("http://127.0.0.1"));
+        assert!(normalize_redirect_uri("").is_err());
+    }
+
+    #[test]
+    fn finds_the_loopback_redirect() {
+        assert_eq!(loopback_redirect("http://127.0.0.1:8888/callback"), Ok(("127.0.0.1:8888".to_string(), "/callback".to_string())));
+        assert_eq!(loopback_redirect("http://[::1]"), Ok(("[::1]:80".to_string(), "/".to_string())));
+        assert!(loopback_redirect("https://example.com/callback").is_err());
+        let request = "GET /callback?code=abc&state=xyz HTTP/1.1\r\nHost: 127.0.0.1:8888\r\n\r\n";
+        assert_eq!(redirect_target(request, "/callback").as_deref(), Some("/callback?code=abc&state=xyz"));
+        assert_eq!(redirect_target("GET /favicon.ico HTTP/1.1\r\n", "/callback"), None);
+    }
+
+    #[test]
+    fn progress_is_clamped_to_the_track() {
+        assert_eq!(clamp_progress(200_450, Some(200_000)), 200_000); // Overshoot at the boundary = end of track
+        assert_eq!(clamp_progress(-30, Some(200_000)), 0);
+        assert_eq!(clamp_progress(42_000, Some(200_000)), 42_000);
+        assert_eq!(clamp_progress(42_000, None), 42_000);
+    }
+
+    #[test]
+    fn detects_likely_ads() {
+        assert!(is_likely_ad(None, CurrentlyPlayingType::Advertisement));
+        assert!(!is_likely_ad(None, CurrentlyPlayingType::Track)); // Handoff gap, not an ad
+        assert!(!is_ad_track("Bohemian Rhapsody", &["Queen"], true));
+        assert!(is_ad_track("Advertisement", &["Spotify"], true));
+        assert!(is_ad_track("Spotify", &[""], true));
+        assert!(is_ad_track("Spotify", &[], true));
+        assert!(is_ad_track("Summer Sale", &["Some Brand"], false)); // No track ID
+        // Real songs that merely mention the words
+        assert!(!is_ad_track("Spotify Singles Medley", &["Spotify"], true));
+        assert!(!is_ad_track("Spotify", &["The Ads"], true));
+    }
+
+    fn track(progress_ms: u32) -> SpotifyInfo {
+        SpotifyInfo {
+            artists: vec!["Artist".to_string()],
+            title: "Song".to_string(),
+            progress_ms: Some(progress_ms),
+            duration_ms: Some(200_000),
+            is_playing: true,
+            album_art_url: None,
+            volume_percent: None,
         }
-        Ok(None) => Ok(None), // Nothing playing
-        // Simplify error handling - catch specific auth errors if needed later
-        // Err(ClientError::InvalidToken) => { ... } // Example if needed
-        Err(e) => Err(format!("Failed to get playback state: {}", e)), // Catch other errors
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn keeps_last_track_through_a_handoff_gap() {
+        let start = Instant::now();
+        let mut last = None;
+        assert_eq!(bridge_missing_item(Some(track(1_000)), false, true, &mut last, start), Some(track(1_000)));
+        // Playing without an item shortly after: same track, progress moved on
+        let bridged = bridge_missing_item(None, true, true, &mut last, start + Duration::from_secs(2));
+        assert_eq!(bridged, Some(track(3_000)));
+        // Past the grace period it really is nothing
+        assert_eq!(bridge_missing_item(None, true, true, &mut last, start + MISSING_ITEM_GRACE), None);
+        assert!(last.is_none());
+    }
+
+    #[test]
+    fn paused_or_non_track_items_are_not_bridged() {
+        let start = Instant::now();
+        let mut last = None;
+        bridge_missing_item(Some(track(1_000)), false, true, &mut last, start);
+        assert_eq!(bridge_missing_item(None, true, false, &mut last, start), None); // Stopped, not a handoff
+        bridge_missing_item(Some(track(1_000)), false, true, &mut last, start);
+        assert_eq!(bridge_missing_item(None, false, true, &mut last, start), None); // An episode is playing
+    }
+
+    #[test]
+    fn picks_tracks_after_the_current_one() {
+        let album: Vec<(Vec<String>, String)> =
+            ["One", "Two", "Three", "Four"].iter().map(|title| (vec!["Artist".to_string()], title.to_string())).collect();
+        let artists = vec!["Artist".to_string()];
+        let next: Vec<String> = tracks_after(&album, &artists, "Two", 5).into_iter().map(|(_, title)| title).collect();
+        assert_eq!(next, ["Three", "Four"]);
+        assert_eq!(tracks_after(&album, &artists, "One", 1).len(), 1);
+        assert!(tracks_after(&album, &artists, "Four", 3).is_empty());
+        assert!(tracks_after(&album, &["Other".to_string()], "Two", 3).is_empty()); // Not on this album
+    }
+
+    #[test]
+    fn rejects_non_track_input() {
+        assert!(parse_track_id("https://open.spotify.com/album/4uLU6hMCjMI75M1A2tKUQC").is_err());
+        assert!(parse_track_id("spotify:track:too-short").is_err());
+        assert!(parse_track_id("").is_err());
+    }
+}